@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE servers
+	DROP COLUMN log_channel_id,
+	DROP COLUMN show_emails;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+ALTER TABLE servers
+	ADD COLUMN log_channel_id	bigint,
+	ADD COLUMN show_emails		boolean NOT NULL DEFAULT false;
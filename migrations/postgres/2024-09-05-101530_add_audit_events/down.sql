@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+drop table audit_events;
+drop function audit_events_reject_update;
+drop type audit_method;
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+CREATE TYPE audit_method AS ENUM ('member_join', 'verify_command', 'set_email', 'otp');
+
+CREATE TABLE audit_events (
+	id			bigserial PRIMARY KEY,
+	actor_id	bigint,
+	subject_id	bigint NOT NULL,
+	guild_id	bigint,
+	old_state	user_state,
+	new_state	user_state NOT NULL,
+	method		audit_method NOT NULL,
+	created_at	timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_events_subject_id_idx ON audit_events (subject_id, created_at);
+
+-- The audit trail is append-only.
+CREATE OR REPLACE FUNCTION audit_events_reject_update() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+	FOR EACH ROW EXECUTE PROCEDURE audit_events_reject_update();
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+drop table tombstones;
+
+CREATE OR REPLACE FUNCTION audit_events_reject_update() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+-- Non-reversible records of erased users, kept only when a ban requires one.
+CREATE TABLE tombstones (
+	id				bigserial PRIMARY KEY,
+	discord_id_hash	bytea NOT NULL,
+	email_hash		bytea,
+	created_at		timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX tombstones_email_hash_idx ON tombstones (email_hash);
+
+-- Erasing a user anonymises the events they acted in, so the only update allowed on the audit
+-- trail is clearing the actor.
+CREATE OR REPLACE FUNCTION audit_events_reject_update() RETURNS trigger AS $$
+BEGIN
+	IF NEW.actor_id IS NULL AND
+		(NEW.id, NEW.subject_id, NEW.guild_id, NEW.old_state, NEW.new_state, NEW.method, NEW.created_at)
+		IS NOT DISTINCT FROM
+		(OLD.id, OLD.subject_id, OLD.guild_id, OLD.old_state, OLD.new_state, OLD.method, OLD.created_at)
+	THEN
+		RETURN NEW;
+	END IF;
+
+	RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
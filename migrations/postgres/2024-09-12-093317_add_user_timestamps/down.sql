@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+
+-- Postgres can't drop values from an enum, so recreate it without the new value.
+DELETE FROM audit_events WHERE method = 'retention';
+ALTER TYPE audit_method RENAME TO audit_method_old;
+CREATE TYPE audit_method AS ENUM ('member_join', 'verify_command', 'set_email', 'otp');
+ALTER TABLE audit_events ALTER COLUMN method TYPE audit_method USING method::text::audit_method;
+DROP TYPE audit_method_old;
+
+DROP INDEX users_state_updated_at_idx;
+DROP TRIGGER set_updated_at ON users;
+
+ALTER TABLE users
+	DROP COLUMN created_at,
+	DROP COLUMN updated_at;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+ALTER TABLE users
+	ADD COLUMN created_at	timestamptz NOT NULL DEFAULT now(),
+	ADD COLUMN updated_at	timestamptz NOT NULL DEFAULT now();
+
+SELECT diesel_manage_updated_at('users');
+
+CREATE INDEX users_state_updated_at_idx ON users (state, updated_at);
+
+ALTER TYPE audit_method ADD VALUE 'retention';
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX users_imperial_email_index_idx;
+
+ALTER TABLE users
+	DROP COLUMN imperial_email_ciphertext,
+	DROP COLUMN imperial_email_key_id,
+	DROP COLUMN imperial_email_index;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Emails are encrypted by the application. The plaintext `imperial_email` column is only kept for
+-- rows written before encryption, until `imperial-bot rotate-keys` encrypts them.
+ALTER TABLE users
+	ADD COLUMN imperial_email_ciphertext	bytea,
+	ADD COLUMN imperial_email_key_id		varchar,
+	ADD COLUMN imperial_email_index			bytea;
+
+CREATE INDEX users_imperial_email_index_idx ON users (imperial_email_index);
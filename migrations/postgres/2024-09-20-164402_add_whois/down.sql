@@ -0,0 +1,25 @@
+-- This file should undo anything in `up.sql`
+CREATE OR REPLACE FUNCTION audit_events_reject_update() RETURNS trigger AS $$
+BEGIN
+	IF NEW.actor_id IS NULL AND
+		(NEW.id, NEW.subject_id, NEW.guild_id, NEW.old_state, NEW.new_state, NEW.method, NEW.created_at)
+		IS NOT DISTINCT FROM
+		(OLD.id, OLD.subject_id, OLD.guild_id, OLD.old_state, OLD.new_state, OLD.method, OLD.created_at)
+	THEN
+		RETURN NEW;
+	END IF;
+
+	RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE audit_events DROP COLUMN reason;
+
+-- Postgres can't drop values from an enum, so recreate it without the new value.
+DELETE FROM audit_events WHERE method = 'whois_lookup';
+ALTER TYPE audit_method RENAME TO audit_method_old;
+CREATE TYPE audit_method AS ENUM ('member_join', 'verify_command', 'set_email', 'otp', 'retention');
+ALTER TABLE audit_events ALTER COLUMN method TYPE audit_method USING method::text::audit_method;
+DROP TYPE audit_method_old;
+
+ALTER TABLE servers DROP COLUMN whois_role_id;
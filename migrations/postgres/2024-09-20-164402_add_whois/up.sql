@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+ALTER TABLE servers ADD COLUMN whois_role_id bigint;
+
+ALTER TYPE audit_method ADD VALUE 'whois_lookup';
+ALTER TABLE audit_events ADD COLUMN reason varchar;
+
+CREATE OR REPLACE FUNCTION audit_events_reject_update() RETURNS trigger AS $$
+BEGIN
+	IF NEW.actor_id IS NULL AND
+		(NEW.id, NEW.subject_id, NEW.guild_id, NEW.old_state, NEW.new_state, NEW.method, NEW.created_at, NEW.reason)
+		IS NOT DISTINCT FROM
+		(OLD.id, OLD.subject_id, OLD.guild_id, OLD.old_state, OLD.new_state, OLD.method, OLD.created_at, OLD.reason)
+	THEN
+		RETURN NEW;
+	END IF;
+
+	RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
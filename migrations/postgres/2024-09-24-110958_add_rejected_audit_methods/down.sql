@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX audit_events_guild_id_method_idx;
+
+-- Postgres can't drop values from an enum, so recreate it without the new values.
+DELETE FROM audit_events WHERE method IN ('email_rejected', 'otp_rejected');
+ALTER TYPE audit_method RENAME TO audit_method_old;
+CREATE TYPE audit_method AS ENUM ('member_join', 'verify_command', 'set_email', 'otp', 'retention', 'whois_lookup');
+ALTER TABLE audit_events ALTER COLUMN method TYPE audit_method USING method::text::audit_method;
+DROP TYPE audit_method_old;
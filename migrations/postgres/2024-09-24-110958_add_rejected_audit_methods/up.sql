@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+ALTER TYPE audit_method ADD VALUE 'email_rejected';
+ALTER TYPE audit_method ADD VALUE 'otp_rejected';
+
+CREATE INDEX audit_events_guild_id_method_idx ON audit_events (guild_id, method);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+CREATE TABLE api_tokens (
+	id			bigserial PRIMARY KEY,
+	guild_id	bigint NOT NULL,
+	name		varchar NOT NULL,
+	token_hash	bytea NOT NULL UNIQUE,
+	created_by	bigint,
+	created_at	timestamptz NOT NULL DEFAULT now(),
+	revoked_at	timestamptz
+);
+
+CREATE INDEX api_tokens_guild_id_idx ON api_tokens (guild_id);
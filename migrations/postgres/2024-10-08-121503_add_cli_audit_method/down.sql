@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+-- Postgres can't drop values from an enum, so recreate it without the new value.
+DELETE FROM audit_events WHERE method = 'cli';
+ALTER TYPE audit_method RENAME TO audit_method_old;
+CREATE TYPE audit_method AS ENUM ('member_join', 'verify_command', 'set_email', 'otp', 'retention', 'whois_lookup', 'email_rejected', 'otp_rejected');
+ALTER TABLE audit_events ALTER COLUMN method TYPE audit_method USING method::text::audit_method;
+DROP TYPE audit_method_old;
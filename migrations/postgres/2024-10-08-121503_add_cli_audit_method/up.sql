@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TYPE audit_method ADD VALUE 'cli';
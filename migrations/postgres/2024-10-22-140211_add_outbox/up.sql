@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+CREATE TYPE outbox_status AS ENUM ('pending', 'sent', 'dead');
+
+-- Emails waiting to be sent, and what happened to them. The message is encrypted like users'
+-- emails, and cleared once it has been sent or given up on.
+CREATE TABLE outbox (
+	id					bigserial PRIMARY KEY,
+	user_id				bigint NOT NULL,
+	status				outbox_status NOT NULL DEFAULT 'pending',
+	attempts			integer NOT NULL DEFAULT 0,
+	next_attempt_at		timestamptz NOT NULL DEFAULT now(),
+	last_error			varchar,
+	message_ciphertext	bytea,
+	message_key_id		varchar,
+	created_at			timestamptz NOT NULL DEFAULT now(),
+	updated_at			timestamptz NOT NULL DEFAULT now()
+);
+
+SELECT diesel_manage_updated_at('outbox');
+
+CREATE INDEX outbox_status_next_attempt_at_idx ON outbox (status, next_attempt_at);
+CREATE INDEX outbox_user_id_idx ON outbox (user_id);
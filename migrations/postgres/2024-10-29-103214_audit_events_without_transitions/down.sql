@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE audit_events DISABLE TRIGGER audit_events_no_update;
+UPDATE audit_events SET new_state = COALESCE(old_state, 'unverified') WHERE new_state IS NULL;
+ALTER TABLE audit_events ENABLE TRIGGER audit_events_no_update;
+
+ALTER TABLE audit_events ALTER COLUMN new_state SET NOT NULL;
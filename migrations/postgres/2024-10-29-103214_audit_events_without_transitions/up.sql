@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- Events that aren't state transitions (like `/whois` lookups) have no new state. The old state
+-- is still the user's state at the time.
+ALTER TABLE audit_events ALTER COLUMN new_state DROP NOT NULL;
+
+-- Lookups were recorded as transitions to the state the user was already in. This is the one
+-- time the trail is rewritten, so the append-only trigger is turned off for it.
+ALTER TABLE audit_events DISABLE TRIGGER audit_events_no_update;
+UPDATE audit_events SET new_state = NULL WHERE method = 'whois_lookup';
+ALTER TABLE audit_events ENABLE TRIGGER audit_events_no_update;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE audit_events DISABLE TRIGGER audit_events_no_update;
+UPDATE audit_events SET new_state = COALESCE(old_state, 'unverified')
+	WHERE method IN ('email_rejected', 'otp_rejected');
+ALTER TABLE audit_events ENABLE TRIGGER audit_events_no_update;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Rejected emails and passcodes were recorded as transitions to the state the user was already
+-- in. Like lookups, they now have no new state.
+ALTER TABLE audit_events DISABLE TRIGGER audit_events_no_update;
+UPDATE audit_events SET new_state = NULL WHERE method IN ('email_rejected', 'otp_rejected');
+ALTER TABLE audit_events ENABLE TRIGGER audit_events_no_update;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- When the user's passcodes were sent. Passcodes expire a while after this, however else the user
+-- is updated in the meantime.
+ALTER TABLE users ADD COLUMN otp_sent_at timestamptz;
+
+-- Passcodes that are already out were sent when the user was last updated. Backfilling them
+-- shouldn't count as updating the users.
+ALTER TABLE users DISABLE TRIGGER set_updated_at;
+UPDATE users SET otp_sent_at = updated_at WHERE cardinality(otps) > 0;
+ALTER TABLE users ENABLE TRIGGER set_updated_at;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE api_tokens;
+DROP TABLE tombstones;
+DROP TABLE audit_events;
+DROP TABLE servers;
+DROP TABLE users;
@@ -0,0 +1,95 @@
+-- Your SQL goes here
+
+-- The same schema as the postgres migrations end up with. SQLite has no enum or array types, so
+-- enums are stored as text and passcodes as a JSON array, and timestamps are stored as text in the
+-- format diesel writes them in, so that they sort correctly.
+
+CREATE TABLE users (
+	id							INTEGER PRIMARY KEY,
+	imperial_email				TEXT,
+	state						TEXT NOT NULL DEFAULT 'unverified'
+		CHECK (state IN ('unverified', 'querying_email', 'querying_otp', 'verified')),
+	otps						TEXT NOT NULL DEFAULT '[]' CHECK (json_type(otps) = 'array'),
+	created_at					TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	updated_at					TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	imperial_email_ciphertext	BLOB,
+	imperial_email_key_id		TEXT,
+	imperial_email_index		BLOB
+);
+
+CREATE INDEX users_state_updated_at_idx ON users (state, updated_at);
+CREATE INDEX users_imperial_email_index_idx ON users (imperial_email_index);
+
+-- Like `diesel_manage_updated_at`, unless `updated_at` was set by the update itself.
+CREATE TRIGGER users_set_updated_at AFTER UPDATE ON users
+	FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+	UPDATE users SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TABLE servers (
+	id					INTEGER PRIMARY KEY,
+	verified_role_id	INTEGER,
+	log_channel_id		INTEGER,
+	show_emails			BOOLEAN NOT NULL DEFAULT false,
+	whois_role_id		INTEGER
+);
+
+CREATE TABLE audit_events (
+	id			INTEGER PRIMARY KEY,
+	actor_id	INTEGER,
+	subject_id	INTEGER NOT NULL,
+	guild_id	INTEGER,
+	old_state	TEXT CHECK (old_state IN ('unverified', 'querying_email', 'querying_otp', 'verified')),
+	new_state	TEXT NOT NULL
+		CHECK (new_state IN ('unverified', 'querying_email', 'querying_otp', 'verified')),
+	method		TEXT NOT NULL CHECK (method IN (
+		'member_join', 'verify_command', 'set_email', 'otp', 'retention', 'whois_lookup',
+		'email_rejected', 'otp_rejected', 'cli'
+	)),
+	created_at	TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	reason		TEXT
+);
+
+CREATE INDEX audit_events_subject_id_idx ON audit_events (subject_id, created_at);
+CREATE INDEX audit_events_guild_id_method_idx ON audit_events (guild_id, method);
+
+-- The audit trail is append-only, except that erasing a user anonymises the events they acted in
+-- by clearing the actor.
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+	FOR EACH ROW WHEN NOT (
+		NEW.actor_id IS NULL
+		AND NEW.id IS OLD.id
+		AND NEW.subject_id IS OLD.subject_id
+		AND NEW.guild_id IS OLD.guild_id
+		AND NEW.old_state IS OLD.old_state
+		AND NEW.new_state IS OLD.new_state
+		AND NEW.method IS OLD.method
+		AND NEW.created_at IS OLD.created_at
+		AND NEW.reason IS OLD.reason
+	)
+BEGIN
+	SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
+
+-- Non-reversible records of erased users, kept only when a ban requires one.
+CREATE TABLE tombstones (
+	id				INTEGER PRIMARY KEY,
+	discord_id_hash	BLOB NOT NULL,
+	email_hash		BLOB,
+	created_at		TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX tombstones_email_hash_idx ON tombstones (email_hash);
+
+CREATE TABLE api_tokens (
+	id			INTEGER PRIMARY KEY,
+	guild_id	INTEGER NOT NULL,
+	name		TEXT NOT NULL,
+	token_hash	BLOB NOT NULL UNIQUE,
+	created_by	INTEGER,
+	created_at	TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	revoked_at	TEXT
+);
+
+CREATE INDEX api_tokens_guild_id_idx ON api_tokens (guild_id);
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+
+-- Emails waiting to be sent, and what happened to them. The message is encrypted like users'
+-- emails, and cleared once it has been sent or given up on.
+CREATE TABLE outbox (
+	id					INTEGER PRIMARY KEY,
+	user_id				INTEGER NOT NULL,
+	status				TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'dead')),
+	attempts			INTEGER NOT NULL DEFAULT 0,
+	next_attempt_at		TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	last_error			TEXT,
+	message_ciphertext	BLOB,
+	message_key_id		TEXT,
+	created_at			TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	updated_at			TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+CREATE TRIGGER outbox_set_updated_at AFTER UPDATE ON outbox
+	FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+	UPDATE outbox SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
+
+CREATE INDEX outbox_status_next_attempt_at_idx ON outbox (status, next_attempt_at);
+CREATE INDEX outbox_user_id_idx ON outbox (user_id);
@@ -0,0 +1,52 @@
+-- Your SQL goes here
+
+-- Events that aren't state transitions (like `/whois` lookups) have no new state. The old state
+-- is still the user's state at the time. SQLite can't change a column's constraints, so the table
+-- is rebuilt, which also rewrites the lookups that were recorded as transitions to the state the
+-- user was already in.
+DROP TRIGGER audit_events_no_update;
+ALTER TABLE audit_events RENAME TO audit_events_old;
+
+CREATE TABLE audit_events (
+	id			INTEGER PRIMARY KEY,
+	actor_id	INTEGER,
+	subject_id	INTEGER NOT NULL,
+	guild_id	INTEGER,
+	old_state	TEXT CHECK (old_state IN ('unverified', 'querying_email', 'querying_otp', 'verified')),
+	new_state	TEXT CHECK (new_state IN ('unverified', 'querying_email', 'querying_otp', 'verified')),
+	method		TEXT NOT NULL CHECK (method IN (
+		'member_join', 'verify_command', 'set_email', 'otp', 'retention', 'whois_lookup',
+		'email_rejected', 'otp_rejected', 'cli'
+	)),
+	created_at	TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+	reason		TEXT
+);
+
+INSERT INTO audit_events
+	SELECT id, actor_id, subject_id, guild_id, old_state,
+		CASE WHEN method = 'whois_lookup' THEN NULL ELSE new_state END,
+		method, created_at, reason
+	FROM audit_events_old;
+
+DROP TABLE audit_events_old;
+
+CREATE INDEX audit_events_subject_id_idx ON audit_events (subject_id, created_at);
+CREATE INDEX audit_events_guild_id_method_idx ON audit_events (guild_id, method);
+
+-- The audit trail is append-only, except that erasing a user anonymises the events they acted in
+-- by clearing the actor.
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+	FOR EACH ROW WHEN NOT (
+		NEW.actor_id IS NULL
+		AND NEW.id IS OLD.id
+		AND NEW.subject_id IS OLD.subject_id
+		AND NEW.guild_id IS OLD.guild_id
+		AND NEW.old_state IS OLD.old_state
+		AND NEW.new_state IS OLD.new_state
+		AND NEW.method IS OLD.method
+		AND NEW.created_at IS OLD.created_at
+		AND NEW.reason IS OLD.reason
+	)
+BEGIN
+	SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+-- Rejected emails and passcodes were recorded as transitions to the state the user was already
+-- in. Like lookups, they now have no new state. SQLite can't turn triggers off, so the
+-- append-only one is dropped for this and put back.
+DROP TRIGGER audit_events_no_update;
+
+UPDATE audit_events SET new_state = NULL WHERE method IN ('email_rejected', 'otp_rejected');
+
+-- The audit trail is append-only, except that erasing a user anonymises the events they acted in
+-- by clearing the actor.
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+	FOR EACH ROW WHEN NOT (
+		NEW.actor_id IS NULL
+		AND NEW.id IS OLD.id
+		AND NEW.subject_id IS OLD.subject_id
+		AND NEW.guild_id IS OLD.guild_id
+		AND NEW.old_state IS OLD.old_state
+		AND NEW.new_state IS OLD.new_state
+		AND NEW.method IS OLD.method
+		AND NEW.created_at IS OLD.created_at
+		AND NEW.reason IS OLD.reason
+	)
+BEGIN
+	SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
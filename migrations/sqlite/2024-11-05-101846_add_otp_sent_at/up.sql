@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+-- When the user's passcodes were sent. Passcodes expire a while after this, however else the user
+-- is updated in the meantime.
+ALTER TABLE users ADD COLUMN otp_sent_at TEXT;
+
+-- Passcodes that are already out were sent when the user was last updated. Backfilling them
+-- shouldn't count as updating the users.
+DROP TRIGGER users_set_updated_at;
+UPDATE users SET otp_sent_at = updated_at WHERE json_array_length(otps) > 0;
+CREATE TRIGGER users_set_updated_at AFTER UPDATE ON users
+	FOR EACH ROW WHEN NEW.updated_at IS OLD.updated_at
+BEGIN
+	UPDATE users SET updated_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now') WHERE id = NEW.id;
+END;
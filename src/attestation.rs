@@ -0,0 +1,175 @@
+use crate::config;
+use base64::prelude::*;
+use chrono::Utc;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use poise::serenity_prelude::UserId;
+use serde::{Deserialize, Serialize};
+
+/// Error checking an attestation.
+#[derive(Debug, thiserror::Error)]
+pub enum AttestationError {
+    #[error("malformed attestation")]
+    Malformed,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("attestation expired")]
+    Expired,
+}
+
+/// What an attestation attests to. Deliberately doesn't include the user's email.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// The user's discord ID.
+    pub sub: String,
+    /// Whether the user is a verified Imperial member.
+    pub verified: bool,
+    /// When the attestation was issued, as a unix timestamp.
+    pub iat: i64,
+    /// When the attestation expires, as a unix timestamp.
+    pub exp: i64,
+}
+
+/// The public key that attestations can be checked with.
+pub fn public_key() -> VerifyingKey {
+    config::get().attestation.signing_key.verifying_key()
+}
+
+/// Parse a base64, 32-byte Ed25519 seed to sign attestations with.
+pub fn parse_signing_key(key: &str) -> Option<SigningKey> {
+    let key = BASE64_STANDARD.decode(key.trim()).ok()?;
+    Some(SigningKey::from_bytes(&key.try_into().ok()?))
+}
+
+/// Parse a base64 public key, as printed by `imperial-bot attestation-key`.
+pub fn parse_public_key(key: &str) -> Option<VerifyingKey> {
+    let key = BASE64_STANDARD.decode(key.trim()).ok()?;
+    VerifyingKey::from_bytes(&key.try_into().ok()?).ok()
+}
+
+/// Issue a signed attestation for a user. Attestations are of the form `<claims>.<signature>`,
+/// where both parts are base64url encoded and the claims are JSON.
+pub fn issue(user_id: UserId, verified: bool) -> String {
+    let config = &config::get().attestation;
+    let now = Utc::now();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        verified,
+        iat: now.timestamp(),
+        exp: (now + config.ttl).timestamp(),
+    };
+
+    sign(&claims, &config.signing_key)
+}
+
+/// Sign claims with a key, giving an attestation.
+fn sign(claims: &Claims, key: &SigningKey) -> String {
+    let claims = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+    let signature = key.sign(claims.as_bytes());
+
+    format!(
+        "{}.{}",
+        claims,
+        BASE64_URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    )
+}
+
+/// Check an attestation against a public key, without needing access to the bot. Returns the
+/// attested claims if the attestation is valid and hasn't expired.
+pub fn check(attestation: &str, key: &VerifyingKey) -> Result<Claims, AttestationError> {
+    let (claims, signature) = attestation
+        .trim()
+        .split_once('.')
+        .ok_or(AttestationError::Malformed)?;
+
+    let signature = BASE64_URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| AttestationError::Malformed)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| AttestationError::Malformed)?;
+
+    key.verify(claims.as_bytes(), &signature)
+        .map_err(|_| AttestationError::InvalidSignature)?;
+
+    let claims = BASE64_URL_SAFE_NO_PAD
+        .decode(claims)
+        .map_err(|_| AttestationError::Malformed)?;
+    let claims: Claims =
+        serde_json::from_slice(&claims).map_err(|_| AttestationError::Malformed)?;
+
+    if claims.exp < Utc::now().timestamp() {
+        return Err(AttestationError::Expired);
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn claims(expires_in: TimeDelta) -> Claims {
+        let now = Utc::now();
+
+        Claims {
+            sub: "1234".to_string(),
+            verified: true,
+            iat: now.timestamp(),
+            exp: (now + expires_in).timestamp(),
+        }
+    }
+
+    #[test]
+    fn attestations_can_be_checked_with_the_public_key() {
+        let attestation = sign(&claims(TimeDelta::minutes(15)), &key());
+
+        let checked = check(&attestation, &key().verifying_key()).unwrap();
+
+        assert_eq!(checked.sub, "1234");
+        assert!(checked.verified);
+    }
+
+    #[test]
+    fn expired_attestations_are_rejected() {
+        let attestation = sign(&claims(TimeDelta::minutes(-1)), &key());
+
+        assert!(matches!(
+            check(&attestation, &key().verifying_key()),
+            Err(AttestationError::Expired)
+        ));
+    }
+
+    #[test]
+    fn tampered_attestations_are_rejected() {
+        let attestation = sign(&claims(TimeDelta::minutes(15)), &key());
+        let (_, signature) = attestation.split_once('.').unwrap();
+
+        // Claim to be someone else, keeping the original signature.
+        let mut forged = claims(TimeDelta::minutes(15));
+        forged.sub = "5678".to_string();
+        let forged = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap()),
+            signature
+        );
+        assert!(matches!(
+            check(&forged, &key().verifying_key()),
+            Err(AttestationError::InvalidSignature)
+        ));
+
+        // Signed by a different key.
+        let other_key = SigningKey::from_bytes(&[2; 32]);
+        assert!(matches!(
+            check(&attestation, &other_key.verifying_key()),
+            Err(AttestationError::InvalidSignature)
+        ));
+
+        assert!(matches!(
+            check("not an attestation", &key().verifying_key()),
+            Err(AttestationError::Malformed)
+        ));
+    }
+}
@@ -0,0 +1,360 @@
+//! Subcommands for operators, so that data can be inspected and fixed without writing SQL.
+
+use crate::db::models::*;
+use crate::db::Database;
+use crate::{attestation, config, db, discord};
+use base64::prelude::*;
+use clap::{Parser, Subcommand};
+use log::info;
+use poise::serenity_prelude::{GuildId, Http, UserId};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+
+/// A discord bot for verifying that users are actually from Imperial College London.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the bot. This is the default.
+    Serve,
+    /// Apply any database migrations that haven't been applied yet.
+    Migrate,
+    /// Inspect or change a user.
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Inspect a server.
+    Server {
+        #[command(subcommand)]
+        command: ServerCommand,
+    },
+    /// Export the whole database as JSON, including the outbox. Emails stay encrypted.
+    Export {
+        /// File to write the export to. Defaults to stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Import an export from `export`. Rows that already exist are skipped.
+    Import {
+        /// File to read the export from.
+        input: PathBuf,
+    },
+    /// Re-encrypt all stored emails, and the messages waiting in the outbox, with the active key
+    /// (`EMAIL_ENCRYPTION_KEY_ID`). Also encrypts emails stored before encryption was added.
+    RotateKeys {
+        /// How many emails or messages to re-encrypt at once.
+        #[arg(long, default_value_t = 100)]
+        batch_size: i64,
+    },
+    /// Print the public key that attestations from `/attest` can be checked with.
+    AttestationKey,
+    /// Check an attestation from `/attest` offline.
+    CheckAttestation {
+        /// The attestation to check.
+        attestation: String,
+        /// The public key to check against, as printed by `attestation-key`. Defaults to the key
+        /// derived from `ATTESTATION_SIGNING_KEY`.
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+    /// Print the DNS TXT record that receivers check DKIM signatures against. Needs
+    /// `DKIM_SELECTOR` and `DKIM_PRIVATE_KEY_FILE`.
+    DkimRecord,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Print everything stored about a user, including their decrypted email.
+    Show {
+        /// The user's discord ID.
+        id: u64,
+    },
+    /// Mark a user as verified, and give them the verified role on every server.
+    Verify {
+        /// The user's discord ID.
+        id: u64,
+    },
+    /// Mark a user as unverified, and take the verified role away on every server.
+    Unverify {
+        /// The user's discord ID.
+        id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ServerCommand {
+    /// Print a server's settings.
+    Show {
+        /// The server's discord ID.
+        id: u64,
+    },
+}
+
+/// Run an operator subcommand. `serve` is handled by `main`.
+pub async fn run(command: Command) {
+    // Checking an attestation against a given key is the only thing that doesn't need the config.
+    if !matches!(
+        command,
+        Command::CheckAttestation {
+            public_key: Some(_),
+            ..
+        }
+    ) {
+        config::load_or_exit();
+    }
+
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Migrate => migrate(&connect()).await,
+        Command::User { command } => {
+            let db = connect();
+            match command {
+                UserCommand::Show { id } => show_user(&db, user_id(id)).await,
+                UserCommand::Verify { id } => set_verified(&db, user_id(id), true).await,
+                UserCommand::Unverify { id } => set_verified(&db, user_id(id), false).await,
+            }
+        }
+        Command::Server {
+            command: ServerCommand::Show { id },
+        } => show_server(&connect(), guild_id(id)).await,
+        Command::Export { output } => export(&connect(), output).await,
+        Command::Import { input } => import(&connect(), input).await,
+        Command::RotateKeys { batch_size } => rotate_keys(&connect(), batch_size).await,
+        Command::AttestationKey => println!(
+            "{}",
+            BASE64_STANDARD.encode(attestation::public_key().as_bytes())
+        ),
+        Command::CheckAttestation {
+            attestation,
+            public_key,
+        } => check_attestation(&attestation, public_key.as_deref()),
+        Command::DkimRecord => match &config::get().mail.dkim {
+            Some(dkim) => println!("{}", dkim.dns_record()),
+            None => fail("DKIM_SELECTOR isn't set, so emails aren't signed"),
+        },
+    }
+}
+
+/// Connect to the database in the config.
+fn connect() -> Database {
+    Database::connect(&config::get().database)
+}
+
+/// Print an error and exit.
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn user_id(id: u64) -> UserId {
+    if id == 0 {
+        fail("Invalid user ID");
+    }
+    UserId::new(id)
+}
+
+fn guild_id(id: u64) -> GuildId {
+    if id == 0 {
+        fail("Invalid server ID");
+    }
+    GuildId::new(id)
+}
+
+async fn migrate(db: &Database) {
+    let applied = db::run_pending_migrations(db)
+        .await
+        .unwrap_or_else(|err| fail(err));
+
+    if applied.is_empty() {
+        println!("Database is up to date");
+    }
+    for version in applied {
+        println!("Applied migration {}", version);
+    }
+}
+
+async fn show_user(db: &Database, user_id: UserId) {
+    let data = db::collect_user_data(db, user_id)
+        .await
+        .unwrap_or_else(|err| fail(err));
+
+    if data.user.is_none() {
+        fail(format!("User {} not found", user_id));
+    }
+
+    println!("{}", serde_json::to_string_pretty(&data).unwrap());
+}
+
+async fn set_verified(db: &Database, user_id: UserId, verified: bool) {
+    if !db::user_exists(db, user_id)
+        .await
+        .unwrap_or_else(|err| fail(err))
+    {
+        if !verified {
+            fail(format!("User {} not found", user_id));
+        }
+        db::create_user(db, user_id)
+            .await
+            .unwrap_or_else(|err| fail(err));
+    }
+
+    let state = if verified {
+        UserState::Verified
+    } else {
+        UserState::Unverified
+    };
+    db::set_user_state(db, user_id, state, None, None, AuditMethod::Cli)
+        .await
+        .unwrap_or_else(|err| fail(err));
+
+    println!("Set user {} to {:?}", user_id, state);
+
+    let http = Http::new(&config::get().discord_token);
+
+    let updates = if verified {
+        discord::verify_on_all_servers(&http, db, user_id).await
+    } else {
+        discord::unverify_on_all_servers(&http, db, user_id).await
+    }
+    .unwrap_or_else(|err| fail(format!("Error updating roles: {}", err)));
+
+    let mut failed = false;
+    for (guild_id, result) in &updates {
+        match result {
+            Ok(()) => println!("Updated roles on server {}", guild_id),
+            Err(err) => {
+                eprintln!("Error updating roles on server {}: {}", guild_id, err);
+                failed = true;
+            }
+        }
+    }
+    if updates.is_empty() {
+        println!("No roles to update");
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+async fn show_server(db: &Database, guild_id: GuildId) {
+    match db::get_server(db, guild_id)
+        .await
+        .unwrap_or_else(|err| fail(err))
+    {
+        Some(server) => println!("{}", serde_json::to_string_pretty(&server).unwrap()),
+        None => fail(format!("Server {} not found", guild_id)),
+    }
+}
+
+async fn export(db: &Database, output: Option<PathBuf>) {
+    let export = db::export_all(db).await.unwrap_or_else(|err| fail(err));
+
+    let writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(File::create(path).unwrap_or_else(|err| fail(err))),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = BufWriter::new(writer);
+
+    serde_json::to_writer(&mut writer, &export).unwrap_or_else(|err| fail(err));
+    writer.flush().unwrap_or_else(|err| fail(err));
+
+    info!(
+        "Exported {} users, {} servers, {} audit events, {} tombstones, {} API tokens and {} \
+        outbox emails",
+        export.users.len(),
+        export.servers.len(),
+        export.audit_events.len(),
+        export.tombstones.len(),
+        export.api_tokens.len(),
+        export.outbox.len()
+    );
+}
+
+async fn import(db: &Database, input: PathBuf) {
+    let file = File::open(&input).unwrap_or_else(|err| fail(err));
+    let export: db::Export =
+        serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| fail(err));
+
+    if !db::export_is_supported(&export) {
+        fail(format!("Unsupported export version {}", export.version));
+    }
+
+    let counts = db::import_all(db, export)
+        .await
+        .unwrap_or_else(|err| fail(err));
+
+    println!(
+        "Imported {} users, {} servers, {} audit events, {} tombstones, {} API tokens and {} \
+        outbox emails",
+        counts.users,
+        counts.servers,
+        counts.audit_events,
+        counts.tombstones,
+        counts.api_tokens,
+        counts.outbox
+    );
+}
+
+async fn rotate_keys(db: &Database, batch_size: i64) {
+    let mut emails = 0;
+    loop {
+        let count = db::reencrypt_emails_batch(db, batch_size)
+            .await
+            .unwrap_or_else(|err| fail(format!("Error re-encrypting emails: {}", err)));
+
+        if count == 0 {
+            break;
+        }
+
+        emails += count;
+        info!("Re-encrypted {} emails so far", emails);
+    }
+
+    let mut messages = 0;
+    loop {
+        let count = db::reencrypt_outbox_batch(db, batch_size)
+            .await
+            .unwrap_or_else(|err| fail(format!("Error re-encrypting the outbox: {}", err)));
+
+        if count == 0 {
+            break;
+        }
+
+        messages += count;
+        info!("Re-encrypted {} outbox messages so far", messages);
+    }
+
+    println!(
+        "Re-encrypted {} emails and {} outbox messages",
+        emails, messages
+    );
+}
+
+fn check_attestation(token: &str, public_key: Option<&str>) {
+    let key = match public_key {
+        Some(key) => attestation::parse_public_key(key).expect("Invalid public key"),
+        None => attestation::public_key(),
+    };
+
+    match attestation::check(token, &key) {
+        Ok(claims) => println!(
+            "Valid: user {} is {}verified (issued at {}, expires at {})",
+            claims.sub,
+            if claims.verified { "" } else { "not " },
+            claims.iat,
+            claims.exp
+        ),
+        Err(err) => {
+            println!("Invalid: {}", err);
+            process::exit(1);
+        }
+    }
+}
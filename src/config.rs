@@ -0,0 +1,873 @@
+//! The bot's configuration, read from a TOML file with environment variables overriding it. All
+//! of it is checked at startup, so that every problem is reported at once rather than whenever a
+//! setting happens to be used first. Some settings can be changed without restarting, by reloading
+//! the config.
+
+use crate::attestation;
+use crate::db::{models::UserState, EmailKeys};
+use crate::mail::{Dkim, VerificationTemplates};
+use crate::retention::{RetentionAction, RetentionPolicy};
+use chrono::TimeDelta;
+use ed25519_dalek::SigningKey;
+use lettre::message::Mailbox;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{env, fmt, fs, io, process};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// The config file read if `CONFIG_FILE` isn't set. It doesn't have to exist.
+const DEFAULT_FILE: &str = "config.toml";
+
+/// Every setting, as its key in the config file and the environment variable that overrides it.
+const SETTINGS: [(&str, &str); 34] = [
+    ("discord.token", "DISCORD_TOKEN"),
+    ("database.url", "DATABASE_URL"),
+    ("database.pool_size", "DATABASE_POOL_SIZE"),
+    ("database.run_migrations", "RUN_MIGRATIONS"),
+    ("mail.backend", "MAIL_BACKEND"),
+    ("mail.from", "SMTP_FROM"),
+    ("mail.dir", "MAIL_DIR"),
+    ("mail.template_dir", "MAIL_TEMPLATE_DIR"),
+    (
+        "mail.test_email_cooldown_minutes",
+        "TEST_EMAIL_COOLDOWN_MINUTES",
+    ),
+    ("smtp.host", "SMTP_HOST"),
+    ("smtp.port", "SMTP_PORT"),
+    ("smtp.user", "SMTP_USER"),
+    ("smtp.pass", "SMTP_PASS"),
+    ("dkim.selector", "DKIM_SELECTOR"),
+    ("dkim.private_key_file", "DKIM_PRIVATE_KEY_FILE"),
+    ("dkim.domain", "DKIM_DOMAIN"),
+    ("encryption.keys", "EMAIL_ENCRYPTION_KEYS"),
+    ("encryption.key_id", "EMAIL_ENCRYPTION_KEY_ID"),
+    ("encryption.index_key", "EMAIL_INDEX_KEY"),
+    ("encryption.tombstone_key", "TOMBSTONE_KEY"),
+    ("attestation.signing_key", "ATTESTATION_SIGNING_KEY"),
+    ("attestation.ttl_minutes", "ATTESTATION_TTL_MINUTES"),
+    ("retention.unverified_days", "RETENTION_UNVERIFIED_DAYS"),
+    (
+        "retention.querying_email_days",
+        "RETENTION_QUERYING_EMAIL_DAYS",
+    ),
+    ("retention.querying_otp_days", "RETENTION_QUERYING_OTP_DAYS"),
+    ("retention.action", "RETENTION_ACTION"),
+    ("retention.dry_run", "RETENTION_DRY_RUN"),
+    ("retention.interval_hours", "RETENTION_INTERVAL_HOURS"),
+    ("http.addr", "HTTP_ADDR"),
+    ("http.metrics_addr", "METRICS_ADDR"),
+    ("dashboard.client_id", "DISCORD_CLIENT_ID"),
+    ("dashboard.client_secret", "DISCORD_CLIENT_SECRET"),
+    ("dashboard.url", "DASHBOARD_URL"),
+    ("verification.allowed_domains", "ALLOWED_EMAIL_DOMAINS"),
+];
+
+/// The settings that take effect when the config is reloaded. The rest need a restart.
+const RELOADABLE_SETTINGS: [&str; 3] = [
+    "mail.template_dir",
+    "mail.test_email_cooldown_minutes",
+    "verification.allowed_domains",
+];
+
+/// The longest any duration setting can be, so that times calculated from them stay in range.
+const MAX_DURATION: TimeDelta = TimeDelta::days(100 * 365);
+
+/// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The config, once it has been loaded.
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Everything the bot can be configured with.
+pub struct Config {
+    pub discord_token: String,
+    pub database: DatabaseConfig,
+    pub mail: MailConfig,
+    pub keys: Keys,
+    pub attestation: AttestationConfig,
+    pub retention: RetentionPolicy,
+    pub http: HttpConfig,
+    /// Swapped for the new settings when the config is reloaded.
+    reloadable: RwLock<Arc<Reloadable>>,
+    /// The raw values of the settings, to tell which ones a reload changed.
+    values: HashMap<&'static str, String>,
+}
+
+/// The settings that can be changed without restarting.
+pub struct Reloadable {
+    pub templates: VerificationTemplates,
+    /// How long each server must wait between test emails from `/test_email`.
+    pub test_email_cooldown: Duration,
+    /// The domains that emails have to be in to verify with, e.g. `imperial.ac.uk`.
+    pub allowed_domains: Vec<String>,
+}
+
+pub struct DatabaseConfig {
+    /// A `postgres://` URL, or a `sqlite://` path if the bot was built with the `sqlite` feature.
+    pub url: String,
+    /// How many connections are kept open.
+    pub pool_size: u32,
+    /// Whether pending migrations are applied on startup.
+    pub run_migrations: bool,
+}
+
+pub struct MailConfig {
+    pub backend: MailBackend,
+    /// The address emails are sent from.
+    pub from: Mailbox,
+    /// Signs emails, if DKIM is set up.
+    pub dkim: Option<Dkim>,
+}
+
+/// How emails are sent.
+pub enum MailBackend {
+    Smtp(SmtpConfig),
+    /// Write each email to a file in this directory.
+    File(PathBuf),
+    /// Print each email.
+    Stdout,
+}
+
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub pass: String,
+}
+
+/// The secret keys stored data is protected with.
+pub struct Keys {
+    pub email: EmailKeys,
+    /// The key tombstoned identifiers are hashed with.
+    pub tombstone: Vec<u8>,
+}
+
+pub struct AttestationConfig {
+    pub signing_key: SigningKey,
+    /// How long attestations are valid for.
+    pub ttl: TimeDelta,
+}
+
+pub struct HttpConfig {
+    /// Where the HTTP endpoints are served, if anywhere.
+    pub addr: Option<String>,
+    /// Where metrics are served, if anywhere. This is separate from `addr` so that metrics aren't
+    /// exposed wherever the dashboard and API are. Only used with the `metrics` feature.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub metrics_addr: Option<String>,
+    pub dashboard: Option<DashboardConfig>,
+}
+
+/// The discord application the dashboard logs in with.
+pub struct DashboardConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// The public URL the dashboard is served at, without a trailing `/`.
+    pub url: String,
+}
+
+/// Every problem found with a config.
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) with the config:", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Load the config and make it available through `get`. Logs every problem with it and exits if
+/// it isn't valid.
+/// NOTE: If using `dotenv`, run `dotenv::dotenv().ok();` before calling this function.
+pub fn load_or_exit() -> &'static Config {
+    match Config::load() {
+        Ok(config) => CONFIG.get_or_init(|| config),
+        Err(errors) => {
+            error!("{}", errors);
+            process::exit(1);
+        }
+    }
+}
+
+/// The config loaded by `load_or_exit`.
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("the config hasn't been loaded")
+}
+
+/// Read the config again, and swap in its reloadable settings. If it isn't valid, the old config
+/// is kept. Returns the other settings that changed, which need a restart to take effect.
+pub fn reload() -> Result<Vec<&'static str>, ConfigErrors> {
+    Ok(get().update(Config::load()?))
+}
+
+/// Reload the config whenever the bot is sent SIGHUP, or the config file changes. Runs forever.
+pub async fn watch() {
+    let mut hangup = signal(SignalKind::hangup()).expect("Error listening for SIGHUP");
+    let mut modified = file_modified();
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!("Got SIGHUP, reloading the config"),
+            _ = tokio::time::sleep(WATCH_INTERVAL) => {
+                let now = file_modified();
+                if now == modified {
+                    continue;
+                }
+                modified = now;
+                info!("The config file changed, reloading it");
+            }
+        }
+
+        match reload() {
+            Ok(restart_needed) => {
+                info!("Reloaded the config");
+                for key in restart_needed {
+                    warn!(
+                        "`{}` changed, but the bot has to be restarted for it to take effect",
+                        key
+                    );
+                }
+            }
+            Err(errors) => error!(
+                "Keeping the old config, as the new one is invalid. {}",
+                errors
+            ),
+        }
+    }
+}
+
+/// The path of the config file, and whether it has to exist.
+fn file() -> (String, bool) {
+    match env::var("CONFIG_FILE") {
+        Ok(path) => (path, true),
+        Err(_) => (DEFAULT_FILE.to_string(), false),
+    }
+}
+
+/// When the config file was last changed, if it exists.
+fn file_modified() -> Option<SystemTime> {
+    fs::metadata(file().0)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Load a config for tests, that doesn't depend on the environment.
+#[cfg(all(test, feature = "sqlite"))]
+pub fn init_for_tests() -> &'static Config {
+    CONFIG.get_or_init(|| Config::parse(tests::CONFIG, |_| None).unwrap())
+}
+
+impl Config {
+    /// Read the config file at `CONFIG_FILE` (by default `config.toml`, if there is one), with
+    /// environment variables overriding it.
+    /// NOTE: If using `dotenv`, run `dotenv::dotenv().ok();` before calling this function.
+    pub fn load() -> Result<Config, ConfigErrors> {
+        let (path, must_exist) = file();
+
+        let file = match fs::read_to_string(&path) {
+            Ok(file) => {
+                info!("Reading the config from {}", path);
+                file
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !must_exist => String::new(),
+            Err(err) => return Err(ConfigErrors(vec![format!("{}: {}", path, err)])),
+        };
+
+        Config::parse(&file, |var| env::var(var).ok())
+    }
+
+    /// Parse and check a config file, with `env` looking up the environment variables that
+    /// override it.
+    pub fn parse(file: &str, env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigErrors> {
+        let mut settings = Settings::read(file, env);
+
+        let discord_token = settings.required("discord.token");
+        let database = database(&mut settings);
+        let mail = mail(&mut settings);
+        let keys = keys(&mut settings);
+        let attestation = attestation(&mut settings);
+        let retention = retention(&mut settings);
+        let http = http(&mut settings);
+        let reloadable = reloadable(&mut settings);
+
+        match (
+            discord_token,
+            database,
+            mail,
+            keys,
+            attestation,
+            retention,
+            http,
+            reloadable,
+        ) {
+            (
+                Some(discord_token),
+                Some(database),
+                Some(mail),
+                Some(keys),
+                Some(attestation),
+                Some(retention),
+                Some(http),
+                Some(reloadable),
+            ) if settings.errors.is_empty() => Ok(Config {
+                discord_token,
+                database,
+                mail,
+                keys,
+                attestation,
+                retention,
+                http,
+                reloadable: RwLock::new(Arc::new(reloadable)),
+                values: settings.values,
+            }),
+            _ => Err(ConfigErrors(settings.errors)),
+        }
+    }
+
+    /// The reloadable settings, as they are now.
+    pub fn reloadable(&self) -> Arc<Reloadable> {
+        self.reloadable.read().unwrap().clone()
+    }
+
+    /// Swap in the reloadable settings of a newer config. Returns the other settings that changed,
+    /// which are left as they were.
+    fn update(&self, new: Config) -> Vec<&'static str> {
+        let reloadable = new.reloadable.into_inner().unwrap();
+        *self.reloadable.write().unwrap() = reloadable;
+
+        SETTINGS
+            .iter()
+            .map(|(key, _)| *key)
+            .filter(|key| !RELOADABLE_SETTINGS.contains(key))
+            .filter(|key| self.values.get(key) != new.values.get(key))
+            .collect()
+    }
+}
+
+/// The values of the settings, from the config file and the environment, and the problems found
+/// with them so far.
+struct Settings {
+    values: HashMap<&'static str, String>,
+    errors: Vec<String>,
+}
+
+impl Settings {
+    fn read(file: &str, env: impl Fn(&str) -> Option<String>) -> Settings {
+        let mut settings = Settings {
+            values: HashMap::new(),
+            errors: vec![],
+        };
+
+        match file.parse::<toml::Table>() {
+            Ok(table) => settings.read_table(table),
+            Err(err) => settings.errors.push(format!(
+                "the config file isn't valid TOML: {}",
+                err.message()
+            )),
+        }
+
+        for (key, var) in SETTINGS {
+            if let Some(value) = env(var) {
+                settings.values.insert(key, value);
+            }
+        }
+
+        settings
+    }
+
+    /// Read the sections of a config file, whose values are all numbers, booleans or strings.
+    fn read_table(&mut self, table: toml::Table) {
+        for (section, values) in table {
+            let toml::Value::Table(values) = values else {
+                self.errors
+                    .push(format!("`{}` isn't a setting or a section", section));
+                continue;
+            };
+
+            for (name, value) in values {
+                let key = format!("{}.{}", section, name);
+                let Some(&(key, _)) = SETTINGS.iter().find(|(setting, _)| *setting == key) else {
+                    self.errors.push(format!("`{}` isn't a setting", key));
+                    continue;
+                };
+
+                let value = match value {
+                    toml::Value::String(value) => value,
+                    toml::Value::Integer(value) => value.to_string(),
+                    toml::Value::Boolean(value) => value.to_string(),
+                    _ => {
+                        self.error(key, "must be a string, a whole number or a boolean");
+                        continue;
+                    }
+                };
+                self.values.insert(key, value);
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    fn is_set(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Record a problem with a setting, naming both its key and its environment variable.
+    fn error(&mut self, key: &str, problem: impl fmt::Display) {
+        let var = SETTINGS
+            .iter()
+            .find(|(setting, _)| *setting == key)
+            .map_or("", |(_, var)| var);
+
+        self.errors.push(format!("`{}` ({}) {}", key, var, problem));
+    }
+
+    /// A setting that has to be set.
+    fn required(&mut self, key: &str) -> Option<String> {
+        let value = self.get(key).map(str::to_string);
+        if value.is_none() {
+            self.error(key, "must be set");
+        }
+
+        value
+    }
+
+    /// Check a setting's value with `parse`, if it is set.
+    fn parse_with<T>(
+        &mut self,
+        key: &str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Option<T> {
+        let value = self.get(key)?;
+
+        match parse(value) {
+            Ok(value) => Some(value),
+            Err(problem) => {
+                self.error(key, problem);
+                None
+            }
+        }
+    }
+
+    /// Parse a setting, if it is set. `expected` describes what it should be, e.g. `a number`.
+    fn parse<T: FromStr>(&mut self, key: &str, expected: &str) -> Option<T> {
+        self.parse_with(key, |value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("must be {}, not `{}`", expected, value))
+        })
+    }
+
+    /// Parse a setting, falling back to `default` if it isn't set (or isn't valid, in which case
+    /// the config is rejected anyway).
+    fn parse_or<T: FromStr>(&mut self, key: &str, expected: &str, default: T) -> T {
+        self.parse(key, expected).unwrap_or(default)
+    }
+
+    /// Parse a number that has to be more than 0.
+    fn positive_or(&mut self, key: &str, default: u64) -> u64 {
+        self.parse_with(key, |value| match value.trim().parse() {
+            Ok(0) | Err(_) => Err(format!("must be a positive number, not `{}`", value)),
+            Ok(value) => Ok(value),
+        })
+        .unwrap_or(default)
+    }
+
+    /// Parse an amount of time in `unit`s, e.g. days with `TimeDelta::try_days`. It has to be at
+    /// least `min`, and at most `MAX_DURATION`.
+    fn duration(
+        &mut self,
+        key: &str,
+        min: i64,
+        unit: &str,
+        to_duration: fn(i64) -> Option<TimeDelta>,
+    ) -> Option<TimeDelta> {
+        self.parse_with(key, |value| {
+            let amount = match value.trim().parse::<i64>() {
+                Ok(amount) if amount >= min => amount,
+                _ if min > 0 => return Err(format!("must be a positive number, not `{}`", value)),
+                _ => return Err(format!("must be a number, not `{}`", value)),
+            };
+
+            to_duration(amount)
+                .filter(|&duration| duration <= MAX_DURATION)
+                .ok_or_else(|| format!("must be at most 100 years, not `{}` {}", value, unit))
+        })
+    }
+}
+
+fn database(settings: &mut Settings) -> Option<DatabaseConfig> {
+    let url = settings.required("database.url");
+    let pool_size = settings.positive_or("database.pool_size", 10);
+    let run_migrations = settings.parse_or("database.run_migrations", "a boolean", false);
+
+    Some(DatabaseConfig {
+        url: url?,
+        pool_size: pool_size.try_into().unwrap_or(u32::MAX),
+        run_migrations,
+    })
+}
+
+fn mail(settings: &mut Settings) -> Option<MailConfig> {
+    let backend = match settings.get("mail.backend").unwrap_or("smtp") {
+        "smtp" => smtp(settings).map(MailBackend::Smtp),
+        "file" => settings
+            .required("mail.dir")
+            .map(|dir| MailBackend::File(dir.into())),
+        "stdout" => Some(MailBackend::Stdout),
+        other => {
+            let problem = format!("must be `smtp`, `file` or `stdout`, not `{}`", other);
+            settings.error("mail.backend", problem);
+            None
+        }
+    };
+
+    let from = settings
+        .required("mail.from")
+        .and_then(|_| settings.parse::<Mailbox>("mail.from", "an email address"));
+
+    let dkim = dkim(settings, from.as_ref());
+
+    Some(MailConfig {
+        backend: backend?,
+        from: from?,
+        dkim: dkim?,
+    })
+}
+
+fn smtp(settings: &mut Settings) -> Option<SmtpConfig> {
+    let host = settings.required("smtp.host");
+    let port = settings
+        .required("smtp.port")
+        .and_then(|_| settings.parse("smtp.port", "a port number"));
+    let user = settings.required("smtp.user");
+    let pass = settings.required("smtp.pass");
+
+    Some(SmtpConfig {
+        host: host?,
+        port: port?,
+        user: user?,
+        pass: pass?,
+    })
+}
+
+/// The DKIM signer, or `Some(None)` if DKIM isn't set up.
+fn dkim(settings: &mut Settings, from: Option<&Mailbox>) -> Option<Option<Dkim>> {
+    if !settings.is_set("dkim.selector") {
+        return Some(None);
+    }
+
+    let selector = settings.required("dkim.selector")?;
+    let key = settings.required("dkim.private_key_file").and_then(|_| {
+        settings.parse_with("dkim.private_key_file", |path| {
+            fs::read_to_string(path).map_err(|err| format!("can't be read: {}", err))
+        })
+    })?;
+    let domain = match settings.get("dkim.domain") {
+        Some(domain) => domain.to_string(),
+        None => from?.email.domain().to_string(),
+    };
+
+    match Dkim::new(selector, domain, &key) {
+        Ok(dkim) => Some(Some(dkim)),
+        Err(problem) => {
+            settings.error(
+                "dkim.private_key_file",
+                format!("isn't a valid key: {}", problem),
+            );
+            None
+        }
+    }
+}
+
+fn keys(settings: &mut Settings) -> Option<Keys> {
+    let keys = settings.required("encryption.keys");
+    let key_id = settings.required("encryption.key_id");
+    let index_key = settings.required("encryption.index_key");
+    let tombstone = settings.required("encryption.tombstone_key");
+
+    let email = match EmailKeys::parse(&keys?, &key_id?, &index_key?) {
+        Ok(email) => email,
+        Err(problem) => {
+            settings.error("encryption.keys", problem);
+            return None;
+        }
+    };
+
+    Some(Keys {
+        email,
+        tombstone: tombstone?.into_bytes(),
+    })
+}
+
+fn attestation(settings: &mut Settings) -> Option<AttestationConfig> {
+    let signing_key = settings.required("attestation.signing_key").and_then(|_| {
+        settings.parse_with("attestation.signing_key", |key| {
+            attestation::parse_signing_key(key)
+                .ok_or_else(|| "must be a base64, 32-byte Ed25519 seed".to_string())
+        })
+    });
+    let ttl = settings
+        .duration(
+            "attestation.ttl_minutes",
+            1,
+            "minutes",
+            TimeDelta::try_minutes,
+        )
+        .unwrap_or(TimeDelta::minutes(15));
+
+    Some(AttestationConfig {
+        signing_key: signing_key?,
+        ttl,
+    })
+}
+
+fn retention(settings: &mut Settings) -> Option<RetentionPolicy> {
+    let periods = [
+        ("retention.unverified_days", UserState::Unverified),
+        ("retention.querying_email_days", UserState::QueryingEmail),
+        ("retention.querying_otp_days", UserState::QueryingOTP),
+    ]
+    .into_iter()
+    .filter_map(|(key, state)| {
+        let period = settings.duration(key, 1, "days", TimeDelta::try_days)?;
+        Some((state, period))
+    })
+    .collect();
+
+    let action = match settings.get("retention.action") {
+        None | Some("anonymise") => Some(RetentionAction::Anonymise),
+        Some("delete") => Some(RetentionAction::Delete),
+        Some(other) => {
+            let problem = format!("must be `anonymise` or `delete`, not `{}`", other);
+            settings.error("retention.action", problem);
+            None
+        }
+    };
+    let dry_run = settings.parse_or("retention.dry_run", "a boolean", false);
+    let interval = settings
+        .duration("retention.interval_hours", 1, "hours", TimeDelta::try_hours)
+        .unwrap_or(TimeDelta::hours(24));
+
+    Some(RetentionPolicy {
+        periods,
+        action: action?,
+        dry_run,
+        interval: Duration::from_secs(interval.num_seconds() as u64),
+    })
+}
+
+fn http(settings: &mut Settings) -> Option<HttpConfig> {
+    let addr = settings.get("http.addr").map(str::to_string);
+    let metrics_addr = settings.get("http.metrics_addr").map(str::to_string);
+    if metrics_addr.is_some() && cfg!(not(feature = "metrics")) {
+        settings.error(
+            "http.metrics_addr",
+            "is set, but the bot was built without the `metrics` feature",
+        );
+    }
+
+    const DASHBOARD: [&str; 3] = [
+        "dashboard.client_id",
+        "dashboard.client_secret",
+        "dashboard.url",
+    ];
+    // The dashboard is optional, but half setting it up is a mistake.
+    let dashboard = if DASHBOARD.iter().any(|key| settings.is_set(key)) {
+        let client_id = settings.required("dashboard.client_id");
+        let client_secret = settings.required("dashboard.client_secret");
+        let url = settings.required("dashboard.url");
+
+        Some(DashboardConfig {
+            client_id: client_id?,
+            client_secret: client_secret?,
+            url: url?.trim_end_matches('/').to_string(),
+        })
+    } else {
+        None
+    };
+
+    Some(HttpConfig {
+        addr,
+        metrics_addr,
+        dashboard,
+    })
+}
+
+fn reloadable(settings: &mut Settings) -> Option<Reloadable> {
+    let template_dir = settings.get("mail.template_dir").map(PathBuf::from);
+    let templates = match VerificationTemplates::load(template_dir.as_deref()) {
+        Ok(templates) => Some(templates),
+        Err(errors) => {
+            for problem in errors.0 {
+                settings.error(
+                    "mail.template_dir",
+                    format!("has an invalid template: {}", problem),
+                );
+            }
+            None
+        }
+    };
+
+    let cooldown = settings
+        .duration(
+            "mail.test_email_cooldown_minutes",
+            0,
+            "minutes",
+            TimeDelta::try_minutes,
+        )
+        .unwrap_or(TimeDelta::minutes(10));
+
+    let allowed_domains = settings
+        .get("verification.allowed_domains")
+        .unwrap_or("imperial.ac.uk")
+        .split(',')
+        .map(|domain| domain.trim().trim_start_matches('@').to_string())
+        .collect::<Vec<_>>();
+    if allowed_domains.iter().any(|domain| domain.is_empty()) {
+        settings.error(
+            "verification.allowed_domains",
+            "must be a comma-separated list of domains",
+        );
+    }
+
+    Some(Reloadable {
+        templates: templates?,
+        test_email_cooldown: Duration::from_secs(cooldown.num_seconds() as u64),
+        allowed_domains,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A complete config, with throwaway keys.
+    pub const CONFIG: &str = r#"
+        [discord]
+        token = "token"
+
+        [database]
+        url = "postgres://localhost/test"
+
+        [mail]
+        backend = "stdout"
+        from = "Imperial Bot <bot@example.com>"
+
+        [encryption]
+        keys = "test:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=, old:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        key_id = "test"
+        index_key = "test"
+        tombstone_key = "test"
+
+        [attestation]
+        signing_key = "AQIDBAUGBwgJCgsMDQ4PEBESExQVFhcYGRobHB0eHyA="
+    "#;
+
+    #[test]
+    fn environment_variables_override_the_file() {
+        let config = Config::parse(CONFIG, |var| match var {
+            "DATABASE_POOL_SIZE" => Some("3".to_string()),
+            "SMTP_FROM" => Some("other@example.com".to_string()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(config.database.pool_size, 3);
+        assert_eq!(config.mail.from.email.to_string(), "other@example.com");
+        assert_eq!(config.attestation.ttl, TimeDelta::minutes(15));
+        assert!(config.http.dashboard.is_none());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let file = CONFIG.replace("backend = \"stdout\"", "backend = \"smtp\"")
+            + "\n[dashboard]\nurl = \"https://bot.example.com\"\n[typo]\nsetting = 1\n";
+
+        let errors = Config::parse(&file, |var| match var {
+            "RETENTION_INTERVAL_HOURS" => Some("0".to_string()),
+            "EMAIL_ENCRYPTION_KEY_ID" => Some("missing".to_string()),
+            _ => None,
+        })
+        .err()
+        .expect("the config should be rejected");
+
+        assert_eq!(
+            errors.0,
+            vec![
+                "`typo.setting` isn't a setting",
+                "`smtp.host` (SMTP_HOST) must be set",
+                "`smtp.port` (SMTP_PORT) must be set",
+                "`smtp.user` (SMTP_USER) must be set",
+                "`smtp.pass` (SMTP_PASS) must be set",
+                "`encryption.keys` (EMAIL_ENCRYPTION_KEYS) must include the active key, `missing` (EMAIL_ENCRYPTION_KEY_ID)",
+                "`retention.interval_hours` (RETENTION_INTERVAL_HOURS) must be a positive number, not `0`",
+                "`dashboard.client_id` (DISCORD_CLIENT_ID) must be set",
+                "`dashboard.client_secret` (DISCORD_CLIENT_SECRET) must be set",
+            ]
+        );
+    }
+
+    #[test]
+    fn durations_must_be_in_range() {
+        let errors = Config::parse(CONFIG, |var| match var {
+            "RETENTION_UNVERIFIED_DAYS" => Some("0".to_string()),
+            "RETENTION_QUERYING_EMAIL_DAYS" => Some("-30".to_string()),
+            "RETENTION_QUERYING_OTP_DAYS" => Some("9223372036854775807".to_string()),
+            "RETENTION_INTERVAL_HOURS" => Some("1000000000000000".to_string()),
+            "ATTESTATION_TTL_MINUTES" => Some("100000000000".to_string()),
+            "TEST_EMAIL_COOLDOWN_MINUTES" => Some("-1".to_string()),
+            _ => None,
+        })
+        .err()
+        .expect("the config should be rejected");
+
+        assert_eq!(
+            errors.0,
+            vec![
+                "`attestation.ttl_minutes` (ATTESTATION_TTL_MINUTES) must be at most 100 years, not `100000000000` minutes",
+                "`retention.unverified_days` (RETENTION_UNVERIFIED_DAYS) must be a positive number, not `0`",
+                "`retention.querying_email_days` (RETENTION_QUERYING_EMAIL_DAYS) must be a positive number, not `-30`",
+                "`retention.querying_otp_days` (RETENTION_QUERYING_OTP_DAYS) must be at most 100 years, not `9223372036854775807` days",
+                "`retention.interval_hours` (RETENTION_INTERVAL_HOURS) must be at most 100 years, not `1000000000000000` hours",
+                "`mail.test_email_cooldown_minutes` (TEST_EMAIL_COOLDOWN_MINUTES) must be a number, not `-1`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reloading_only_swaps_the_reloadable_settings() {
+        let config = Config::parse(CONFIG, |_| None).unwrap();
+        assert_eq!(config.reloadable().allowed_domains, vec!["imperial.ac.uk"]);
+
+        let new = Config::parse(CONFIG, |var| match var {
+            "ALLOWED_EMAIL_DOMAINS" => Some("imperial.ac.uk, @ic.ac.uk".to_string()),
+            "TEST_EMAIL_COOLDOWN_MINUTES" => Some("1".to_string()),
+            "DATABASE_POOL_SIZE" => Some("3".to_string()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(config.update(new), vec!["database.pool_size"]);
+        assert_eq!(
+            config.reloadable().allowed_domains,
+            vec!["imperial.ac.uk", "ic.ac.uk"]
+        );
+        assert_eq!(
+            config.reloadable().test_email_cooldown,
+            Duration::from_secs(60)
+        );
+        assert_eq!(config.database.pool_size, 10);
+    }
+}
@@ -0,0 +1,111 @@
+use super::models::*;
+use super::{schema, Database};
+use crate::errors::Result;
+use base64::prelude::*;
+use chrono::Utc;
+use diesel::prelude::*;
+use poise::serenity_prelude as serenity;
+use rand::RngCore;
+use serenity::{GuildId, UserId};
+use sha2::{Digest, Sha256};
+
+/// The prefix of every API token, to make them easy to recognise.
+const TOKEN_PREFIX: &str = "ib_";
+
+/// Tokens are random, so a plain hash is enough to store them safely.
+pub(super) fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Generate a new random token.
+pub(super) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    format!("{}{}", TOKEN_PREFIX, BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Create a new API token for a server, returning the token. Only its hash is stored, so this is
+/// the only time the token can be seen.
+pub async fn create_api_token(
+    db: &Database,
+    server_id: GuildId,
+    token_name: &str,
+    creator: UserId,
+) -> Result<String> {
+    let token = generate_token();
+
+    let new_token = NewApiToken {
+        guild_id: i64::from(server_id),
+        name: token_name.to_string(),
+        token_hash: hash_token(&token),
+        created_by: Some(i64::from(creator)),
+    };
+
+    db.run(move |conn| {
+        diesel::insert_into(schema::api_tokens::table)
+            .values(&new_token)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await?;
+
+    Ok(token)
+}
+
+/// Get the API tokens of a server that haven't been revoked.
+pub async fn get_api_tokens(db: &Database, server_id: GuildId) -> Result<Vec<ApiToken>> {
+    db.run(move |conn| {
+        use schema::api_tokens::dsl::*;
+
+        let res = api_tokens
+            .filter(guild_id.eq(i64::from(server_id)).and(revoked_at.is_null()))
+            .order(id)
+            .load(conn)?;
+
+        Ok(res)
+    })
+    .await
+}
+
+/// Revoke one of a server's API tokens. Returns whether there was a token to revoke.
+pub async fn revoke_api_token(db: &Database, server_id: GuildId, token_id: i64) -> Result<bool> {
+    db.run(move |conn| {
+        use schema::api_tokens::dsl::*;
+
+        let res = diesel::update(
+            api_tokens.filter(
+                id.eq(token_id)
+                    .and(guild_id.eq(i64::from(server_id)))
+                    .and(revoked_at.is_null()),
+            ),
+        )
+        .set(revoked_at.eq(Some(Utc::now())))
+        .execute(conn)?;
+
+        Ok(res > 0)
+    })
+    .await
+}
+
+/// Get the server an API token belongs to, if the token is valid.
+pub async fn get_api_token_server(db: &Database, token: &str) -> Result<Option<GuildId>> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+    let hash = hash_token(token);
+
+    db.run(move |conn| {
+        use schema::api_tokens::dsl::*;
+
+        let res = api_tokens
+            .filter(token_hash.eq(hash).and(revoked_at.is_null()))
+            .select(guild_id)
+            .first::<i64>(conn)
+            .optional()?;
+
+        Ok(res.map(|server_id| GuildId::new(server_id as u64)))
+    })
+    .await
+}
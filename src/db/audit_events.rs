@@ -0,0 +1,184 @@
+use super::models::*;
+use super::users::get_user;
+use super::{schema, Database};
+use crate::errors::Result;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use poise::serenity_prelude as serenity;
+use serenity::{GuildId, UserId};
+
+/// Get a page of the audit trail for a user, newest first, along with the total number of
+/// events. Only events that happened in `server_id`, or that didn't happen in any server (i.e. in
+/// DMs), are returned.
+pub async fn get_audit_events_for_user(
+    db: &Database,
+    user_id: UserId,
+    server_id: GuildId,
+    page: i64,
+    page_size: i64,
+) -> Result<(Vec<AuditEvent>, i64)> {
+    use schema::audit_events::dsl::*;
+
+    db.run(move |conn| {
+        let visible = subject_id
+            .eq(i64::from(user_id))
+            .and(guild_id.eq(i64::from(server_id)).or(guild_id.is_null()));
+
+        let total = audit_events.filter(visible).count().get_result(conn)?;
+
+        let res = audit_events
+            .filter(visible)
+            .order((created_at.desc(), id.desc()))
+            .limit(page_size)
+            .offset(page * page_size)
+            .load(conn)?;
+
+        Ok((res, total))
+    })
+    .await
+}
+
+/// Get a page of the audit trail for a whole server, newest first, along with the total number
+/// of events. As well as the events that happened in `server_id`, this includes events of its
+/// members (`member_ids`) that didn't happen in any server.
+pub async fn get_audit_events_for_server(
+    db: &Database,
+    server_id: GuildId,
+    member_ids: &[i64],
+    page: i64,
+    page_size: i64,
+) -> Result<(Vec<AuditEvent>, i64)> {
+    use schema::audit_events::dsl::*;
+
+    let member_ids = member_ids.to_vec();
+
+    db.run(move |conn| {
+        let visible = guild_id
+            .eq(i64::from(server_id))
+            .or(guild_id.is_null().and(subject_id.eq_any(member_ids)));
+
+        let total = audit_events
+            .filter(visible.clone())
+            .count()
+            .get_result(conn)?;
+
+        let res = audit_events
+            .filter(visible)
+            .order((created_at.desc(), id.desc()))
+            .limit(page_size)
+            .offset(page * page_size)
+            .load(conn)?;
+
+        Ok((res, total))
+    })
+    .await
+}
+
+/// Record that a moderator looked up a user's email with `/whois`, and why. This isn't a state
+/// transition, so the event only has the state the user was in.
+pub async fn record_whois_lookup(
+    db: &Database,
+    moderator: UserId,
+    user_id: UserId,
+    server_id: GuildId,
+    lookup_reason: &str,
+) -> Result<()> {
+    let user_state = get_user(db, user_id)
+        .await?
+        .map_or(UserState::Unverified, |user| user.state);
+
+    let event = NewAuditEvent {
+        actor_id: Some(i64::from(moderator)),
+        subject_id: i64::from(user_id),
+        guild_id: Some(i64::from(server_id)),
+        old_state: Some(user_state),
+        new_state: None,
+        method: AuditMethod::WhoisLookup,
+        reason: Some(lookup_reason.to_string()),
+    };
+
+    db.run(move |conn| {
+        diesel::insert_into(schema::audit_events::table)
+            .values(&event)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Record that a user's email or passcode was rejected, for `/stats`. Like lookups, this isn't a
+/// state transition.
+pub async fn record_rejection(
+    db: &Database,
+    user_id: UserId,
+    rejection: AuditMethod,
+) -> Result<()> {
+    let user_state = get_user(db, user_id)
+        .await?
+        .map_or(UserState::Unverified, |user| user.state);
+
+    let event = NewAuditEvent {
+        actor_id: Some(i64::from(user_id)),
+        subject_id: i64::from(user_id),
+        guild_id: None,
+        old_state: Some(user_state),
+        new_state: None,
+        method: rejection,
+        reason: None,
+    };
+
+    db.run(move |conn| {
+        diesel::insert_into(schema::audit_events::table)
+            .values(&event)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Get when a user was last verified, if they ever were. Setting a verified user to verified again
+/// (e.g. from the command line) doesn't count.
+pub async fn get_verified_at(db: &Database, user_id: UserId) -> Result<Option<DateTime<Utc>>> {
+    db.run(move |conn| {
+        use schema::audit_events::dsl::*;
+
+        let res = audit_events
+            .filter(
+                subject_id
+                    .eq(i64::from(user_id))
+                    .and(new_state.eq(UserState::Verified))
+                    .and(old_state.is_null().or(old_state.ne(UserState::Verified))),
+            )
+            .select(created_at)
+            .order(created_at.desc())
+            .first(conn)
+            .optional()?;
+
+        Ok(res)
+    })
+    .await
+}
+
+/// Get the server a user last started verifying in, if they started in one.
+pub async fn get_verifying_server(db: &Database, user_id: UserId) -> Result<Option<GuildId>> {
+    db.run(move |conn| {
+        use schema::audit_events::dsl::*;
+
+        let res = audit_events
+            .filter(
+                subject_id
+                    .eq(i64::from(user_id))
+                    .and(new_state.eq(UserState::QueryingEmail))
+                    .and(guild_id.is_not_null()),
+            )
+            .select(guild_id)
+            .order((created_at.desc(), id.desc()))
+            .first::<Option<i64>>(conn)
+            .optional()?;
+
+        Ok(res.flatten().map(|server| GuildId::new(server as u64)))
+    })
+    .await
+}
@@ -0,0 +1,91 @@
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ManageConnection, R2D2Connection};
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+
+/// A connection to whichever database `DATABASE_URL` points at. Queries that every database
+/// supports can be run on it directly, anything else has to go through [`each_backend!`].
+#[derive(diesel::MultiConnection)]
+pub enum AnyConnection {
+    Postgresql(PgConnection),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteConnection),
+}
+
+/// Run the same code on the inner connection, whichever backend it is. This is for queries that
+/// can be written the same way for each backend, but that `AnyConnection` can't build itself
+/// (like `ON CONFLICT`).
+macro_rules! each_backend {
+    ($conn:expr, |$inner:ident| $body:expr) => {
+        match $conn {
+            $crate::db::backend::AnyConnection::Postgresql($inner) => $body,
+            #[cfg(feature = "sqlite")]
+            $crate::db::backend::AnyConnection::Sqlite($inner) => $body,
+        }
+    };
+}
+pub(crate) use each_backend;
+
+/// The SQLite file a `sqlite://` URL points at, if it is one.
+pub fn sqlite_path(url: &str) -> Option<&str> {
+    url.strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))
+}
+
+/// Opens the pool's connections, to the kind of database named by the URL's scheme. (diesel's
+/// own `AnyConnection::establish` tries each kind in turn instead, so a postgres server that is
+/// down would end up with a SQLite file named after its URL.)
+pub struct ConnectionManager {
+    url: String,
+}
+
+impl ConnectionManager {
+    pub fn new(url: &str) -> ConnectionManager {
+        ConnectionManager {
+            url: url.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn establish_sqlite(path: &str) -> ConnectionResult<AnyConnection> {
+    let mut conn = SqliteConnection::establish(path)?;
+
+    // Wait for other connections to finish writing rather than failing straight away, and let
+    // them read while another writes.
+    conn.batch_execute(
+        "PRAGMA busy_timeout = 5000; PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;",
+    )
+    .map_err(ConnectionError::CouldntSetupConfiguration)?;
+
+    Ok(AnyConnection::Sqlite(conn))
+}
+
+impl ManageConnection for ConnectionManager {
+    type Connection = AnyConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<AnyConnection, r2d2::Error> {
+        let conn = match sqlite_path(&self.url) {
+            #[cfg(feature = "sqlite")]
+            Some(path) => establish_sqlite(path),
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => Err(ConnectionError::InvalidConnectionUrl(
+                "SQLite databases need the bot to be built with the `sqlite` feature".to_string(),
+            )),
+            None => PgConnection::establish(&self.url).map(AnyConnection::Postgresql),
+        };
+
+        conn.map_err(r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut AnyConnection) -> Result<(), r2d2::Error> {
+        conn.ping().map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut AnyConnection) -> bool {
+        std::thread::panicking() || conn.is_broken()
+    }
+}
@@ -0,0 +1,180 @@
+use super::models::User;
+use crate::config;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+/// Result type for encrypting and decrypting emails.
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Error encrypting or decrypting an email, e.g. because it was encrypted with an unknown key.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct EncryptionError(String);
+
+/// The length of the nonce prepended to every ciphertext.
+const NONCE_LEN: usize = 12;
+
+/// The keys used to encrypt and index stored emails.
+pub struct EmailKeys {
+    /// The ID of the key new emails are encrypted with.
+    active_id: String,
+    /// All known encryption keys by ID, so that emails encrypted with old keys can be decrypted.
+    ciphers: HashMap<String, Aes256Gcm>,
+    /// The key for the blind index, used to look up emails without decrypting them.
+    index_key: Vec<u8>,
+}
+
+impl EmailKeys {
+    /// Parse the keys from a list of `id:base64-key` pairs, the ID of the active key, and the
+    /// index key.
+    pub fn parse(
+        keys: &str,
+        active_id: &str,
+        index_key: &str,
+    ) -> std::result::Result<Self, String> {
+        let ciphers = keys
+            .split(',')
+            .map(|entry| {
+                let (id, key) = entry
+                    .trim()
+                    .split_once(':')
+                    .ok_or("must be a list of `id:base64-key` pairs")?;
+                let key = BASE64_STANDARD
+                    .decode(key)
+                    .map_err(|_| format!("key `{}` must be base64", id))?;
+                let cipher = Aes256Gcm::new_from_slice(&key)
+                    .map_err(|_| format!("key `{}` must be 32 bytes long", id))?;
+
+                Ok((id.to_string(), cipher))
+            })
+            .collect::<std::result::Result<HashMap<_, _>, String>>()?;
+
+        if !ciphers.contains_key(active_id) {
+            return Err(format!(
+                "must include the active key, `{}` (EMAIL_ENCRYPTION_KEY_ID)",
+                active_id
+            ));
+        }
+
+        Ok(Self {
+            active_id: active_id.to_string(),
+            ciphers,
+            index_key: index_key.as_bytes().to_vec(),
+        })
+    }
+}
+
+/// The keys in the config.
+fn keys() -> &'static EmailKeys {
+    &config::get().keys.email
+}
+
+/// An email, encrypted for storing in the database.
+pub struct EncryptedEmail {
+    /// The nonce followed by the ciphertext.
+    pub ciphertext: Vec<u8>,
+    /// The ID of the key the email was encrypted with.
+    pub key_id: String,
+    /// The blind index of the email.
+    pub index: Vec<u8>,
+}
+
+/// The ID of the key that new emails are encrypted with.
+pub fn active_key_id() -> &'static str {
+    &keys().active_id
+}
+
+/// Encrypt a user's email with the active key. The ciphertext is bound to the user's ID, so it
+/// can't be moved to another user.
+pub fn encrypt_email(user_id: i64, email: &str) -> Result<EncryptedEmail> {
+    Ok(EncryptedEmail {
+        ciphertext: encrypt(user_id, email.as_bytes())?,
+        key_id: keys().active_id.clone(),
+        index: email_index(email),
+    })
+}
+
+/// Encrypt a message to a user with the active key, returning the ciphertext and the key's ID.
+/// Like emails, the ciphertext is bound to the user's ID.
+pub fn encrypt_message(user_id: i64, message: &[u8]) -> Result<(Vec<u8>, String)> {
+    Ok((encrypt(user_id, message)?, keys().active_id.clone()))
+}
+
+/// Encrypt with the active key, prepending the nonce to the ciphertext.
+fn encrypt(user_id: i64, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let keys = keys();
+    let cipher = &keys.ciphers[&keys.active_id];
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let mut ciphertext = nonce.to_vec();
+    ciphertext.extend(
+        cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &user_id.to_be_bytes(),
+                },
+            )
+            .map_err(|_| EncryptionError("could not encrypt".to_string()))?,
+    );
+
+    Ok(ciphertext)
+}
+
+/// Decrypt a user's email.
+pub fn decrypt_email(user_id: i64, ciphertext: &[u8], key_id: &str) -> Result<String> {
+    let plaintext = decrypt(user_id, ciphertext, key_id)?;
+
+    String::from_utf8(plaintext).map_err(|err| EncryptionError(err.to_string()))
+}
+
+/// Decrypt a message to a user.
+pub fn decrypt_message(user_id: i64, ciphertext: &[u8], key_id: &str) -> Result<Vec<u8>> {
+    decrypt(user_id, ciphertext, key_id)
+}
+
+/// Decrypt a ciphertext made by `encrypt`.
+fn decrypt(user_id: i64, ciphertext: &[u8], key_id: &str) -> Result<Vec<u8>> {
+    let cipher = keys()
+        .ciphers
+        .get(key_id)
+        .ok_or_else(|| EncryptionError(format!("unknown key ID `{}`", key_id)))?;
+
+    if ciphertext.len() < NONCE_LEN {
+        return Err(EncryptionError("ciphertext is too short".to_string()));
+    }
+    let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &user_id.to_be_bytes(),
+            },
+        )
+        .map_err(|_| EncryptionError(format!("could not decrypt data of user {}", user_id)))
+}
+
+/// Decrypt the email stored for a user, if they have one. Falls back to the plaintext email for
+/// users stored before emails were encrypted.
+pub fn decrypt_user_email(user: &User) -> Result<Option<String>> {
+    match (&user.imperial_email_ciphertext, &user.imperial_email_key_id) {
+        (Some(ciphertext), Some(key_id)) => decrypt_email(user.id, ciphertext, key_id).map(Some),
+        _ => Ok(user.imperial_email.clone()),
+    }
+}
+
+/// The blind index of an email: a keyed hash that lets equal emails be found without decrypting
+/// anything. Emails are compared case-insensitively.
+pub fn email_index(email: &str) -> Vec<u8> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&keys().index_key).expect("HMAC accepts any key");
+    mac.update(email.trim().to_lowercase().as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
@@ -0,0 +1,361 @@
+use super::backend::AnyConnection;
+use super::models::{AuditMethod, OutboxStatus, UserState};
+use super::{schema, Database};
+use crate::errors::Result;
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+use serde::{Deserialize, Serialize};
+
+/// The version of the export format, bumped whenever it changes incompatibly.
+const EXPORT_VERSION: u32 = 3;
+
+/// How many rows are inserted at once when importing into postgres.
+const IMPORT_CHUNK_SIZE: usize = 1000;
+
+/// A full copy of the database, for backups and moving between databases. Emails (including the
+/// messages in the outbox) stay encrypted, so the same keys are needed to use an import.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u32,
+    pub users: Vec<UserRecord>,
+    pub servers: Vec<ServerRecord>,
+    pub audit_events: Vec<AuditEventRecord>,
+    pub tombstones: Vec<TombstoneRecord>,
+    pub api_tokens: Vec<ApiTokenRecord>,
+    /// Missing from exports before version 3.
+    #[serde(default)]
+    pub outbox: Vec<OutboxRecord>,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::users)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct UserRecord {
+    pub id: i64,
+    pub imperial_email: Option<String>,
+    pub state: UserState,
+    pub otps: Vec<Option<i32>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub imperial_email_ciphertext: Option<Vec<u8>>,
+    pub imperial_email_key_id: Option<String>,
+    pub imperial_email_index: Option<Vec<u8>>,
+    /// Missing from exports made before it was added.
+    #[serde(default)]
+    pub otp_sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::servers)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct ServerRecord {
+    pub id: i64,
+    pub verified_role_id: Option<i64>,
+    pub log_channel_id: Option<i64>,
+    pub show_emails: bool,
+    pub whois_role_id: Option<i64>,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::audit_events)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct AuditEventRecord {
+    pub id: i64,
+    pub actor_id: Option<i64>,
+    pub subject_id: i64,
+    pub guild_id: Option<i64>,
+    pub old_state: Option<UserState>,
+    pub new_state: Option<UserState>,
+    pub method: AuditMethod,
+    pub created_at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::tombstones)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct TombstoneRecord {
+    pub id: i64,
+    pub discord_id_hash: Vec<u8>,
+    pub email_hash: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::api_tokens)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct ApiTokenRecord {
+    pub id: i64,
+    pub guild_id: i64,
+    pub name: String,
+    pub token_hash: Vec<u8>,
+    pub created_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize)]
+#[diesel(table_name = schema::outbox)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct OutboxRecord {
+    pub id: i64,
+    pub user_id: i64,
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub message_ciphertext: Option<Vec<u8>>,
+    pub message_key_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// How many rows of each table were imported.
+#[derive(Debug, Default)]
+pub struct ImportCounts {
+    pub users: usize,
+    pub servers: usize,
+    pub audit_events: usize,
+    pub tombstones: usize,
+    pub api_tokens: usize,
+    pub outbox: usize,
+}
+
+/// Export the whole database.
+pub async fn export_all(db: &Database) -> Result<Export> {
+    db.run(move |conn| {
+        let export = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            Ok(Export {
+                version: EXPORT_VERSION,
+                users: schema::users::table
+                    .order(schema::users::id)
+                    .select(UserRecord::as_select())
+                    .load(conn)?,
+                servers: schema::servers::table
+                    .order(schema::servers::id)
+                    .select(ServerRecord::as_select())
+                    .load(conn)?,
+                audit_events: schema::audit_events::table
+                    .order(schema::audit_events::id)
+                    .select(AuditEventRecord::as_select())
+                    .load(conn)?,
+                tombstones: schema::tombstones::table
+                    .order(schema::tombstones::id)
+                    .select(TombstoneRecord::as_select())
+                    .load(conn)?,
+                api_tokens: schema::api_tokens::table
+                    .order(schema::api_tokens::id)
+                    .select(ApiTokenRecord::as_select())
+                    .load(conn)?,
+                outbox: schema::outbox::table
+                    .order(schema::outbox::id)
+                    .select(OutboxRecord::as_select())
+                    .load(conn)?,
+            })
+        })?;
+
+        Ok(export)
+    })
+    .await
+}
+
+/// Import an export, in one transaction. Rows that already exist are skipped, so importing the
+/// same export twice is harmless.
+pub async fn import_all(db: &Database, export: Export) -> Result<ImportCounts> {
+    let export = upgrade(export);
+
+    db.run(move |conn| {
+        let counts = conn.transaction::<_, diesel::result::Error, _>(|conn| match conn {
+            AnyConnection::Postgresql(conn) => import_postgres(conn, &export),
+            #[cfg(feature = "sqlite")]
+            AnyConnection::Sqlite(conn) => import_sqlite(conn, &export),
+        })?;
+
+        Ok(counts)
+    })
+    .await
+}
+
+fn import_postgres(conn: &mut PgConnection, export: &Export) -> QueryResult<ImportCounts> {
+    let mut counts = ImportCounts::default();
+
+    // Postgres limits how many values one statement can have, so insert in chunks.
+    for chunk in export.users.chunks(IMPORT_CHUNK_SIZE) {
+        counts.users += diesel::insert_into(schema::users::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in export.servers.chunks(IMPORT_CHUNK_SIZE) {
+        counts.servers += diesel::insert_into(schema::servers::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in export.audit_events.chunks(IMPORT_CHUNK_SIZE) {
+        counts.audit_events += diesel::insert_into(schema::audit_events::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in export.tombstones.chunks(IMPORT_CHUNK_SIZE) {
+        counts.tombstones += diesel::insert_into(schema::tombstones::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in export.api_tokens.chunks(IMPORT_CHUNK_SIZE) {
+        counts.api_tokens += diesel::insert_into(schema::api_tokens::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in export.outbox.chunks(IMPORT_CHUNK_SIZE) {
+        counts.outbox += diesel::insert_into(schema::outbox::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+
+    // IDs were inserted explicitly, so move the sequences past them.
+    for table in ["audit_events", "tombstones", "api_tokens", "outbox"] {
+        diesel::sql_query(format!(
+            "SELECT setval(pg_get_serial_sequence('{table}', 'id'), \
+                COALESCE((SELECT MAX(id) FROM {table}), 0) + 1, false)"
+        ))
+        .execute(conn)?;
+    }
+
+    Ok(counts)
+}
+
+/// diesel can only build inserts of the schema's (postgres') column types for postgres, so
+/// SQLite's are written out, binding each value as the type SQLite stores it as.
+#[cfg(feature = "sqlite")]
+fn import_sqlite(conn: &mut SqliteConnection, export: &Export) -> QueryResult<ImportCounts> {
+    use super::schema::sql_types;
+    use super::types::Otps;
+    use diesel::sql_types::{BigInt, Binary, Bool, Integer, Nullable, Text, TimestamptzSqlite};
+
+    let mut counts = ImportCounts::default();
+
+    for user in &export.users {
+        counts.users += diesel::sql_query(
+            "INSERT OR IGNORE INTO users (id, imperial_email, state, otps, created_at, updated_at, \
+                imperial_email_ciphertext, imperial_email_key_id, imperial_email_index, \
+                otp_sent_at) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind::<BigInt, _>(user.id)
+        .bind::<Nullable<Text>, _>(&user.imperial_email)
+        .bind::<sql_types::UserState, _>(user.state)
+        .bind::<Otps, _>(&user.otps)
+        .bind::<TimestamptzSqlite, _>(user.created_at)
+        .bind::<TimestamptzSqlite, _>(user.updated_at)
+        .bind::<Nullable<Binary>, _>(&user.imperial_email_ciphertext)
+        .bind::<Nullable<Text>, _>(&user.imperial_email_key_id)
+        .bind::<Nullable<Binary>, _>(&user.imperial_email_index)
+        .bind::<Nullable<TimestamptzSqlite>, _>(user.otp_sent_at)
+        .execute(conn)?;
+    }
+    for server in &export.servers {
+        counts.servers += diesel::sql_query(
+            "INSERT OR IGNORE INTO servers (id, verified_role_id, log_channel_id, show_emails, \
+                whois_role_id) \
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind::<BigInt, _>(server.id)
+        .bind::<Nullable<BigInt>, _>(server.verified_role_id)
+        .bind::<Nullable<BigInt>, _>(server.log_channel_id)
+        .bind::<Bool, _>(server.show_emails)
+        .bind::<Nullable<BigInt>, _>(server.whois_role_id)
+        .execute(conn)?;
+    }
+    for event in &export.audit_events {
+        counts.audit_events += diesel::sql_query(
+            "INSERT OR IGNORE INTO audit_events (id, actor_id, subject_id, guild_id, old_state, \
+                new_state, method, created_at, reason) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind::<BigInt, _>(event.id)
+        .bind::<Nullable<BigInt>, _>(event.actor_id)
+        .bind::<BigInt, _>(event.subject_id)
+        .bind::<Nullable<BigInt>, _>(event.guild_id)
+        .bind::<Nullable<sql_types::UserState>, _>(event.old_state)
+        .bind::<Nullable<sql_types::UserState>, _>(event.new_state)
+        .bind::<sql_types::AuditMethod, _>(event.method)
+        .bind::<TimestamptzSqlite, _>(event.created_at)
+        .bind::<Nullable<Text>, _>(&event.reason)
+        .execute(conn)?;
+    }
+    for tombstone in &export.tombstones {
+        counts.tombstones += diesel::sql_query(
+            "INSERT OR IGNORE INTO tombstones (id, discord_id_hash, email_hash, created_at) \
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind::<BigInt, _>(tombstone.id)
+        .bind::<Binary, _>(&tombstone.discord_id_hash)
+        .bind::<Nullable<Binary>, _>(&tombstone.email_hash)
+        .bind::<TimestamptzSqlite, _>(tombstone.created_at)
+        .execute(conn)?;
+    }
+    for token in &export.api_tokens {
+        counts.api_tokens += diesel::sql_query(
+            "INSERT OR IGNORE INTO api_tokens (id, guild_id, name, token_hash, created_by, \
+                created_at, revoked_at) \
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind::<BigInt, _>(token.id)
+        .bind::<BigInt, _>(token.guild_id)
+        .bind::<Text, _>(&token.name)
+        .bind::<Binary, _>(&token.token_hash)
+        .bind::<Nullable<BigInt>, _>(token.created_by)
+        .bind::<TimestamptzSqlite, _>(token.created_at)
+        .bind::<Nullable<TimestamptzSqlite>, _>(token.revoked_at)
+        .execute(conn)?;
+    }
+    for email in &export.outbox {
+        counts.outbox += diesel::sql_query(
+            "INSERT OR IGNORE INTO outbox (id, user_id, status, attempts, next_attempt_at, \
+                last_error, message_ciphertext, message_key_id, created_at, updated_at) \
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind::<BigInt, _>(email.id)
+        .bind::<BigInt, _>(email.user_id)
+        .bind::<sql_types::OutboxStatus, _>(email.status)
+        .bind::<Integer, _>(email.attempts)
+        .bind::<TimestamptzSqlite, _>(email.next_attempt_at)
+        .bind::<Nullable<Text>, _>(&email.last_error)
+        .bind::<Nullable<Binary>, _>(&email.message_ciphertext)
+        .bind::<Nullable<Text>, _>(&email.message_key_id)
+        .bind::<TimestamptzSqlite, _>(email.created_at)
+        .bind::<TimestamptzSqlite, _>(email.updated_at)
+        .execute(conn)?;
+    }
+
+    Ok(counts)
+}
+
+/// Bring an export in an older format up to date.
+fn upgrade(mut export: Export) -> Export {
+    // Version 1 recorded events that aren't state transitions (lookups and rejections) as
+    // transitions to the same state.
+    if export.version == 1 {
+        for event in &mut export.audit_events {
+            if !event.method.is_transition() {
+                event.new_state = None;
+            }
+        }
+    }
+
+    export.version = EXPORT_VERSION;
+    export
+}
+
+/// Whether an export is in a format this version can import.
+pub fn export_is_supported(export: &Export) -> bool {
+    (1..=EXPORT_VERSION).contains(&export.version)
+}
@@ -0,0 +1,623 @@
+use super::api_tokens::{generate_token, hash_token};
+use super::models::*;
+use super::repository::{ServerRepository, UserRepository};
+use super::stats::{StatsPeriod, VerificationStats};
+use super::user_data::UserData;
+use crate::errors::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeDelta, Utc};
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId, RoleId, UserId};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Users and servers kept in memory rather than in the database, so that code using the
+/// repositories can be tested without one. Emails are stored unencrypted, tombstones keep the
+/// email itself rather than a hash, and there is no outbox.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    users: Mutex<HashMap<i64, User>>,
+    servers: Mutex<HashMap<i64, Server>>,
+    audit_events: Mutex<Vec<AuditEvent>>,
+    tombstoned_emails: Mutex<HashSet<String>>,
+    api_tokens: Mutex<Vec<ApiToken>>,
+}
+
+impl InMemoryRepository {
+    /// Get a user, if they exist.
+    pub fn get_user(&self, user_id: UserId) -> Option<User> {
+        self.users.lock().unwrap().get(&i64::from(user_id)).cloned()
+    }
+
+    /// Get the audit trail of a user, oldest first.
+    pub fn audit_events_for(&self, user_id: UserId) -> Vec<AuditEvent> {
+        self.audit_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.subject_id == i64::from(user_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Add an event to the audit trail.
+    fn record(&self, event: NewAuditEvent) {
+        let mut audit_events = self.audit_events.lock().unwrap();
+        let id = audit_events.len() as i64 + 1;
+
+        audit_events.push(AuditEvent {
+            id,
+            actor_id: event.actor_id,
+            subject_id: event.subject_id,
+            guild_id: event.guild_id,
+            old_state: event.old_state,
+            new_state: event.new_state,
+            method: event.method,
+            created_at: Utc::now(),
+            reason: event.reason,
+        });
+    }
+
+    /// Record an event that isn't a state transition, with the state the user is in.
+    fn record_non_transition(
+        &self,
+        actor: UserId,
+        user_id: UserId,
+        guild_id: Option<GuildId>,
+        method: AuditMethod,
+        reason: Option<String>,
+    ) {
+        let user_state = self
+            .get_user(user_id)
+            .map_or(UserState::Unverified, |user| user.state);
+
+        self.record(NewAuditEvent {
+            actor_id: Some(i64::from(actor)),
+            subject_id: i64::from(user_id),
+            guild_id: guild_id.map(i64::from),
+            old_state: Some(user_state),
+            new_state: None,
+            method,
+            reason,
+        });
+    }
+
+    /// Get the servers matching `filter`.
+    fn servers_where(&self, filter: impl Fn(&Server) -> bool) -> Vec<Server> {
+        self.servers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|server| filter(server))
+            .cloned()
+            .collect()
+    }
+
+    /// Pretend a user's OTPs were sent `ago`.
+    pub fn backdate_otps(&self, user_id: UserId, ago: TimeDelta) {
+        if let Some(user) = self.users.lock().unwrap().get_mut(&i64::from(user_id)) {
+            user.otp_sent_at = user.otp_sent_at.map(|sent_at| sent_at - ago);
+        }
+    }
+
+    /// Update a user, failing with `NotFound` like the database if they don't exist.
+    fn update_user(&self, user_id: UserId, update: impl FnOnce(&mut User)) -> Result<()> {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .get_mut(&i64::from(user_id))
+            .ok_or(diesel::result::Error::NotFound)?;
+
+        update(user);
+        user.updated_at = Utc::now();
+
+        Ok(())
+    }
+
+    /// Update a server, creating it first if it doesn't exist yet.
+    fn update_server(&self, guild_id: GuildId, update: impl FnOnce(&mut Server)) {
+        let mut servers = self.servers.lock().unwrap();
+        let server = servers
+            .entry(i64::from(guild_id))
+            .or_insert_with(|| Server {
+                id: i64::from(guild_id),
+                verified_role_id: None,
+                log_channel_id: None,
+                show_emails: false,
+                whois_role_id: None,
+            });
+
+        update(server);
+    }
+}
+
+/// The start of the period that `time` is in. Weeks start on Monday, like the database's.
+fn period_start(period: StatsPeriod, time: DateTime<Utc>) -> DateTime<Utc> {
+    let day = time.date_naive();
+    let start = match period {
+        StatsPeriod::Day => day,
+        StatsPeriod::Week => day - Days::new(day.weekday().num_days_from_monday().into()),
+    };
+
+    start.and_time(NaiveTime::MIN).and_utc()
+}
+
+/// The median of some sorted numbers, if there are any.
+fn median(sorted: &[f64]) -> Option<f64> {
+    let middle = sorted.len() / 2;
+
+    match sorted.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((sorted[middle - 1] + sorted[middle]) / 2.0),
+        _ => Some(sorted[middle]),
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryRepository {
+    async fn user_exists(&self, user_id: UserId) -> Result<bool> {
+        Ok(self.users.lock().unwrap().contains_key(&i64::from(user_id)))
+    }
+
+    async fn get_user_state(&self, user_id: UserId) -> Result<Option<UserState>> {
+        Ok(self.get_user(user_id).map(|user| user.state))
+    }
+
+    async fn get_users(&self, user_ids: &[i64]) -> Result<Vec<User>> {
+        let users = self.users.lock().unwrap();
+
+        Ok(user_ids
+            .iter()
+            .filter_map(|user_id| users.get(user_id).cloned())
+            .collect())
+    }
+
+    async fn is_verified(&self, user_id: UserId) -> Result<bool> {
+        Ok(self
+            .get_user(user_id)
+            .is_some_and(|user| user.state == UserState::Verified))
+    }
+
+    async fn set_user_state(
+        &self,
+        user_id: UserId,
+        state: UserState,
+        actor: Option<UserId>,
+        server: Option<GuildId>,
+        method: AuditMethod,
+    ) -> Result<()> {
+        let old_state = match self.get_user(user_id) {
+            Some(user) => user.state,
+            None => return Err(diesel::result::Error::NotFound.into()),
+        };
+        self.update_user(user_id, |user| user.state = state)?;
+
+        self.record(NewAuditEvent {
+            actor_id: actor.map(i64::from),
+            subject_id: i64::from(user_id),
+            guild_id: server.map(i64::from),
+            old_state: Some(old_state),
+            new_state: Some(state),
+            method,
+            reason: None,
+        });
+
+        Ok(())
+    }
+
+    async fn create_user(&self, user_id: UserId) -> Result<User> {
+        let now = Utc::now();
+        let user = User {
+            id: i64::from(user_id),
+            imperial_email: None,
+            state: UserState::Unverified,
+            otps: vec![],
+            created_at: now,
+            updated_at: now,
+            imperial_email_ciphertext: None,
+            imperial_email_key_id: None,
+            imperial_email_index: None,
+            otp_sent_at: None,
+        };
+
+        self.users.lock().unwrap().insert(user.id, user.clone());
+
+        Ok(user)
+    }
+
+    async fn email_exists(&self, email: &str) -> Result<bool> {
+        // Emails are compared case-insensitively, like the database's email index.
+        let email = email.trim().to_lowercase();
+
+        Ok(self.users.lock().unwrap().values().any(|user| {
+            user.state == UserState::Verified
+                && user
+                    .imperial_email
+                    .as_ref()
+                    .is_some_and(|existing| existing.trim().to_lowercase() == email)
+        }))
+    }
+
+    async fn set_imperial_email(&self, user_id: UserId, email: String) -> Result<()> {
+        self.update_user(user_id, |user| user.imperial_email = Some(email))
+    }
+
+    async fn get_imperial_email(&self, user_id: UserId) -> Result<Option<String>> {
+        Ok(self.get_user(user_id).and_then(|user| user.imperial_email))
+    }
+
+    async fn insert_otp(&self, user_id: UserId, otp: i32) -> Result<()> {
+        self.update_user(user_id, |user| {
+            user.otps.push(Some(otp));
+            user.otp_sent_at = Some(Utc::now());
+        })
+    }
+
+    async fn otp_exists_for_user(&self, user_id: UserId, otp: i32) -> Result<bool> {
+        Ok(self
+            .get_user(user_id)
+            .is_some_and(|user| user.otps.contains(&Some(otp))))
+    }
+
+    async fn clear_otps(&self, user_id: UserId) -> Result<()> {
+        // Like the database, a missing user has no OTPs to clear.
+        if self.get_user(user_id).is_none() {
+            return Ok(());
+        }
+
+        self.update_user(user_id, |user| {
+            user.otps.clear();
+            user.otp_sent_at = None;
+        })
+    }
+
+    async fn otps_sent_at(&self, user_id: UserId) -> Result<Option<DateTime<Utc>>> {
+        Ok(self.get_user(user_id).and_then(|user| user.otp_sent_at))
+    }
+
+    async fn record_rejection(&self, user_id: UserId, rejection: AuditMethod) -> Result<()> {
+        self.record_non_transition(user_id, user_id, None, rejection, None);
+
+        Ok(())
+    }
+
+    async fn record_whois_lookup(
+        &self,
+        moderator: UserId,
+        user_id: UserId,
+        guild_id: GuildId,
+        reason: &str,
+    ) -> Result<()> {
+        self.record_non_transition(
+            moderator,
+            user_id,
+            Some(guild_id),
+            AuditMethod::WhoisLookup,
+            Some(reason.to_string()),
+        );
+
+        Ok(())
+    }
+
+    async fn get_verifying_server(&self, user_id: UserId) -> Result<Option<GuildId>> {
+        Ok(self
+            .audit_events_for(user_id)
+            .iter()
+            .rev()
+            .filter(|event| event.new_state == Some(UserState::QueryingEmail))
+            .find_map(|event| event.guild_id)
+            .map(|guild_id| GuildId::new(guild_id as u64)))
+    }
+
+    async fn get_audit_events(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        page: i64,
+        page_size: i64,
+    ) -> Result<(Vec<AuditEvent>, i64)> {
+        let visible: Vec<AuditEvent> = self
+            .audit_events_for(user_id)
+            .into_iter()
+            .rev()
+            .filter(|event| event.guild_id.is_none() || event.guild_id == Some(i64::from(guild_id)))
+            .collect();
+        let total = visible.len() as i64;
+
+        let page = visible
+            .into_iter()
+            .skip((page * page_size) as usize)
+            .take(page_size as usize)
+            .collect();
+
+        Ok((page, total))
+    }
+
+    async fn email_is_tombstoned(&self, email: &str) -> Result<bool> {
+        Ok(self
+            .tombstoned_emails
+            .lock()
+            .unwrap()
+            .contains(&email.to_lowercase()))
+    }
+
+    async fn collect_user_data(&self, user_id: UserId) -> Result<UserData> {
+        let user = self.get_user(user_id);
+        let id = i64::from(user_id);
+
+        let audit_events = self
+            .audit_events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.subject_id == id || event.actor_id == Some(id))
+            .cloned()
+            .collect();
+        let api_tokens = self
+            .api_tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|token| token.created_by == Some(id))
+            .cloned()
+            .collect();
+
+        Ok(UserData {
+            discord_id: id,
+            imperial_email: user.as_ref().and_then(|user| user.imperial_email.clone()),
+            user,
+            audit_events,
+            api_tokens,
+            emails: vec![],
+        })
+    }
+
+    async fn erase_user_data(&self, user_id: UserId, keep_tombstone: bool) -> Result<()> {
+        let id = i64::from(user_id);
+        let user = self.users.lock().unwrap().remove(&id);
+
+        if keep_tombstone {
+            if let Some(email) = user.and_then(|user| user.imperial_email) {
+                self.tombstoned_emails
+                    .lock()
+                    .unwrap()
+                    .insert(email.to_lowercase());
+            }
+        }
+
+        let mut audit_events = self.audit_events.lock().unwrap();
+        audit_events.retain(|event| event.subject_id != id);
+        for event in audit_events.iter_mut() {
+            if event.actor_id == Some(id) {
+                event.actor_id = None;
+            }
+        }
+
+        for token in self.api_tokens.lock().unwrap().iter_mut() {
+            if token.created_by == Some(id) {
+                token.created_by = None;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ServerRepository for InMemoryRepository {
+    async fn get_server(&self, guild_id: GuildId) -> Result<Option<Server>> {
+        Ok(self
+            .servers
+            .lock()
+            .unwrap()
+            .get(&i64::from(guild_id))
+            .cloned())
+    }
+
+    async fn get_verified_role(&self, guild_id: GuildId) -> Result<Option<RoleId>> {
+        Ok(self
+            .get_server(guild_id)
+            .await?
+            .and_then(|server| server.verified_role_id)
+            .map(|role_id| RoleId::new(role_id as u64)))
+    }
+
+    async fn set_verified_role(&self, guild_id: GuildId, role_id: RoleId) -> Result<()> {
+        self.update_server(guild_id, |server| {
+            server.verified_role_id = Some(i64::from(role_id))
+        });
+
+        Ok(())
+    }
+
+    async fn set_log_channel(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+    ) -> Result<()> {
+        self.update_server(guild_id, |server| {
+            server.log_channel_id = channel_id.map(i64::from)
+        });
+
+        Ok(())
+    }
+
+    async fn set_show_emails(&self, guild_id: GuildId, show: bool) -> Result<()> {
+        self.update_server(guild_id, |server| server.show_emails = show);
+
+        Ok(())
+    }
+
+    async fn set_whois_role(&self, guild_id: GuildId, role_id: Option<RoleId>) -> Result<()> {
+        self.update_server(guild_id, |server| {
+            server.whois_role_id = role_id.map(i64::from)
+        });
+
+        Ok(())
+    }
+
+    async fn get_servers_with_verified_roles(&self) -> Result<Vec<Server>> {
+        Ok(self.servers_where(|server| server.verified_role_id.is_some()))
+    }
+
+    async fn get_servers_with_log_channels(&self) -> Result<Vec<Server>> {
+        Ok(self.servers_where(|server| server.log_channel_id.is_some()))
+    }
+
+    async fn get_server_audit_events(
+        &self,
+        guild_id: GuildId,
+        member_ids: &[i64],
+        page: i64,
+        page_size: i64,
+    ) -> Result<(Vec<AuditEvent>, i64)> {
+        let visible: Vec<AuditEvent> = self
+            .audit_events
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|event| match event.guild_id {
+                Some(event_guild_id) => event_guild_id == i64::from(guild_id),
+                None => member_ids.contains(&event.subject_id),
+            })
+            .cloned()
+            .collect();
+        let total = visible.len() as i64;
+
+        let page = visible
+            .into_iter()
+            .skip((page * page_size) as usize)
+            .take(page_size as usize)
+            .collect();
+
+        Ok((page, total))
+    }
+
+    async fn get_verification_stats(
+        &self,
+        guild_id: GuildId,
+        member_ids: &[i64],
+        period: StatsPeriod,
+        since: DateTime<Utc>,
+    ) -> Result<VerificationStats> {
+        let members: HashSet<i64> = member_ids.iter().copied().collect();
+
+        let mut state_counts: HashMap<UserState, i64> = HashMap::new();
+        for user in self.users.lock().unwrap().values() {
+            if members.contains(&user.id) {
+                *state_counts.entry(user.state).or_default() += 1;
+            }
+        }
+        let mut state_counts: Vec<(UserState, i64)> = state_counts.into_iter().collect();
+        state_counts.sort_by_key(|&(state, _)| state as i32);
+
+        let audit_events = self.audit_events.lock().unwrap();
+        let is_verification = |event: &AuditEvent| {
+            event.new_state == Some(UserState::Verified)
+                && event.old_state != Some(UserState::Verified)
+        };
+
+        let mut verifications: Vec<(DateTime<Utc>, i64)> = vec![];
+        for event in audit_events.iter().filter(|event| {
+            is_verification(event)
+                && members.contains(&event.subject_id)
+                && event.created_at >= since
+        }) {
+            let start = period_start(period, event.created_at);
+            match verifications.last_mut() {
+                Some((last, count)) if *last == start => *count += 1,
+                _ => verifications.push((start, 1)),
+            }
+        }
+
+        // Pair each time someone joined this server with the first verification after it.
+        let mut seconds_to_verify: Vec<f64> = audit_events
+            .iter()
+            .filter(|event| {
+                event.method == AuditMethod::MemberJoin
+                    && event.guild_id == Some(i64::from(guild_id))
+            })
+            .filter_map(|join| {
+                let verified = audit_events.iter().find(|event| {
+                    is_verification(event)
+                        && event.subject_id == join.subject_id
+                        && event.created_at >= join.created_at
+                })?;
+
+                Some((verified.created_at - join.created_at).num_milliseconds() as f64 / 1000.0)
+            })
+            .collect();
+        seconds_to_verify.sort_by(f64::total_cmp);
+
+        let count_method = |method: AuditMethod| {
+            audit_events
+                .iter()
+                .filter(|event| event.method == method && members.contains(&event.subject_id))
+                .count() as i64
+        };
+
+        let email_failures = count_method(AuditMethod::EmailRejected);
+        let otp_failures = count_method(AuditMethod::OtpRejected);
+
+        Ok(VerificationStats {
+            state_counts,
+            verifications,
+            median_seconds_to_verify: median(&seconds_to_verify),
+            email_attempts: count_method(AuditMethod::SetEmail) + email_failures,
+            email_failures,
+            otp_attempts: count_method(AuditMethod::Otp) + otp_failures,
+            otp_failures,
+        })
+    }
+
+    async fn create_api_token(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        creator: UserId,
+    ) -> Result<String> {
+        let token = generate_token();
+
+        let mut api_tokens = self.api_tokens.lock().unwrap();
+        let id = api_tokens.len() as i64 + 1;
+        api_tokens.push(ApiToken {
+            id,
+            guild_id: i64::from(guild_id),
+            name: name.to_string(),
+            token_hash: hash_token(&token),
+            created_by: Some(i64::from(creator)),
+            created_at: Utc::now(),
+            revoked_at: None,
+        });
+
+        Ok(token)
+    }
+
+    async fn get_api_tokens(&self, guild_id: GuildId) -> Result<Vec<ApiToken>> {
+        Ok(self
+            .api_tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|token| token.guild_id == i64::from(guild_id) && token.revoked_at.is_none())
+            .cloned()
+            .collect())
+    }
+
+    async fn revoke_api_token(&self, guild_id: GuildId, token_id: i64) -> Result<bool> {
+        let mut api_tokens = self.api_tokens.lock().unwrap();
+        let token = api_tokens.iter_mut().find(|token| {
+            token.id == token_id
+                && token.guild_id == i64::from(guild_id)
+                && token.revoked_at.is_none()
+        });
+
+        Ok(match token {
+            Some(token) => {
+                token.revoked_at = Some(Utc::now());
+                true
+            }
+            None => false,
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use super::{AnyConnection, Database};
+use crate::errors::{Error, Result};
+use diesel::backend::Backend;
+use diesel::migration::MigrationSource;
+use diesel::pg::Pg;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use std::collections::HashSet;
+
+/// The migrations in `migrations/postgres/`, embedded in the binary.
+const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+
+/// The migrations in `migrations/sqlite/`, embedded in the binary.
+#[cfg(feature = "sqlite")]
+const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+
+/// The versions of the migrations embedded for the connection's backend.
+fn embedded_versions(conn: &AnyConnection) -> Result<HashSet<String>> {
+    match conn {
+        AnyConnection::Postgresql(_) => versions::<Pg>(&POSTGRES_MIGRATIONS),
+        #[cfg(feature = "sqlite")]
+        AnyConnection::Sqlite(_) => versions::<diesel::sqlite::Sqlite>(&SQLITE_MIGRATIONS),
+    }
+}
+
+fn versions<DB: Backend>(migrations: &EmbeddedMigrations) -> Result<HashSet<String>>
+where
+    EmbeddedMigrations: MigrationSource<DB>,
+{
+    Ok(migrations
+        .migrations()
+        .map_err(|err| Error::Migration(err.to_string()))?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect())
+}
+
+/// Apply any migrations that haven't been applied yet. Returns the versions that were applied.
+pub async fn run_pending_migrations(db: &Database) -> Result<Vec<String>> {
+    db.run(move |conn| {
+        let applied = match conn {
+            AnyConnection::Postgresql(conn) => conn.run_pending_migrations(POSTGRES_MIGRATIONS),
+            #[cfg(feature = "sqlite")]
+            AnyConnection::Sqlite(conn) => conn.run_pending_migrations(SQLITE_MIGRATIONS),
+        }
+        .map_err(|err| Error::Migration(err.to_string()))?;
+
+        Ok(applied.iter().map(|version| version.to_string()).collect())
+    })
+    .await
+}
+
+/// The state of the database schema compared to the migrations embedded in the binary.
+pub struct SchemaStatus {
+    /// Migrations that haven't been applied yet.
+    pub pending: Vec<String>,
+    /// Migrations that have been applied, but that this binary doesn't know about. This usually
+    /// means the database was migrated by a newer version of the bot.
+    pub unknown: Vec<String>,
+}
+
+impl SchemaStatus {
+    /// Whether the database schema is exactly what this binary expects.
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty() && self.unknown.is_empty()
+    }
+}
+
+/// Compare the applied migrations to the ones embedded in the binary, without changing anything.
+pub async fn check_migrations(db: &Database) -> Result<SchemaStatus> {
+    db.run(move |conn| {
+        let applied: HashSet<String> = conn
+            .applied_migrations()
+            .map_err(|err| Error::Migration(err.to_string()))?
+            .iter()
+            .map(|version| version.to_string())
+            .collect();
+
+        let embedded = embedded_versions(conn)?;
+
+        let mut pending: Vec<String> = embedded.difference(&applied).cloned().collect();
+        let mut unknown: Vec<String> = applied.difference(&embedded).cloned().collect();
+        pending.sort();
+        unknown.sort();
+
+        Ok(SchemaStatus { pending, unknown })
+    })
+    .await
+}
@@ -1,31 +1,116 @@
+mod api_tokens;
+mod audit_events;
+pub(crate) mod backend;
+mod encryption;
+mod export;
+#[cfg(test)]
+mod memory;
+mod migrations;
 pub mod models;
+mod outbox;
+mod repository;
+mod retention;
 pub mod schema;
 mod servers;
+mod stats;
+#[cfg(all(test, feature = "sqlite"))]
+mod tests;
+mod tombstones;
+mod types;
+mod user_data;
 mod users;
 
-use diesel::pg::PgConnection;
+use crate::config::DatabaseConfig;
+use crate::{errors, metrics};
+use backend::ConnectionManager;
 use diesel::prelude::*;
+use diesel::r2d2::Pool;
 use log::debug;
-use std::env;
-use std::sync::LazyLock;
-use tokio::sync::Mutex;
+use std::time::Instant;
 
+pub use api_tokens::*;
+pub use audit_events::*;
+pub use backend::AnyConnection;
+pub use encryption::{EmailKeys, EncryptionError};
+pub use export::*;
+#[cfg(test)]
+pub use memory::InMemoryRepository;
+pub use migrations::*;
+pub use outbox::*;
+pub use repository::{ServerRepository, UserRepository};
+pub use retention::*;
 pub use servers::*;
+pub use stats::*;
+pub use user_data::*;
 pub use users::*;
 
-/// The global connection to the database.
-/// NOTE: If using `dotenv`, run `dotenv::dotenv().ok();` before using this.
-/// TODO: This is bad! Get rid of this!
-pub static PG_CONNECTION: LazyLock<Mutex<PgConnection>> =
-    LazyLock::new(|| Mutex::new(establish_connection()));
+/// A pool of connections to the database. Cloning it is cheap, and clones share the same pool.
+#[derive(Clone)]
+pub struct Database {
+    pool: Pool<ConnectionManager>,
+}
+
+impl Database {
+    /// Connect to the database in the config.
+    pub fn connect(config: &DatabaseConfig) -> Database {
+        Database::open(&config.url, config.pool_size)
+    }
+
+    /// Connect to `database_url`, keeping up to `pool_size` connections open.
+    pub fn open(database_url: &str, pool_size: u32) -> Database {
+        if cfg!(not(feature = "sqlite")) && backend::sqlite_path(database_url).is_some() {
+            panic!("DATABASE_URL is a SQLite database, but the bot was built without the `sqlite` feature");
+        }
+
+        debug!(
+            "Connecting to {} with {} connections",
+            database_url, pool_size
+        );
+
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .build(ConnectionManager::new(database_url))
+            .unwrap_or_else(|err| panic!("Error connecting to {}: {}", database_url, err));
+
+        Database { pool }
+    }
 
-// This function will establish a connection to the database using the DATABASE_URL environment variable.
-// NOTE: If using `dotenv`, run `dotenv::dotenv().ok();` before calling this function.
-fn establish_connection() -> PgConnection {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    /// Run blocking database code with a connection from the pool, on a thread where blocking is
+    /// fine. How long this waits for a connection, and how long the code then takes, are both
+    /// recorded in the metrics.
+    async fn run<T, F>(&self, f: F) -> errors::Result<T>
+    where
+        F: FnOnce(&mut AnyConnection) -> errors::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            let mut conn = pool.get()?;
+            metrics::db_wait(start.elapsed());
+
+            let start = Instant::now();
+            let result = f(&mut conn);
+            metrics::db_query(start.elapsed());
+
+            result
+        })
+        .await;
+
+        match result {
+            Ok(result) => result,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
 
-    debug!("Connecting to {}", database_url);
+/// Check that the database can be reached.
+pub async fn ping(db: &Database) -> errors::Result<()> {
+    db.run(|conn| {
+        diesel::sql_query("SELECT 1").execute(conn)?;
 
-    PgConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+        Ok(())
+    })
+    .await
 }
@@ -0,0 +1,28 @@
+use crate::db::schema;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = schema::api_tokens)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct ApiToken {
+    pub id: i64,
+    pub guild_id: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: Vec<u8>,
+    pub created_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::api_tokens)]
+pub struct NewApiToken {
+    pub guild_id: i64,
+    pub name: String,
+    pub token_hash: Vec<u8>,
+    pub created_by: Option<i64>,
+}
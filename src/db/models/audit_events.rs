@@ -0,0 +1,70 @@
+use super::UserState;
+use crate::db::schema;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = schema::audit_events)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor_id: Option<i64>,
+    pub subject_id: i64,
+    pub guild_id: Option<i64>,
+    pub old_state: Option<UserState>,
+    /// `None` for events that aren't state transitions, like `/whois` lookups.
+    pub new_state: Option<UserState>,
+    pub method: AuditMethod,
+    pub created_at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::audit_events)]
+pub struct NewAuditEvent {
+    pub actor_id: Option<i64>,
+    pub subject_id: i64,
+    pub guild_id: Option<i64>,
+    pub old_state: Option<UserState>,
+    /// `None` for events that aren't state transitions, like `/whois` lookups.
+    pub new_state: Option<UserState>,
+    pub method: AuditMethod,
+    pub reason: Option<String>,
+}
+
+/// How a user's state was changed.
+#[derive(Debug, Clone, Copy, DbEnum, PartialEq, Eq, Serialize, Deserialize)]
+#[ExistingTypePath = "crate::db::schema::sql_types::AuditMethod"]
+pub enum AuditMethod {
+    /// The user joined a server with the bot on it.
+    MemberJoin,
+    /// Someone ran `/verify` for the user.
+    VerifyCommand,
+    /// The user submitted their email with `/set_email`.
+    SetEmail,
+    /// The user submitted their passcode with `/otp`.
+    Otp,
+    /// The user's data expired under the retention policy.
+    Retention,
+    /// A moderator looked up the user's email with `/whois`. This doesn't change their state.
+    WhoisLookup,
+    /// The user submitted an email that was rejected. This doesn't change their state.
+    EmailRejected,
+    /// The user submitted a passcode that was rejected. This doesn't change their state.
+    OtpRejected,
+    /// An operator changed the user's state from the command line.
+    Cli,
+}
+
+impl AuditMethod {
+    /// Whether events of this kind change the user's state. Those that don't have no new state.
+    pub fn is_transition(self) -> bool {
+        !matches!(
+            self,
+            AuditMethod::WhoisLookup | AuditMethod::EmailRejected | AuditMethod::OtpRejected
+        )
+    }
+}
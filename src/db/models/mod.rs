@@ -1,5 +1,13 @@
+mod api_tokens;
+mod audit_events;
+mod outbox;
 mod servers;
+mod tombstones;
 mod users;
 
+pub use api_tokens::*;
+pub use audit_events::*;
+pub use outbox::*;
 pub use servers::*;
+pub use tombstones::*;
 pub use users::*;
@@ -0,0 +1,46 @@
+use crate::db::schema;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+
+#[allow(dead_code)]
+#[derive(Debug, Queryable, Selectable, Serialize)]
+#[diesel(table_name = schema::outbox)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct OutboxEmail {
+    pub id: i64,
+    /// The discord user the email is for, who is told if it can't be delivered.
+    pub user_id: i64,
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// Cleared once the email has been sent or given up on.
+    #[serde(skip)]
+    pub message_ciphertext: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub message_key_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::outbox)]
+pub struct NewOutboxEmail {
+    pub user_id: i64,
+    pub message_ciphertext: Option<Vec<u8>>,
+    pub message_key_id: Option<String>,
+}
+
+/// Where an email in the outbox is up to.
+#[derive(Debug, Clone, Copy, DbEnum, PartialEq, Eq, Serialize, Deserialize)]
+#[ExistingTypePath = "crate::db::schema::sql_types::OutboxStatus"]
+pub enum OutboxStatus {
+    /// Waiting to be sent, possibly after failed attempts.
+    Pending,
+    /// Sent.
+    Sent,
+    /// Given up on after too many failed attempts.
+    Dead,
+}
@@ -1,19 +1,23 @@
 use crate::db::schema;
 use diesel::prelude::*;
+use serde::Serialize;
 
 #[allow(dead_code)]
-#[derive(Debug, Queryable, Selectable)]
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
 #[diesel(table_name = schema::servers)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
 pub struct Server {
     pub id: i64,
     pub verified_role_id: Option<i64>,
+    pub log_channel_id: Option<i64>,
+    pub show_emails: bool,
+    pub whois_role_id: Option<i64>,
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Insertable)]
 #[diesel(table_name = schema::servers)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
 pub struct NewServer {
     pub id: i64,
 }
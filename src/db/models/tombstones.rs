@@ -0,0 +1,21 @@
+use crate::db::schema;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+
+#[allow(dead_code)]
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = schema::tombstones)]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
+pub struct Tombstone {
+    pub id: i64,
+    pub discord_id_hash: Vec<u8>,
+    pub email_hash: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::tombstones)]
+pub struct NewTombstone {
+    pub discord_id_hash: Vec<u8>,
+    pub email_hash: Option<Vec<u8>>,
+}
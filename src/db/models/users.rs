@@ -1,16 +1,30 @@
 use crate::db::schema;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
 
 #[allow(dead_code)]
-#[derive(Debug, Queryable, Selectable)]
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
 #[diesel(table_name = schema::users)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(crate::db::backend::MultiBackend))]
 pub struct User {
     pub id: i64,
+    /// Only set for users whose email was stored before emails were encrypted.
+    #[serde(skip)]
     pub imperial_email: Option<String>,
     pub state: UserState,
     pub otps: Vec<Option<i32>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub imperial_email_ciphertext: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub imperial_email_key_id: Option<String>,
+    #[serde(skip)]
+    pub imperial_email_index: Option<Vec<u8>>,
+    /// When the user's passcodes were sent, if they have any.
+    pub otp_sent_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Insertable)]
@@ -20,7 +34,7 @@ pub struct NewUser {
 }
 
 #[repr(i32)]
-#[derive(Debug, Clone, Copy, DbEnum, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, DbEnum, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[ExistingTypePath = "crate::db::schema::sql_types::UserState"]
 pub enum UserState {
     Unverified = 0,
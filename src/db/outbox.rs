@@ -0,0 +1,164 @@
+use super::encryption::{active_key_id, decrypt_message, encrypt_message};
+use super::models::*;
+use super::{schema, Database};
+use crate::errors::Result;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use poise::serenity_prelude::UserId;
+
+/// Add an email for a user to the outbox, to be sent as soon as possible. The message is stored
+/// encrypted.
+pub async fn enqueue_email(db: &Database, user_id: UserId, message: &[u8]) -> Result<()> {
+    let (ciphertext, key_id) = encrypt_message(i64::from(user_id), message)?;
+
+    let new_email = NewOutboxEmail {
+        user_id: i64::from(user_id),
+        message_ciphertext: Some(ciphertext),
+        message_key_id: Some(key_id),
+    };
+
+    db.run(move |conn| {
+        diesel::insert_into(schema::outbox::table)
+            .values(&new_email)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Get up to `limit` pending emails that are due to be (re)sent, oldest first.
+pub async fn get_due_emails(db: &Database, limit: i64) -> Result<Vec<OutboxEmail>> {
+    db.run(move |conn| {
+        use schema::outbox::dsl::*;
+
+        let res = outbox
+            .filter(
+                status
+                    .eq(OutboxStatus::Pending)
+                    .and(next_attempt_at.le(Utc::now())),
+            )
+            .order(next_attempt_at)
+            .limit(limit)
+            .load(conn)?;
+
+        Ok(res)
+    })
+    .await
+}
+
+/// Decrypt the message of an email in the outbox. Returns `None` if it has already been cleared.
+pub fn decrypt_outbox_message(email: &OutboxEmail) -> Result<Option<Vec<u8>>> {
+    match (&email.message_ciphertext, &email.message_key_id) {
+        (Some(ciphertext), Some(key_id)) => {
+            Ok(Some(decrypt_message(email.user_id, ciphertext, key_id)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Re-encrypts up to `batch_size` messages in the outbox that are encrypted with a key other than
+/// the active one. Returns the number of messages re-encrypted, so this should be called until it
+/// returns 0.
+pub async fn reencrypt_outbox_batch(db: &Database, batch_size: i64) -> Result<usize> {
+    db.run(move |conn| {
+        use schema::outbox::dsl::*;
+
+        let batch = outbox
+            .filter(
+                message_ciphertext
+                    .is_not_null()
+                    .and(message_key_id.ne(active_key_id())),
+            )
+            .order(id)
+            .limit(batch_size)
+            .load::<OutboxEmail>(conn)?;
+
+        let mut updates = Vec::with_capacity(batch.len());
+        for email in &batch {
+            if let Some(message) = decrypt_outbox_message(email)? {
+                updates.push((email.id, encrypt_message(email.user_id, &message)?));
+            }
+        }
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            for (email_id, (ciphertext, key_id)) in updates {
+                diesel::update(outbox.find(email_id))
+                    .set((
+                        message_ciphertext.eq(Some(ciphertext)),
+                        message_key_id.eq(Some(key_id)),
+                    ))
+                    .execute(conn)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(batch.len())
+    })
+    .await
+}
+
+/// Record that an email was sent, clearing its message.
+pub async fn mark_email_sent(db: &Database, email_id: i64) -> Result<()> {
+    db.run(move |conn| {
+        use schema::outbox::dsl::*;
+
+        diesel::update(outbox.find(email_id))
+            .set((
+                status.eq(OutboxStatus::Sent),
+                attempts.eq(attempts + 1),
+                last_error.eq(None::<String>),
+                message_ciphertext.eq(None::<Vec<u8>>),
+                message_key_id.eq(None::<String>),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Record that sending an email failed, and try again at `retry_at`.
+pub async fn retry_email(
+    db: &Database,
+    email_id: i64,
+    error: String,
+    retry_at: DateTime<Utc>,
+) -> Result<()> {
+    db.run(move |conn| {
+        use schema::outbox::dsl::*;
+
+        diesel::update(outbox.find(email_id))
+            .set((
+                attempts.eq(attempts + 1),
+                last_error.eq(Some(error)),
+                next_attempt_at.eq(retry_at),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Record that sending an email failed for the last time, giving up on it and clearing its
+/// message.
+pub async fn dead_letter_email(db: &Database, email_id: i64, error: String) -> Result<()> {
+    db.run(move |conn| {
+        use schema::outbox::dsl::*;
+
+        diesel::update(outbox.find(email_id))
+            .set((
+                status.eq(OutboxStatus::Dead),
+                attempts.eq(attempts + 1),
+                last_error.eq(Some(error)),
+                message_ciphertext.eq(None::<Vec<u8>>),
+                message_key_id.eq(None::<String>),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,334 @@
+use super::models::*;
+use super::stats::{StatsPeriod, VerificationStats};
+use super::user_data::UserData;
+use super::{api_tokens, audit_events, servers, stats, tombstones, user_data, users, Database};
+use crate::errors::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId, RoleId, UserId};
+
+/// Where users and their verification progress are stored.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Check if a discord user exists.
+    async fn user_exists(&self, user_id: UserId) -> Result<bool>;
+
+    /// Get a user's state, if they exist.
+    async fn get_user_state(&self, user_id: UserId) -> Result<Option<UserState>>;
+
+    /// Get all the users in `user_ids` that exist.
+    async fn get_users(&self, user_ids: &[i64]) -> Result<Vec<User>>;
+
+    /// Check if a discord user is verified. If the user doesn't exist, return false.
+    async fn is_verified(&self, user_id: UserId) -> Result<bool>;
+
+    /// Sets a user's state to `state`, recording who caused it, where, and how. Fails if the user
+    /// doesn't exist.
+    async fn set_user_state(
+        &self,
+        user_id: UserId,
+        state: UserState,
+        actor: Option<UserId>,
+        server: Option<GuildId>,
+        method: AuditMethod,
+    ) -> Result<()>;
+
+    /// Creates a new user given their discord ID.
+    async fn create_user(&self, user_id: UserId) -> Result<User>;
+
+    /// Check if this email is already in use by a verified user.
+    async fn email_exists(&self, email: &str) -> Result<bool>;
+
+    /// Sets the user's imperial email. Fails if the user doesn't exist.
+    async fn set_imperial_email(&self, user_id: UserId, email: String) -> Result<()>;
+
+    /// Gets the user's imperial email, if they have one.
+    async fn get_imperial_email(&self, user_id: UserId) -> Result<Option<String>>;
+
+    /// Inserts an OTP into a user's OTPs. Fails if the user doesn't exist.
+    async fn insert_otp(&self, user_id: UserId, otp: i32) -> Result<()>;
+
+    /// Check if an OTP is one of the user's OTPs.
+    async fn otp_exists_for_user(&self, user_id: UserId, otp: i32) -> Result<bool>;
+
+    /// Clear all the user's OTPs.
+    async fn clear_otps(&self, user_id: UserId) -> Result<()>;
+
+    /// When the user's OTPs were last sent, if they have any.
+    async fn otps_sent_at(&self, user_id: UserId) -> Result<Option<DateTime<Utc>>>;
+
+    /// Record that a user's email or passcode was rejected.
+    async fn record_rejection(&self, user_id: UserId, rejection: AuditMethod) -> Result<()>;
+
+    /// Record that a moderator looked up a user's email with `/whois`, and why.
+    async fn record_whois_lookup(
+        &self,
+        moderator: UserId,
+        user_id: UserId,
+        guild_id: GuildId,
+        reason: &str,
+    ) -> Result<()>;
+
+    /// Get the server a user last started verifying in, if they started in one.
+    async fn get_verifying_server(&self, user_id: UserId) -> Result<Option<GuildId>>;
+
+    /// Get a page of a user's audit trail as seen from a server, newest first, along with the
+    /// total number of events.
+    async fn get_audit_events(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        page: i64,
+        page_size: i64,
+    ) -> Result<(Vec<AuditEvent>, i64)>;
+
+    /// Check if an email belongs to an erased user whose tombstone has to be kept.
+    async fn email_is_tombstoned(&self, email: &str) -> Result<bool>;
+
+    /// Collect everything stored about a user.
+    async fn collect_user_data(&self, user_id: UserId) -> Result<UserData>;
+
+    /// Erase everything stored about a user, optionally keeping a tombstone of their email.
+    async fn erase_user_data(&self, user_id: UserId, keep_tombstone: bool) -> Result<()>;
+}
+
+/// Where the settings of each server are stored.
+#[async_trait]
+pub trait ServerRepository: Send + Sync {
+    /// Get the server's settings, if it has any.
+    async fn get_server(&self, guild_id: GuildId) -> Result<Option<Server>>;
+
+    /// Get the verified role for the server.
+    async fn get_verified_role(&self, guild_id: GuildId) -> Result<Option<RoleId>>;
+
+    /// Set the verified role for the server.
+    async fn set_verified_role(&self, guild_id: GuildId, role_id: RoleId) -> Result<()>;
+
+    /// Set (or clear) the channel that verification events are logged to.
+    async fn set_log_channel(&self, guild_id: GuildId, channel_id: Option<ChannelId>)
+        -> Result<()>;
+
+    /// Set whether emails are shown unredacted in the server's log channel.
+    async fn set_show_emails(&self, guild_id: GuildId, show: bool) -> Result<()>;
+
+    /// Set (or clear) the role allowed to look up members' emails with `/whois`.
+    async fn set_whois_role(&self, guild_id: GuildId, role_id: Option<RoleId>) -> Result<()>;
+
+    /// Get the servers that have a verified role.
+    async fn get_servers_with_verified_roles(&self) -> Result<Vec<Server>>;
+
+    /// Get the servers that have a log channel.
+    async fn get_servers_with_log_channels(&self) -> Result<Vec<Server>>;
+
+    /// Get a page of a server's audit trail, newest first, along with the total number of events.
+    /// This includes events of its members (`member_ids`) that didn't happen in any server.
+    async fn get_server_audit_events(
+        &self,
+        guild_id: GuildId,
+        member_ids: &[i64],
+        page: i64,
+        page_size: i64,
+    ) -> Result<(Vec<AuditEvent>, i64)>;
+
+    /// Get the verification statistics of a server, given the IDs of its members.
+    async fn get_verification_stats(
+        &self,
+        guild_id: GuildId,
+        member_ids: &[i64],
+        period: StatsPeriod,
+        since: DateTime<Utc>,
+    ) -> Result<VerificationStats>;
+
+    /// Create a new API token for a server, returning the token.
+    async fn create_api_token(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        creator: UserId,
+    ) -> Result<String>;
+
+    /// Get the API tokens of a server that haven't been revoked.
+    async fn get_api_tokens(&self, guild_id: GuildId) -> Result<Vec<ApiToken>>;
+
+    /// Revoke one of a server's API tokens. Returns whether there was a token to revoke.
+    async fn revoke_api_token(&self, guild_id: GuildId, token_id: i64) -> Result<bool>;
+}
+
+#[async_trait]
+impl UserRepository for Database {
+    async fn user_exists(&self, user_id: UserId) -> Result<bool> {
+        users::user_exists(self, user_id).await
+    }
+
+    async fn get_user_state(&self, user_id: UserId) -> Result<Option<UserState>> {
+        Ok(users::get_user(self, user_id).await?.map(|user| user.state))
+    }
+
+    async fn get_users(&self, user_ids: &[i64]) -> Result<Vec<User>> {
+        users::get_users(self, user_ids).await
+    }
+
+    async fn is_verified(&self, user_id: UserId) -> Result<bool> {
+        users::is_verified(self, user_id).await
+    }
+
+    async fn set_user_state(
+        &self,
+        user_id: UserId,
+        state: UserState,
+        actor: Option<UserId>,
+        server: Option<GuildId>,
+        method: AuditMethod,
+    ) -> Result<()> {
+        users::set_user_state(self, user_id, state, actor, server, method).await
+    }
+
+    async fn create_user(&self, user_id: UserId) -> Result<User> {
+        users::create_user(self, user_id).await
+    }
+
+    async fn email_exists(&self, email: &str) -> Result<bool> {
+        users::email_exists(self, email).await
+    }
+
+    async fn set_imperial_email(&self, user_id: UserId, email: String) -> Result<()> {
+        users::set_imperial_email(self, user_id, email).await
+    }
+
+    async fn get_imperial_email(&self, user_id: UserId) -> Result<Option<String>> {
+        users::get_imperial_email(self, user_id).await
+    }
+
+    async fn insert_otp(&self, user_id: UserId, otp: i32) -> Result<()> {
+        users::insert_otp(self, user_id, otp).await
+    }
+
+    async fn otp_exists_for_user(&self, user_id: UserId, otp: i32) -> Result<bool> {
+        users::otp_exists_for_user(self, user_id, otp).await
+    }
+
+    async fn clear_otps(&self, user_id: UserId) -> Result<()> {
+        users::clear_otps(self, user_id).await
+    }
+
+    async fn otps_sent_at(&self, user_id: UserId) -> Result<Option<DateTime<Utc>>> {
+        users::get_otps_sent_at(self, user_id).await
+    }
+
+    async fn record_rejection(&self, user_id: UserId, rejection: AuditMethod) -> Result<()> {
+        audit_events::record_rejection(self, user_id, rejection).await
+    }
+
+    async fn record_whois_lookup(
+        &self,
+        moderator: UserId,
+        user_id: UserId,
+        guild_id: GuildId,
+        reason: &str,
+    ) -> Result<()> {
+        audit_events::record_whois_lookup(self, moderator, user_id, guild_id, reason).await
+    }
+
+    async fn get_verifying_server(&self, user_id: UserId) -> Result<Option<GuildId>> {
+        audit_events::get_verifying_server(self, user_id).await
+    }
+
+    async fn get_audit_events(
+        &self,
+        user_id: UserId,
+        guild_id: GuildId,
+        page: i64,
+        page_size: i64,
+    ) -> Result<(Vec<AuditEvent>, i64)> {
+        audit_events::get_audit_events_for_user(self, user_id, guild_id, page, page_size).await
+    }
+
+    async fn email_is_tombstoned(&self, email: &str) -> Result<bool> {
+        tombstones::email_is_tombstoned(self, email).await
+    }
+
+    async fn collect_user_data(&self, user_id: UserId) -> Result<UserData> {
+        user_data::collect_user_data(self, user_id).await
+    }
+
+    async fn erase_user_data(&self, user_id: UserId, keep_tombstone: bool) -> Result<()> {
+        user_data::erase_user_data(self, user_id, keep_tombstone).await
+    }
+}
+
+#[async_trait]
+impl ServerRepository for Database {
+    async fn get_server(&self, guild_id: GuildId) -> Result<Option<Server>> {
+        servers::get_server(self, guild_id).await
+    }
+
+    async fn get_verified_role(&self, guild_id: GuildId) -> Result<Option<RoleId>> {
+        servers::get_verified_role(self, guild_id).await
+    }
+
+    async fn set_verified_role(&self, guild_id: GuildId, role_id: RoleId) -> Result<()> {
+        servers::set_verified_role(self, guild_id, role_id).await
+    }
+
+    async fn set_log_channel(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+    ) -> Result<()> {
+        servers::set_log_channel(self, guild_id, channel_id).await
+    }
+
+    async fn set_show_emails(&self, guild_id: GuildId, show: bool) -> Result<()> {
+        servers::set_show_emails(self, guild_id, show).await
+    }
+
+    async fn set_whois_role(&self, guild_id: GuildId, role_id: Option<RoleId>) -> Result<()> {
+        servers::set_whois_role(self, guild_id, role_id).await
+    }
+
+    async fn get_servers_with_verified_roles(&self) -> Result<Vec<Server>> {
+        servers::get_servers_with_verified_roles(self).await
+    }
+
+    async fn get_servers_with_log_channels(&self) -> Result<Vec<Server>> {
+        servers::get_servers_with_log_channels(self).await
+    }
+
+    async fn get_server_audit_events(
+        &self,
+        guild_id: GuildId,
+        member_ids: &[i64],
+        page: i64,
+        page_size: i64,
+    ) -> Result<(Vec<AuditEvent>, i64)> {
+        audit_events::get_audit_events_for_server(self, guild_id, member_ids, page, page_size).await
+    }
+
+    async fn get_verification_stats(
+        &self,
+        guild_id: GuildId,
+        member_ids: &[i64],
+        period: StatsPeriod,
+        since: DateTime<Utc>,
+    ) -> Result<VerificationStats> {
+        stats::get_verification_stats(self, guild_id, member_ids, period, since).await
+    }
+
+    async fn create_api_token(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        creator: UserId,
+    ) -> Result<String> {
+        api_tokens::create_api_token(self, guild_id, name, creator).await
+    }
+
+    async fn get_api_tokens(&self, guild_id: GuildId) -> Result<Vec<ApiToken>> {
+        api_tokens::get_api_tokens(self, guild_id).await
+    }
+
+    async fn revoke_api_token(&self, guild_id: GuildId, token_id: i64) -> Result<bool> {
+        api_tokens::revoke_api_token(self, guild_id, token_id).await
+    }
+}
@@ -0,0 +1,153 @@
+use super::backend::{each_backend, AnyConnection};
+use super::models::*;
+use super::{schema, Database};
+use crate::errors::Result;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+
+/// How many users are purged in each query, to stay well under the number of parameters a query
+/// can have.
+const BATCH_SIZE: usize = 1000;
+
+/// Record in the audit trail that the retention policy moved `user_ids` from `old_state` to
+/// `new_state`.
+fn record_retention(
+    conn: &mut AnyConnection,
+    user_ids: &[i64],
+    old_state: UserState,
+    new_state: UserState,
+    reason: Option<&str>,
+) -> diesel::QueryResult<()> {
+    for batch in user_ids.chunks(BATCH_SIZE) {
+        let events = batch
+            .iter()
+            .map(|&user_id| NewAuditEvent {
+                actor_id: None,
+                subject_id: user_id,
+                guild_id: None,
+                old_state: Some(old_state),
+                new_state: Some(new_state),
+                method: AuditMethod::Retention,
+                reason: reason.map(str::to_string),
+            })
+            .collect::<Vec<_>>();
+
+        // `AnyConnection` can't insert several rows at once, but each backend can.
+        each_backend!(&mut *conn, |conn| {
+            diesel::insert_into(schema::audit_events::table)
+                .values(&events)
+                .execute(conn)?;
+        });
+    }
+
+    Ok(())
+}
+
+/// Count the users in `user_state` that haven't been updated since `cutoff`.
+pub async fn count_stale_users(
+    db: &Database,
+    user_state: UserState,
+    cutoff: DateTime<Utc>,
+) -> Result<i64> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let res = users
+            .filter(state.eq(user_state).and(updated_at.lt(cutoff)))
+            .count()
+            .get_result(conn)?;
+
+        Ok(res)
+    })
+    .await
+}
+
+/// Delete the users in `user_state` that haven't been updated since `cutoff`, along with the
+/// emails waiting to be sent to them. The deletion is recorded in the audit trail, as a move back
+/// to `Unverified` (which is what users without an entry are). Returns the number of users
+/// deleted.
+pub async fn delete_stale_users(
+    db: &Database,
+    user_state: UserState,
+    cutoff: DateTime<Utc>,
+) -> Result<usize> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let res = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let stale = users
+                .filter(state.eq(user_state).and(updated_at.lt(cutoff)))
+                .select(id)
+                .load::<i64>(conn)?;
+
+            record_retention(
+                conn,
+                &stale,
+                user_state,
+                UserState::Unverified,
+                Some("deleted"),
+            )?;
+
+            let mut deleted = 0;
+            for batch in stale.chunks(BATCH_SIZE) {
+                diesel::delete(schema::outbox::table.filter(schema::outbox::user_id.eq_any(batch)))
+                    .execute(conn)?;
+
+                deleted += diesel::delete(users.filter(id.eq_any(batch))).execute(conn)?;
+            }
+
+            Ok(deleted)
+        })?;
+
+        Ok(res)
+    })
+    .await
+}
+
+/// Anonymise the users in `user_state` that haven't been updated since `cutoff`, by clearing
+/// their email and passcodes, deleting the emails waiting to be sent to them, and moving them back
+/// to `Unverified`. Returns the number of users anonymised.
+pub async fn anonymise_stale_users(
+    db: &Database,
+    user_state: UserState,
+    cutoff: DateTime<Utc>,
+) -> Result<usize> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let res = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let stale = users
+                .filter(state.eq(user_state).and(updated_at.lt(cutoff)))
+                .select(id)
+                .load::<i64>(conn)?;
+
+            // Moving back to `Unverified` is a state transition, so it goes in the audit trail.
+            if user_state != UserState::Unverified {
+                record_retention(conn, &stale, user_state, UserState::Unverified, None)?;
+            }
+
+            let mut anonymised = 0;
+            for batch in stale.chunks(BATCH_SIZE) {
+                diesel::delete(schema::outbox::table.filter(schema::outbox::user_id.eq_any(batch)))
+                    .execute(conn)?;
+
+                anonymised += diesel::update(users.filter(id.eq_any(batch)))
+                    .set((
+                        imperial_email.eq(None::<String>),
+                        imperial_email_ciphertext.eq(None::<Vec<u8>>),
+                        imperial_email_key_id.eq(None::<String>),
+                        imperial_email_index.eq(None::<Vec<u8>>),
+                        otps.eq(Vec::<Option<i32>>::new()),
+                        otp_sent_at.eq(None::<DateTime<Utc>>),
+                        state.eq(UserState::Unverified),
+                    ))
+                    .execute(conn)?;
+            }
+
+            Ok(anonymised)
+        })?;
+
+        Ok(res)
+    })
+    .await
+}
@@ -1,15 +1,83 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "audit_method"))]
+    pub struct AuditMethod;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "outbox_status"))]
+    pub struct OutboxStatus;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "user_state"))]
     pub struct UserState;
 }
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Int8,
+        guild_id -> Int8,
+        name -> Varchar,
+        token_hash -> Bytea,
+        created_by -> Nullable<Int8>,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UserState;
+    use super::sql_types::AuditMethod;
+
+    audit_events (id) {
+        id -> Int8,
+        actor_id -> Nullable<Int8>,
+        subject_id -> Int8,
+        guild_id -> Nullable<Int8>,
+        old_state -> Nullable<UserState>,
+        new_state -> Nullable<UserState>,
+        method -> AuditMethod,
+        created_at -> Timestamptz,
+        reason -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::OutboxStatus;
+
+    outbox (id) {
+        id -> Int8,
+        user_id -> Int8,
+        status -> OutboxStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamptz,
+        last_error -> Nullable<Varchar>,
+        message_ciphertext -> Nullable<Bytea>,
+        message_key_id -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     servers (id) {
         id -> Int8,
         verified_role_id -> Nullable<Int8>,
+        log_channel_id -> Nullable<Int8>,
+        show_emails -> Bool,
+        whois_role_id -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    tombstones (id) {
+        id -> Int8,
+        discord_id_hash -> Bytea,
+        email_hash -> Nullable<Bytea>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -22,10 +90,20 @@ diesel::table! {
         imperial_email -> Nullable<Varchar>,
         state -> UserState,
         otps -> Array<Nullable<Int4>>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        imperial_email_ciphertext -> Nullable<Bytea>,
+        imperial_email_key_id -> Nullable<Varchar>,
+        imperial_email_index -> Nullable<Bytea>,
+        otp_sent_at -> Nullable<Timestamptz>,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_events,
+    outbox,
     servers,
+    tombstones,
     users,
 );
@@ -1,71 +1,146 @@
+use crate::db::backend::{each_backend, AnyConnection};
 use crate::db::models::*;
-use crate::db::{schema, PG_CONNECTION};
-use crate::errors::{Error, Result};
+use crate::db::{schema, Database};
+use crate::errors::Result;
 use diesel::prelude::*;
 use poise::serenity_prelude as serenity;
-use serenity::{GuildId, RoleId};
-use std::ops::DerefMut;
+use serenity::{ChannelId, GuildId, RoleId};
 
-/// Check if a server exists in the database.
-pub async fn server_exists(guild_id: GuildId) -> Result<bool> {
+/// Create the server in the database if it doesn't exist yet.
+fn create_server_if_missing(conn: &mut AnyConnection, guild_id: GuildId) -> Result<()> {
     use schema::servers::dsl::*;
 
-    match servers
-        .find(i64::from(guild_id))
-        .first::<Server>(PG_CONNECTION.lock().await.deref_mut())
-    {
-        Ok(_) => Ok(true),
-        Err(diesel::result::Error::NotFound) => Ok(false),
-        Err(err) => Err(Error::Db(err)),
-    }
+    let new_server = NewServer {
+        id: i64::from(guild_id),
+    };
+
+    each_backend!(conn, |conn| {
+        diesel::insert_into(servers)
+            .values(&new_server)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    });
+
+    Ok(())
 }
 
 /// Set the verified role for the server.
-pub async fn set_verified_role(guild_id: GuildId, role_id: RoleId) -> Result<()> {
-    use schema::servers::dsl::*;
+pub async fn set_verified_role(db: &Database, guild_id: GuildId, role_id: RoleId) -> Result<()> {
+    db.run(move |conn| {
+        use schema::servers::dsl::*;
 
-    // Check if the server exists.
-    if !server_exists(guild_id).await? {
-        // If it doesn't exist, create it.
-        diesel::insert_into(servers)
-            .values(&NewServer {
-                id: i64::from(guild_id),
-            })
-            .execute(PG_CONNECTION.lock().await.deref_mut())?;
-    }
-
-    // Update the verified role.
-    diesel::update(servers.find(i64::from(guild_id)))
-        .set(verified_role_id.eq(Some(i64::from(role_id))))
-        .execute(PG_CONNECTION.lock().await.deref_mut())?;
-    Ok(())
+        create_server_if_missing(conn, guild_id)?;
+
+        // Update the verified role.
+        diesel::update(servers.find(i64::from(guild_id)))
+            .set(verified_role_id.eq(Some(i64::from(role_id))))
+            .execute(conn)?;
+        Ok(())
+    })
+    .await
 }
 
 /// Get the verified role for the server.
-pub async fn get_verified_role(guild_id: GuildId) -> Result<Option<RoleId>> {
-    use schema::servers::dsl::*;
+pub async fn get_verified_role(db: &Database, guild_id: GuildId) -> Result<Option<RoleId>> {
+    // If the server doesn't exist, it doesn't have a verified role.
+    let server = get_server(db, guild_id).await?;
 
-    // Check if the server exists.
-    if !server_exists(guild_id).await? {
-        // If it doesn't exist, return None.
-        return Ok(None);
-    }
+    Ok(server
+        .and_then(|server| server.verified_role_id)
+        .map(|_id| RoleId::new(_id as u64)))
+}
+
+/// Get all the servers with verified roles.
+pub async fn get_servers_with_verified_roles(db: &Database) -> Result<Vec<Server>> {
+    db.run(|conn| {
+        use schema::servers::dsl::*;
 
-    // Get the verified role.
-    let server: Server = servers
-        .find(i64::from(guild_id))
-        .first(PG_CONNECTION.lock().await.deref_mut())?;
+        let res = servers.filter(verified_role_id.is_not_null()).load(conn)?;
 
-    Ok(server.verified_role_id.map(|_id| RoleId::new(_id as u64)))
+        Ok(res)
+    })
+    .await
 }
 
-/// Get all the servers with verified roles.
-pub async fn get_servers_with_verified_roles() -> Result<Vec<Server>> {
-    use schema::servers::dsl::*;
+/// Set (or clear) the channel that verification events are logged to.
+pub async fn set_log_channel(
+    db: &Database,
+    guild_id: GuildId,
+    channel_id: Option<ChannelId>,
+) -> Result<()> {
+    db.run(move |conn| {
+        use schema::servers::dsl::*;
+
+        create_server_if_missing(conn, guild_id)?;
+
+        diesel::update(servers.find(i64::from(guild_id)))
+            .set(log_channel_id.eq(channel_id.map(i64::from)))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Set whether emails are shown unredacted in the server's log channel.
+pub async fn set_show_emails(db: &Database, guild_id: GuildId, show: bool) -> Result<()> {
+    db.run(move |conn| {
+        use schema::servers::dsl::*;
+
+        create_server_if_missing(conn, guild_id)?;
+
+        diesel::update(servers.find(i64::from(guild_id)))
+            .set(show_emails.eq(show))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Set (or clear) the role allowed to look up members' emails with `/whois`.
+pub async fn set_whois_role(
+    db: &Database,
+    guild_id: GuildId,
+    role_id: Option<RoleId>,
+) -> Result<()> {
+    db.run(move |conn| {
+        use schema::servers::dsl::*;
+
+        create_server_if_missing(conn, guild_id)?;
+
+        diesel::update(servers.find(i64::from(guild_id)))
+            .set(whois_role_id.eq(role_id.map(i64::from)))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+}
+
+/// Get the server entry, if the server exists.
+pub async fn get_server(db: &Database, guild_id: GuildId) -> Result<Option<Server>> {
+    db.run(move |conn| {
+        use schema::servers::dsl::*;
+
+        let res = servers
+            .find(i64::from(guild_id))
+            .first::<Server>(conn)
+            .optional()?;
+
+        Ok(res)
+    })
+    .await
+}
+
+/// Get all the servers with log channels.
+pub async fn get_servers_with_log_channels(db: &Database) -> Result<Vec<Server>> {
+    db.run(|conn| {
+        use schema::servers::dsl::*;
 
-    let res = servers
-        .filter(verified_role_id.is_not_null())
-        .load(PG_CONNECTION.lock().await.deref_mut())?;
+        let res = servers.filter(log_channel_id.is_not_null()).load(conn)?;
 
-    Ok(res)
+        Ok(res)
+    })
+    .await
 }
@@ -0,0 +1,302 @@
+use super::backend::{each_backend, AnyConnection};
+use super::models::*;
+use super::schema::{self, audit_events, users};
+use super::Database;
+use crate::errors::Result;
+use chrono::{DateTime, Utc};
+use diesel::connection::SimpleConnection;
+use diesel::dsl::count_star;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Double, Nullable};
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::SqliteConnection;
+use poise::serenity_prelude as serenity;
+use serenity::GuildId;
+
+/// How many member IDs are inserted into [`stats_members`] in each query, to stay well under the
+/// number of parameters a query can have.
+const BATCH_SIZE: usize = 1000;
+
+diesel::table! {
+    /// The IDs of the members of the server the statistics are for. Big servers have too many
+    /// members to list in a query, so they are put in this temporary table to be joined instead.
+    stats_members (id) {
+        id -> Int8,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(stats_members, audit_events);
+diesel::allow_tables_to_appear_in_same_query!(stats_members, users);
+
+/// The unit that verifications are counted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsPeriod {
+    Day,
+    Week,
+}
+
+/// Verification statistics for a server's members.
+#[derive(Debug)]
+pub struct VerificationStats {
+    /// The number of members in each state. Members without an entry are not counted.
+    pub state_counts: Vec<(UserState, i64)>,
+    /// The number of verifications in each period, oldest first. Periods without any
+    /// verifications are left out.
+    pub verifications: Vec<(DateTime<Utc>, i64)>,
+    /// The median time, in seconds, from joining the server to being verified.
+    pub median_seconds_to_verify: Option<f64>,
+    /// The number of emails submitted, and how many of those were rejected.
+    pub email_attempts: i64,
+    pub email_failures: i64,
+    /// The number of passcodes submitted, and how many of those were rejected.
+    pub otp_attempts: i64,
+    pub otp_failures: i64,
+}
+
+/// The parts of the statistics queries that each database writes differently.
+trait Dialect {
+    /// Query parameter `n`, counting from 1.
+    fn param(&self, n: usize) -> String;
+
+    /// The start of the period (in UTC) that `column` is in, in seconds since the epoch. Weeks
+    /// start on Monday.
+    fn period_start(&self, column: &str, period: StatsPeriod) -> String;
+
+    /// Whether `column` is at or after the time in query parameter `n`, given in seconds since
+    /// the epoch.
+    fn at_or_after(&self, column: &str, n: usize) -> String;
+
+    /// The number of seconds from the time in `from` to the time in `to`.
+    fn seconds_between(&self, from: &str, to: &str) -> String;
+
+    /// A query for the median of the `seconds` column of the `durations` table.
+    fn median_of_durations(&self) -> &'static str;
+}
+
+impl Dialect for PgConnection {
+    fn param(&self, n: usize) -> String {
+        format!("${}", n)
+    }
+
+    fn period_start(&self, column: &str, period: StatsPeriod) -> String {
+        let unit = match period {
+            StatsPeriod::Day => "day",
+            StatsPeriod::Week => "week",
+        };
+
+        format!(
+            "extract(epoch FROM date_trunc('{}', {} AT TIME ZONE 'UTC'))::bigint",
+            unit, column
+        )
+    }
+
+    fn at_or_after(&self, column: &str, n: usize) -> String {
+        format!("{} >= to_timestamp(${})", column, n)
+    }
+
+    fn seconds_between(&self, from: &str, to: &str) -> String {
+        format!("extract(epoch FROM {} - {})::double precision", to, from)
+    }
+
+    fn median_of_durations(&self) -> &'static str {
+        "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY seconds) AS median FROM durations"
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Dialect for SqliteConnection {
+    fn param(&self, _n: usize) -> String {
+        "?".to_string()
+    }
+
+    fn period_start(&self, column: &str, period: StatsPeriod) -> String {
+        match period {
+            StatsPeriod::Day => format!("unixepoch(date({}))", column),
+            // Move forward to the end of the week (Sunday), then back to its start.
+            StatsPeriod::Week => format!("unixepoch(date({}, 'weekday 0', '-6 days'))", column),
+        }
+    }
+
+    fn at_or_after(&self, column: &str, _n: usize) -> String {
+        format!("unixepoch({}) >= ?", column)
+    }
+
+    fn seconds_between(&self, from: &str, to: &str) -> String {
+        format!(
+            "unixepoch({}, 'subsec') - unixepoch({}, 'subsec')",
+            to, from
+        )
+    }
+
+    // SQLite has no percentile functions, so average the middle one or two durations.
+    fn median_of_durations(&self) -> &'static str {
+        "SELECT avg(seconds) AS median
+        FROM (
+            SELECT seconds,
+                row_number() OVER (ORDER BY seconds) AS position,
+                count(*) OVER () AS total
+            FROM durations
+        ) ranked
+        WHERE position IN ((total + 1) / 2, (total + 2) / 2)"
+    }
+}
+
+#[derive(QueryableByName)]
+struct PeriodCount {
+    /// The start of the period, in seconds since the epoch.
+    #[diesel(sql_type = BigInt)]
+    period: i64,
+    #[diesel(sql_type = BigInt)]
+    verifications: i64,
+}
+
+#[derive(QueryableByName)]
+struct Median {
+    #[diesel(sql_type = Nullable<Double>)]
+    median: Option<f64>,
+}
+
+/// A verification is a transition to verified from any other state.
+const IS_VERIFICATION: &str =
+    "new_state = 'verified' AND (old_state IS NULL OR old_state <> 'verified')";
+
+/// Get the verification statistics of a server, given the IDs of its members. Verifications are
+/// counted per `period` since `since`.
+pub async fn get_verification_stats(
+    db: &Database,
+    server_id: GuildId,
+    member_ids: &[i64],
+    period: StatsPeriod,
+    since: DateTime<Utc>,
+) -> Result<VerificationStats> {
+    let member_ids = member_ids.to_vec();
+
+    db.run(move |conn| {
+        // The temporary table is dropped at the end, or when rolling back if anything fails, so
+        // that it isn't left behind on the pooled connection.
+        let stats = conn.transaction(|conn| {
+            conn.batch_execute("CREATE TEMPORARY TABLE stats_members (id BIGINT PRIMARY KEY)")?;
+            for batch in member_ids.chunks(BATCH_SIZE) {
+                let rows = batch
+                    .iter()
+                    .map(|&member_id| stats_members::id.eq(member_id))
+                    .collect::<Vec<_>>();
+
+                // `AnyConnection` can't insert several rows at once, but each backend can.
+                each_backend!(&mut *conn, |conn| {
+                    diesel::insert_into(stats_members::table)
+                        .values(&rows)
+                        .execute(conn)?;
+                });
+            }
+
+            let stats = query_stats(conn, server_id, period, since)?;
+            conn.batch_execute("DROP TABLE stats_members")?;
+
+            diesel::QueryResult::Ok(stats)
+        })?;
+
+        Ok(stats)
+    })
+    .await
+}
+
+/// Get the verification statistics of the members in [`stats_members`].
+fn query_stats(
+    conn: &mut AnyConnection,
+    server_id: GuildId,
+    period: StatsPeriod,
+    since: DateTime<Utc>,
+) -> diesel::QueryResult<VerificationStats> {
+    let members = stats_members::table.select(stats_members::id);
+
+    let mut state_counts = {
+        use schema::users::dsl::*;
+
+        users
+            .filter(id.eq_any(members))
+            .group_by(state)
+            .select((state, count_star()))
+            .load::<(UserState, i64)>(conn)?
+    };
+    // SQLite stores states as text, so it can't sort them in order.
+    state_counts.sort_by_key(|&(user_state, _)| user_state as i32);
+
+    let verifications = each_backend!(&mut *conn, |conn| {
+        let query = format!(
+            "SELECT {} AS period, count(*) AS verifications
+            FROM audit_events
+            WHERE {}
+                AND subject_id IN (SELECT id FROM stats_members)
+                AND {}
+            GROUP BY period
+            ORDER BY period",
+            conn.period_start("created_at", period),
+            IS_VERIFICATION,
+            conn.at_or_after("created_at", 1),
+        );
+
+        diesel::sql_query(query)
+            .bind::<BigInt, _>(since.timestamp())
+            .load::<PeriodCount>(conn)?
+    });
+    let verifications = verifications
+        .into_iter()
+        .filter_map(|row| Some((DateTime::from_timestamp(row.period, 0)?, row.verifications)))
+        .collect();
+
+    // Pair each time someone joined this server with the first verification after it.
+    let median_seconds_to_verify = each_backend!(&mut *conn, |conn| {
+        let query = format!(
+            "WITH joins AS (
+                SELECT joined.created_at AS joined_at, (
+                    SELECT min(created_at)
+                    FROM audit_events
+                    WHERE subject_id = joined.subject_id
+                        AND {}
+                        AND created_at >= joined.created_at
+                ) AS verified_at
+                FROM audit_events joined
+                WHERE joined.method = 'member_join' AND joined.guild_id = {}
+            ),
+            durations AS (
+                SELECT {} AS seconds FROM joins WHERE verified_at IS NOT NULL
+            )
+            {}",
+            IS_VERIFICATION,
+            conn.param(1),
+            conn.seconds_between("joined_at", "verified_at"),
+            conn.median_of_durations(),
+        );
+
+        diesel::sql_query(query)
+            .bind::<BigInt, _>(i64::from(server_id))
+            .get_result::<Median>(conn)?
+            .median
+    });
+
+    let count_method = |conn: &mut AnyConnection, audit_method: AuditMethod| {
+        use schema::audit_events::dsl::*;
+
+        audit_events
+            .filter(subject_id.eq_any(members).and(method.eq(audit_method)))
+            .count()
+            .get_result::<i64>(conn)
+    };
+
+    let email_failures = count_method(conn, AuditMethod::EmailRejected)?;
+    let email_attempts = count_method(conn, AuditMethod::SetEmail)? + email_failures;
+    let otp_failures = count_method(conn, AuditMethod::OtpRejected)?;
+    let otp_attempts = count_method(conn, AuditMethod::Otp)? + otp_failures;
+
+    Ok(VerificationStats {
+        state_counts,
+        verifications,
+        median_seconds_to_verify,
+        email_attempts,
+        email_failures,
+        otp_attempts,
+        otp_failures,
+    })
+}
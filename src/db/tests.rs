@@ -0,0 +1,631 @@
+//! The database code run against an in-memory SQLite database, which also checks that the SQLite
+//! migrations match the schema.
+
+use super::models::{AuditMethod, UserState};
+use super::*;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use poise::serenity_prelude::{GuildId, RoleId, UserId};
+
+const USER: UserId = UserId::new(1);
+const OTHER_USER: UserId = UserId::new(2);
+const SERVER: GuildId = GuildId::new(10);
+
+/// Stored emails and outbox messages are encrypted, so set up throwaway keys.
+fn encryption_keys() {
+    crate::config::init_for_tests();
+}
+
+async fn database() -> Database {
+    // Every connection to `:memory:` gets its own database, so only keep one open.
+    let db = Database::open("sqlite://:memory:", 1);
+    run_pending_migrations(&db).await.unwrap();
+
+    db
+}
+
+/// Take a user from joining up to being verified.
+async fn verify(db: &Database, user_id: UserId) {
+    create_user(db, user_id).await.unwrap();
+    set_user_state(
+        db,
+        user_id,
+        UserState::QueryingEmail,
+        None,
+        Some(SERVER),
+        AuditMethod::MemberJoin,
+    )
+    .await
+    .unwrap();
+    set_user_state(
+        db,
+        user_id,
+        UserState::Verified,
+        Some(user_id),
+        None,
+        AuditMethod::Otp,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn migrations_are_up_to_date() {
+    let db = database().await;
+
+    assert!(check_migrations(&db).await.unwrap().is_up_to_date());
+}
+
+#[tokio::test]
+async fn new_users_have_no_otps() {
+    let db = database().await;
+
+    let user = create_user(&db, USER).await.unwrap();
+
+    assert_eq!(user.state, UserState::Unverified);
+    assert!(user.otps.is_empty());
+}
+
+#[tokio::test]
+async fn otps_are_stored_until_cleared() {
+    let db = database().await;
+    create_user(&db, USER).await.unwrap();
+
+    insert_otp(&db, USER, 123456).await.unwrap();
+    insert_otp(&db, USER, 654321).await.unwrap();
+
+    assert!(otp_exists_for_user(&db, USER, 123456).await.unwrap());
+    assert!(otp_exists_for_user(&db, USER, 654321).await.unwrap());
+    assert!(!otp_exists_for_user(&db, USER, 111111).await.unwrap());
+    assert!(!otp_exists_for_user(&db, OTHER_USER, 123456).await.unwrap());
+
+    clear_otps(&db, USER).await.unwrap();
+
+    assert!(!otp_exists_for_user(&db, USER, 123456).await.unwrap());
+    assert!(get_user(&db, USER).await.unwrap().unwrap().otps.is_empty());
+}
+
+#[tokio::test]
+async fn otps_sent_at_is_kept_until_cleared() {
+    let db = database().await;
+    create_user(&db, USER).await.unwrap();
+    assert_eq!(get_otps_sent_at(&db, USER).await.unwrap(), None);
+
+    insert_otp(&db, USER, 123456).await.unwrap();
+    let sent_at = get_otps_sent_at(&db, USER).await.unwrap().unwrap();
+
+    // Other changes to the user don't count as sending the passcode again.
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    set_user_state(
+        &db,
+        USER,
+        UserState::QueryingOTP,
+        None,
+        None,
+        AuditMethod::Cli,
+    )
+    .await
+    .unwrap();
+    assert_eq!(get_otps_sent_at(&db, USER).await.unwrap(), Some(sent_at));
+
+    clear_otps(&db, USER).await.unwrap();
+    assert_eq!(get_otps_sent_at(&db, USER).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn state_changes_are_audited() {
+    let db = database().await;
+    let before = Utc::now() - Duration::seconds(1);
+
+    verify(&db, USER).await;
+
+    assert!(is_verified(&db, USER).await.unwrap());
+    let verified_at = get_verified_at(&db, USER).await.unwrap().unwrap();
+    assert!(verified_at > before && verified_at <= Utc::now());
+}
+
+#[tokio::test]
+async fn state_changes_of_missing_users_are_not_audited() {
+    let db = database().await;
+
+    let result = set_user_state(&db, USER, UserState::Verified, None, None, AuditMethod::Cli).await;
+
+    assert!(matches!(
+        result,
+        Err(crate::errors::Error::Db(diesel::result::Error::NotFound))
+    ));
+    assert_eq!(get_verified_at(&db, USER).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn otps_and_emails_of_missing_users_are_not_stored() {
+    let db = database().await;
+
+    for result in [
+        insert_otp(&db, USER, 123456).await,
+        set_imperial_email(&db, USER, "someone@imperial.ac.uk".to_string()).await,
+    ] {
+        assert!(matches!(
+            result,
+            Err(crate::errors::Error::Db(diesel::result::Error::NotFound))
+        ));
+    }
+    assert!(!user_exists(&db, USER).await.unwrap());
+}
+
+#[tokio::test]
+async fn whois_lookups_are_not_transitions() {
+    let db = database().await;
+    verify(&db, USER).await;
+
+    record_whois_lookup(&db, OTHER_USER, USER, SERVER, "spam")
+        .await
+        .unwrap();
+
+    let (events, total) = get_audit_events_for_user(&db, USER, SERVER, 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(total, 3);
+    assert_eq!(events[0].method, AuditMethod::WhoisLookup);
+    assert_eq!(events[0].old_state, Some(UserState::Verified));
+    assert_eq!(events[0].new_state, None);
+}
+
+#[tokio::test]
+async fn rejections_are_not_transitions() {
+    let db = database().await;
+    create_user(&db, USER).await.unwrap();
+
+    record_rejection(&db, USER, AuditMethod::EmailRejected)
+        .await
+        .unwrap();
+
+    let (events, _) = get_audit_events_for_user(&db, USER, SERVER, 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].old_state, Some(UserState::Unverified));
+    assert_eq!(events[0].new_state, None);
+}
+
+#[tokio::test]
+async fn server_settings_create_the_server() {
+    let db = database().await;
+
+    set_show_emails(&db, SERVER, true).await.unwrap();
+    set_verified_role(&db, SERVER, RoleId::new(99))
+        .await
+        .unwrap();
+
+    let server = get_server(&db, SERVER).await.unwrap().unwrap();
+    assert!(server.show_emails);
+    assert_eq!(server.verified_role_id, Some(99));
+}
+
+#[tokio::test]
+async fn stats_count_verifications() {
+    let db = database().await;
+    verify(&db, USER).await;
+    verify(&db, OTHER_USER).await;
+    create_user(&db, UserId::new(3)).await.unwrap();
+
+    let since = Utc::now() - Duration::days(7);
+    let stats = get_verification_stats(&db, SERVER, &[1, 2, 3], StatsPeriod::Day, since)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        stats.state_counts,
+        vec![(UserState::Unverified, 1), (UserState::Verified, 2)]
+    );
+    assert_eq!(stats.verifications.len(), 1);
+    assert_eq!(stats.verifications[0].1, 2);
+    assert!(stats.median_seconds_to_verify.is_some());
+}
+
+/// Record a state change as if it happened at `at`.
+async fn record_at(
+    db: &Database,
+    user_id: i64,
+    new: UserState,
+    how: AuditMethod,
+    server: Option<GuildId>,
+    at: DateTime<Utc>,
+) {
+    db.run(move |conn| {
+        use schema::audit_events::dsl::*;
+
+        diesel::insert_into(audit_events)
+            .values((
+                subject_id.eq(user_id),
+                guild_id.eq(server.map(i64::from)),
+                new_state.eq(Some(new)),
+                method.eq(how),
+                created_at.eq(at),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn stats_count_verifications_per_week() {
+    let db = database().await;
+    // A Monday.
+    let joined_at = Utc.with_ymd_and_hms(2024, 10, 14, 12, 0, 0).unwrap();
+
+    let verified_after = [
+        Duration::minutes(1),
+        // The Sunday at the end of the same week.
+        Duration::days(6) + Duration::hours(11),
+        // The next Monday.
+        Duration::days(7),
+    ];
+    for (user_id, after) in (1..).zip(verified_after) {
+        record_at(
+            &db,
+            user_id,
+            UserState::QueryingEmail,
+            AuditMethod::MemberJoin,
+            Some(SERVER),
+            joined_at,
+        )
+        .await;
+        record_at(
+            &db,
+            user_id,
+            UserState::Verified,
+            AuditMethod::Otp,
+            None,
+            joined_at + after,
+        )
+        .await;
+    }
+    // Someone who isn't a member.
+    record_at(
+        &db,
+        4,
+        UserState::Verified,
+        AuditMethod::Otp,
+        None,
+        joined_at,
+    )
+    .await;
+
+    let since = joined_at - Duration::days(1);
+    let stats = get_verification_stats(&db, SERVER, &[1, 2, 3], StatsPeriod::Week, since)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        stats.verifications,
+        vec![
+            (Utc.with_ymd_and_hms(2024, 10, 14, 0, 0, 0).unwrap(), 2),
+            (Utc.with_ymd_and_hms(2024, 10, 21, 0, 0, 0).unwrap(), 1),
+        ]
+    );
+    assert_eq!(
+        stats.median_seconds_to_verify,
+        Some(verified_after[1].num_seconds() as f64)
+    );
+}
+
+#[tokio::test]
+async fn stats_handle_servers_with_many_members() {
+    let db = database().await;
+    verify(&db, USER).await;
+
+    // More members than a query can have parameters.
+    let member_ids: Vec<i64> = (1..=50_000).collect();
+    let since = Utc::now() - Duration::days(7);
+    let stats = get_verification_stats(&db, SERVER, &member_ids, StatsPeriod::Day, since)
+        .await
+        .unwrap();
+
+    assert_eq!(stats.state_counts, vec![(UserState::Verified, 1)]);
+    assert_eq!(stats.verifications.len(), 1);
+
+    // The members are only kept for the query.
+    let stats = get_verification_stats(&db, SERVER, &[], StatsPeriod::Day, since)
+        .await
+        .unwrap();
+    assert!(stats.state_counts.is_empty());
+}
+
+#[tokio::test]
+async fn anonymising_resets_stale_users() {
+    let db = database().await;
+    verify(&db, USER).await;
+    insert_otp(&db, USER, 123456).await.unwrap();
+
+    let cutoff = Utc::now() + Duration::hours(1);
+    let anonymised = anonymise_stale_users(&db, UserState::Verified, cutoff)
+        .await
+        .unwrap();
+
+    assert_eq!(anonymised, 1);
+    let user = get_user(&db, USER).await.unwrap().unwrap();
+    assert_eq!(user.state, UserState::Unverified);
+    assert!(user.otps.is_empty());
+    assert_eq!(user.otp_sent_at, None);
+}
+
+#[tokio::test]
+async fn anonymising_removes_the_outbox_emails() {
+    encryption_keys();
+    let db = database().await;
+    verify(&db, USER).await;
+    enqueue_email(&db, USER, b"Hello!").await.unwrap();
+    enqueue_email(&db, USER, b"Hello again!").await.unwrap();
+    let due = get_due_emails(&db, 10).await.unwrap();
+    dead_letter_email(&db, due[0].id, "rejected".to_string())
+        .await
+        .unwrap();
+
+    let cutoff = Utc::now() + Duration::hours(1);
+    anonymise_stale_users(&db, UserState::Verified, cutoff)
+        .await
+        .unwrap();
+
+    // Neither the queued email nor the dead-lettered one is left.
+    assert_eq!(rows_referring_to(&db, USER).await[3], 0);
+}
+
+#[tokio::test]
+async fn rotating_keys_reencrypts_emails_and_the_outbox() {
+    encryption_keys();
+    let db = database().await;
+    verify(&db, USER).await;
+    set_imperial_email(&db, USER, "someone@imperial.ac.uk".to_string())
+        .await
+        .unwrap();
+    enqueue_email(&db, USER, b"Hello!").await.unwrap();
+
+    // Pretend both were encrypted with a key that has since been retired. The test config has
+    // it as `old`.
+    db.run(|conn| {
+        use diesel::prelude::*;
+        use schema::{outbox, users};
+
+        diesel::update(users::table)
+            .set(users::imperial_email_key_id.eq(Some("old")))
+            .execute(conn)?;
+        diesel::update(outbox::table)
+            .set(outbox::message_key_id.eq(Some("old")))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(reencrypt_emails_batch(&db, 10).await.unwrap(), 1);
+    assert_eq!(reencrypt_emails_batch(&db, 10).await.unwrap(), 0);
+    assert_eq!(reencrypt_outbox_batch(&db, 10).await.unwrap(), 1);
+    assert_eq!(reencrypt_outbox_batch(&db, 10).await.unwrap(), 0);
+
+    let user = get_user(&db, USER).await.unwrap().unwrap();
+    assert_eq!(user.imperial_email_key_id.as_deref(), Some("test"));
+    assert_eq!(
+        get_imperial_email(&db, USER).await.unwrap().as_deref(),
+        Some("someone@imperial.ac.uk")
+    );
+    let due = get_due_emails(&db, 10).await.unwrap();
+    assert_eq!(due[0].message_key_id.as_deref(), Some("test"));
+    assert_eq!(
+        decrypt_outbox_message(&due[0]).unwrap().as_deref(),
+        Some(&b"Hello!"[..])
+    );
+}
+
+#[tokio::test]
+async fn exports_keep_the_outbox() {
+    encryption_keys();
+    let db = database().await;
+    verify(&db, USER).await;
+    enqueue_email(&db, USER, b"Hello!").await.unwrap();
+    let due = get_due_emails(&db, 10).await.unwrap();
+    let retry_at = Utc::now() + Duration::minutes(1);
+    retry_email(&db, due[0].id, "timed out".to_string(), retry_at)
+        .await
+        .unwrap();
+    enqueue_email(&db, OTHER_USER, b"Hi!").await.unwrap();
+
+    let export = export_all(&db).await.unwrap();
+    let other_db = database().await;
+    let counts = import_all(&other_db, export).await.unwrap();
+
+    assert_eq!(counts.outbox, 2);
+    let outbox = export_all(&other_db).await.unwrap().outbox;
+    assert_eq!(outbox[0].attempts, 1);
+    assert_eq!(outbox[0].last_error.as_deref(), Some("timed out"));
+    let due = get_due_emails(&other_db, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(
+        decrypt_outbox_message(&due[0]).unwrap().as_deref(),
+        Some(&b"Hi!"[..])
+    );
+}
+
+#[tokio::test]
+async fn outbox_emails_are_retried_then_cleared() {
+    encryption_keys();
+    let db = database().await;
+
+    enqueue_email(&db, USER, b"Hello!").await.unwrap();
+
+    let due = get_due_emails(&db, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(
+        decrypt_outbox_message(&due[0]).unwrap().as_deref(),
+        Some(&b"Hello!"[..])
+    );
+
+    let retry_at = Utc::now() + Duration::minutes(1);
+    retry_email(&db, due[0].id, "timed out".to_string(), retry_at)
+        .await
+        .unwrap();
+    assert!(get_due_emails(&db, 10).await.unwrap().is_empty());
+
+    dead_letter_email(&db, due[0].id, "timed out".to_string())
+        .await
+        .unwrap();
+    enqueue_email(&db, OTHER_USER, b"Hi!").await.unwrap();
+    let due = get_due_emails(&db, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].user_id, 2);
+
+    mark_email_sent(&db, due[0].id).await.unwrap();
+    assert!(get_due_emails(&db, 10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn verifying_server_is_where_verification_started() {
+    let db = database().await;
+
+    assert_eq!(get_verifying_server(&db, USER).await.unwrap(), None);
+    verify(&db, USER).await;
+
+    assert_eq!(get_verifying_server(&db, USER).await.unwrap(), Some(SERVER));
+}
+
+#[tokio::test]
+async fn user_data_includes_their_emails() {
+    encryption_keys();
+    let db = database().await;
+    verify(&db, USER).await;
+    enqueue_email(&db, USER, b"Hello!").await.unwrap();
+    enqueue_email(&db, OTHER_USER, b"Hi!").await.unwrap();
+
+    let data = collect_user_data(&db, USER).await.unwrap();
+
+    assert_eq!(data.emails.len(), 1);
+    assert_eq!(data.emails[0].message.as_deref(), Some("Hello!"));
+    assert_eq!(data.audit_events.len(), 2);
+}
+
+/// Give a user everything that erasing them has to deal with: an email, audit events about them
+/// and about someone else they acted on, an API token and an email in the outbox.
+async fn user_with_data(db: &Database, user_id: UserId) {
+    verify(db, user_id).await;
+    set_imperial_email(db, user_id, "someone@imperial.ac.uk".to_string())
+        .await
+        .unwrap();
+
+    create_user(db, OTHER_USER).await.unwrap();
+    set_user_state(
+        db,
+        OTHER_USER,
+        UserState::QueryingEmail,
+        Some(user_id),
+        Some(SERVER),
+        AuditMethod::VerifyCommand,
+    )
+    .await
+    .unwrap();
+
+    create_api_token(db, SERVER, "bot", user_id).await.unwrap();
+    enqueue_email(db, user_id, b"Hello!").await.unwrap();
+}
+
+/// The number of rows in each table that still refer to a user.
+async fn rows_referring_to(db: &Database, user_id: UserId) -> [i64; 4] {
+    let user_id = i64::from(user_id);
+
+    db.run(move |conn| {
+        use diesel::prelude::*;
+        use schema::{api_tokens, audit_events, outbox, users};
+
+        Ok([
+            users::table.find(user_id).count().get_result(conn)?,
+            audit_events::table
+                .filter(
+                    audit_events::subject_id
+                        .eq(user_id)
+                        .or(audit_events::actor_id.eq(user_id)),
+                )
+                .count()
+                .get_result(conn)?,
+            api_tokens::table
+                .filter(api_tokens::created_by.eq(user_id))
+                .count()
+                .get_result(conn)?,
+            outbox::table
+                .filter(outbox::user_id.eq(user_id))
+                .count()
+                .get_result(conn)?,
+        ])
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn erasing_removes_everything_about_the_user() {
+    encryption_keys();
+    let db = database().await;
+    user_with_data(&db, USER).await;
+    assert_eq!(rows_referring_to(&db, USER).await, [1, 3, 1, 1]);
+
+    erase_user_data(&db, USER, false).await.unwrap();
+
+    assert_eq!(rows_referring_to(&db, USER).await, [0, 0, 0, 0]);
+    assert!(
+        !tombstones::email_is_tombstoned(&db, "someone@imperial.ac.uk")
+            .await
+            .unwrap()
+    );
+
+    // What they did to others is kept, but no longer says it was them.
+    let (events, _) = get_audit_events_for_user(&db, OTHER_USER, SERVER, 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor_id, None);
+
+    let tokens = get_api_tokens(&db, SERVER).await.unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].created_by, None);
+}
+
+#[tokio::test]
+async fn erasing_can_keep_a_tombstone() {
+    encryption_keys();
+    let db = database().await;
+    user_with_data(&db, USER).await;
+
+    erase_user_data(&db, USER, true).await.unwrap();
+
+    assert_eq!(rows_referring_to(&db, USER).await, [0, 0, 0, 0]);
+    assert!(
+        tombstones::email_is_tombstoned(&db, "Someone@imperial.ac.uk")
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn deleting_stale_users_is_audited() {
+    encryption_keys();
+    let db = database().await;
+    verify(&db, USER).await;
+    enqueue_email(&db, USER, b"Hello!").await.unwrap();
+
+    let cutoff = Utc::now() + Duration::hours(1);
+    let deleted = delete_stale_users(&db, UserState::Verified, cutoff)
+        .await
+        .unwrap();
+
+    assert_eq!(deleted, 1);
+    assert!(!user_exists(&db, USER).await.unwrap());
+    assert!(get_due_emails(&db, 10).await.unwrap().is_empty());
+
+    let (events, _) = get_audit_events_for_user(&db, USER, SERVER, 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(events[0].method, AuditMethod::Retention);
+    assert_eq!(events[0].old_state, Some(UserState::Verified));
+    assert_eq!(events[0].new_state, Some(UserState::Unverified));
+}
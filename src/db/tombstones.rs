@@ -0,0 +1,33 @@
+use super::models::*;
+use super::{schema, Database};
+use crate::config;
+use crate::errors::Result;
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Hash a value for storing in a tombstone. The key is secret, so that tombstoned identifiers can
+/// be matched but not reversed.
+pub(super) fn tombstone_hash(value: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&config::get().keys.tombstone)
+        .expect("HMAC accepts any key");
+    mac.update(value.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Check if an email belongs to an erased user whose tombstone has to be kept.
+pub async fn email_is_tombstoned(db: &Database, email: &str) -> Result<bool> {
+    let hash = tombstone_hash(&email.to_lowercase());
+
+    db.run(move |conn| {
+        use schema::tombstones::dsl::*;
+
+        let res = tombstones
+            .filter(email_hash.eq(hash))
+            .first::<Tombstone>(conn)
+            .optional()?;
+
+        Ok(res.is_some())
+    })
+    .await
+}
@@ -0,0 +1,211 @@
+//! Support for the column types that `AnyConnection` doesn't know about, because postgres and
+//! SQLite store them differently. The schema keeps postgres' types, and values are converted to
+//! and from each backend's own type here.
+
+use super::backend::MultiBackend;
+use super::models::{AuditMethod, OutboxStatus, UserState};
+use super::schema::sql_types;
+use chrono::{DateTime, Utc};
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::QueryId;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{self as diesel_types, HasSqlType, Nullable, SqlType};
+#[cfg(feature = "sqlite")]
+use diesel::sqlite::{Sqlite, SqliteType};
+
+/// A point in time. Postgres stores it as a `timestamptz`, and SQLite as text.
+#[derive(Debug, Clone, Copy, QueryId, SqlType)]
+pub struct Timestamptz;
+
+/// A user's passcodes. Postgres stores them as an `integer[]`, and SQLite as a JSON array.
+#[derive(Debug, Clone, Copy, QueryId, SqlType)]
+pub struct Otps;
+
+/// Lets `AnyConnection` use a column type, by handing values to whichever backend it's connected
+/// to as `$backend_type`.
+macro_rules! multi_backend_type {
+    ($sql_type:ty as $backend_type:path => $rust_type:ty) => {
+        impl HasSqlType<$sql_type> for MultiBackend {
+            fn metadata(lookup: &mut Self::MetadataLookup) -> Self::TypeMetadata {
+                MultiBackend::lookup_sql_type::<$backend_type>(lookup)
+            }
+        }
+
+        impl FromSql<$sql_type, MultiBackend> for $rust_type {
+            fn from_sql(
+                bytes: <MultiBackend as Backend>::RawValue<'_>,
+            ) -> deserialize::Result<Self> {
+                bytes.from_sql::<Self, $backend_type>()
+            }
+        }
+
+        impl ToSql<$sql_type, MultiBackend> for $rust_type {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, MultiBackend>) -> serialize::Result {
+                out.set_value(($backend_type, self));
+                Ok(IsNull::No)
+            }
+        }
+    };
+}
+
+/// Lets a rust enum be loaded from an `AnyConnection`.
+macro_rules! multi_backend_enum {
+    ($sql_type:path => $rust_type:ty) => {
+        multi_backend_type!($sql_type as $sql_type => $rust_type);
+
+        impl Queryable<$sql_type, MultiBackend> for $rust_type {
+            type Row = Self;
+
+            fn build(row: Self) -> deserialize::Result<Self> {
+                Ok(row)
+            }
+        }
+    };
+}
+
+/// Stores an enum in SQLite as text, using the same names as its postgres enum type.
+macro_rules! sqlite_text_enum {
+    ($sql_type:path => $rust_type:ident { $($variant:ident => $name:literal,)* }) => {
+        #[cfg(feature = "sqlite")]
+        impl HasSqlType<$sql_type> for Sqlite {
+            fn metadata(_: &mut ()) -> SqliteType {
+                SqliteType::Text
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl FromSql<$sql_type, Sqlite> for $rust_type {
+            fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+                match <String as FromSql<diesel_types::Text, Sqlite>>::from_sql(bytes)?.as_str() {
+                    $($name => Ok($rust_type::$variant),)*
+                    other => Err(format!("Unrecognized enum variant: '{}'", other).into()),
+                }
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl ToSql<$sql_type, Sqlite> for $rust_type {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+                out.set_value(match self {
+                    $($rust_type::$variant => $name,)*
+                });
+                Ok(IsNull::No)
+            }
+        }
+    };
+}
+
+impl HasSqlType<Timestamptz> for Pg {
+    fn metadata(lookup: &mut Self::MetadataLookup) -> Self::TypeMetadata {
+        <Pg as HasSqlType<diesel_types::Timestamptz>>::metadata(lookup)
+    }
+}
+
+impl FromSql<Timestamptz, Pg> for DateTime<Utc> {
+    fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        <Self as FromSql<diesel_types::Timestamptz, Pg>>::from_sql(bytes)
+    }
+}
+
+impl ToSql<Timestamptz, Pg> for DateTime<Utc> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <Self as ToSql<diesel_types::Timestamptz, Pg>>::to_sql(self, out)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl HasSqlType<Timestamptz> for Sqlite {
+    fn metadata(lookup: &mut Self::MetadataLookup) -> Self::TypeMetadata {
+        <Sqlite as HasSqlType<diesel_types::TimestamptzSqlite>>::metadata(lookup)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromSql<Timestamptz, Sqlite> for DateTime<Utc> {
+    fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        <Self as FromSql<diesel_types::TimestamptzSqlite, Sqlite>>::from_sql(bytes)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl ToSql<Timestamptz, Sqlite> for DateTime<Utc> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <Self as ToSql<diesel_types::TimestamptzSqlite, Sqlite>>::to_sql(self, out)
+    }
+}
+
+multi_backend_type!(diesel_types::Timestamptz as Timestamptz => DateTime<Utc>);
+
+impl HasSqlType<Otps> for Pg {
+    fn metadata(lookup: &mut Self::MetadataLookup) -> Self::TypeMetadata {
+        <Pg as HasSqlType<diesel_types::Array<Nullable<diesel_types::Integer>>>>::metadata(lookup)
+    }
+}
+
+impl FromSql<Otps, Pg> for Vec<Option<i32>> {
+    fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        <Self as FromSql<diesel_types::Array<Nullable<diesel_types::Integer>>, Pg>>::from_sql(bytes)
+    }
+}
+
+impl ToSql<Otps, Pg> for Vec<Option<i32>> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <Self as ToSql<diesel_types::Array<Nullable<diesel_types::Integer>>, Pg>>::to_sql(self, out)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl HasSqlType<Otps> for Sqlite {
+    fn metadata(_: &mut ()) -> SqliteType {
+        SqliteType::Text
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl FromSql<Otps, Sqlite> for Vec<Option<i32>> {
+    fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+        let json = <String as FromSql<diesel_types::Text, Sqlite>>::from_sql(bytes)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl ToSql<Otps, Sqlite> for Vec<Option<i32>> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(self)?);
+        Ok(IsNull::No)
+    }
+}
+
+multi_backend_type!(diesel_types::Array<Nullable<diesel_types::Integer>> as Otps => Vec<Option<i32>>);
+
+sqlite_text_enum!(sql_types::UserState => UserState {
+    Unverified => "unverified",
+    QueryingEmail => "querying_email",
+    QueryingOTP => "querying_otp",
+    Verified => "verified",
+});
+multi_backend_enum!(sql_types::UserState => UserState);
+
+sqlite_text_enum!(sql_types::AuditMethod => AuditMethod {
+    MemberJoin => "member_join",
+    VerifyCommand => "verify_command",
+    SetEmail => "set_email",
+    Otp => "otp",
+    Retention => "retention",
+    WhoisLookup => "whois_lookup",
+    EmailRejected => "email_rejected",
+    OtpRejected => "otp_rejected",
+    Cli => "cli",
+});
+multi_backend_enum!(sql_types::AuditMethod => AuditMethod);
+
+sqlite_text_enum!(sql_types::OutboxStatus => OutboxStatus {
+    Pending => "pending",
+    Sent => "sent",
+    Dead => "dead",
+});
+multi_backend_enum!(sql_types::OutboxStatus => OutboxStatus);
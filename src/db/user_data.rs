@@ -0,0 +1,157 @@
+use super::encryption::decrypt_user_email;
+use super::models::*;
+use super::outbox::decrypt_outbox_message;
+use super::tombstones::tombstone_hash;
+use super::users::get_imperial_email;
+use super::{schema, Database};
+use crate::errors::Result;
+use diesel::prelude::*;
+use poise::serenity_prelude as serenity;
+use serde::Serialize;
+use serenity::UserId;
+
+/// Everything stored about a user, for answering subject access requests.
+#[derive(Debug, Serialize)]
+pub struct UserData {
+    /// The user's discord ID.
+    pub discord_id: i64,
+    /// The user's entry, if they have one.
+    pub user: Option<User>,
+    /// The user's (decrypted) imperial email, if they have one.
+    pub imperial_email: Option<String>,
+    /// Every audit event where the user is either the subject or the actor.
+    pub audit_events: Vec<AuditEvent>,
+    /// The API tokens the user created for servers.
+    pub api_tokens: Vec<ApiToken>,
+    /// The emails sent (or waiting to be sent) to the user.
+    pub emails: Vec<UserEmail>,
+}
+
+/// An email in the outbox, with its message decrypted.
+#[derive(Debug, Serialize)]
+pub struct UserEmail {
+    #[serde(flatten)]
+    pub email: OutboxEmail,
+    /// The whole message, as it was (or will be) sent. Only kept until it has been sent or given
+    /// up on.
+    pub message: Option<String>,
+}
+
+/// Collect everything stored about a user.
+pub async fn collect_user_data(db: &Database, user_id: UserId) -> Result<UserData> {
+    db.run(move |conn| {
+        let user = schema::users::table
+            .find(i64::from(user_id))
+            .first::<User>(conn)
+            .optional()?;
+
+        let audit_events = {
+            use schema::audit_events::dsl::*;
+
+            audit_events
+                .filter(
+                    subject_id
+                        .eq(i64::from(user_id))
+                        .or(actor_id.eq(i64::from(user_id))),
+                )
+                .order((created_at.asc(), id.asc()))
+                .load(conn)?
+        };
+
+        let api_tokens = {
+            use schema::api_tokens::dsl::*;
+
+            api_tokens
+                .filter(created_by.eq(i64::from(user_id)))
+                .order(id)
+                .load(conn)?
+        };
+
+        let emails = schema::outbox::table
+            .filter(schema::outbox::user_id.eq(i64::from(user_id)))
+            .order(schema::outbox::id)
+            .load::<OutboxEmail>(conn)?;
+
+        let imperial_email = match &user {
+            Some(user) => decrypt_user_email(user)?,
+            None => None,
+        };
+
+        let emails = emails
+            .into_iter()
+            .map(|email| {
+                let message = decrypt_outbox_message(&email)?
+                    .map(|message| String::from_utf8_lossy(&message).into_owned());
+
+                Ok(UserEmail { email, message })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(UserData {
+            discord_id: i64::from(user_id),
+            user,
+            imperial_email,
+            audit_events,
+            api_tokens,
+            emails,
+        })
+    })
+    .await
+}
+
+/// Erase everything stored about a user. If `keep_tombstone` is set, a non-reversible tombstone
+/// of their discord ID and email is kept, so that the email can't be used to verify again.
+pub async fn erase_user_data(db: &Database, user_id: UserId, keep_tombstone: bool) -> Result<()> {
+    let email = if keep_tombstone {
+        get_imperial_email(db, user_id).await?
+    } else {
+        None
+    };
+
+    db.run(move |conn| {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            if keep_tombstone {
+                diesel::insert_into(schema::tombstones::table)
+                    .values(&NewTombstone {
+                        discord_id_hash: tombstone_hash(&user_id.to_string()),
+                        email_hash: email
+                            .as_ref()
+                            .map(|email| tombstone_hash(&email.to_lowercase())),
+                    })
+                    .execute(conn)?;
+            }
+
+            {
+                use schema::audit_events::dsl::*;
+
+                diesel::delete(audit_events.filter(subject_id.eq(i64::from(user_id))))
+                    .execute(conn)?;
+
+                // Events about other users that this user took part in are kept, but anonymised.
+                diesel::update(audit_events.filter(actor_id.eq(i64::from(user_id))))
+                    .set(actor_id.eq(None::<i64>))
+                    .execute(conn)?;
+            }
+
+            {
+                use schema::api_tokens::dsl::*;
+
+                diesel::update(api_tokens.filter(created_by.eq(i64::from(user_id))))
+                    .set(created_by.eq(None::<i64>))
+                    .execute(conn)?;
+            }
+
+            diesel::delete(
+                schema::outbox::table.filter(schema::outbox::user_id.eq(i64::from(user_id))),
+            )
+            .execute(conn)?;
+
+            diesel::delete(schema::users::table.find(i64::from(user_id))).execute(conn)?;
+
+            Ok(())
+        })?;
+
+        Ok(())
+    })
+    .await
+}
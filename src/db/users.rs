@@ -1,147 +1,307 @@
+use super::encryption::{active_key_id, decrypt_user_email, email_index, encrypt_email};
 use super::models::*;
-use super::{schema, PG_CONNECTION};
+use super::{schema, Database};
 use crate::errors::{Error, Result};
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use poise::serenity_prelude as serenity;
-use serenity::UserId;
-use std::ops::DerefMut;
+use serenity::{GuildId, UserId};
 
 /// Check if a discord user exists in the database.
-pub async fn user_exists(user_id: UserId) -> Result<bool> {
-    use schema::users::dsl::*;
-
-    match users
-        .find(i64::from(user_id))
-        .first::<User>(PG_CONNECTION.lock().await.deref_mut())
-    {
-        Ok(_) => Ok(true),
-        Err(diesel::result::Error::NotFound) => Ok(false),
-        Err(err) => Err(Error::Db(err)),
-    }
-    // TODO: Better error handling
+pub async fn user_exists(db: &Database, user_id: UserId) -> Result<bool> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        match users.find(i64::from(user_id)).first::<User>(conn) {
+            Ok(_) => Ok(true),
+            Err(diesel::result::Error::NotFound) => Ok(false),
+            Err(err) => Err(Error::Db(err)),
+        }
+        // TODO: Better error handling
+    })
+    .await
 }
 
-/// Check if a discord user is verified. If the user doesn't exist, return false.
-pub async fn is_verified(user_id: UserId) -> Result<bool> {
-    use schema::users::dsl::*;
+/// Get a discord user from the database, if they exist.
+pub async fn get_user(db: &Database, user_id: UserId) -> Result<Option<User>> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
 
-    if !user_exists(user_id).await? {
-        return Ok(false);
-    }
+        let u = users
+            .find(i64::from(user_id))
+            .first::<User>(conn)
+            .optional()?;
 
-    // The user must exist to reach here.
+        Ok(u)
+    })
+    .await
+}
 
-    let res = users
-        .find(i64::from(user_id))
-        .select(state)
-        .first::<UserState>(PG_CONNECTION.lock().await.deref_mut())
-        .map(|_state| _state == UserState::Verified)?;
+/// Get all the users in `user_ids` that exist in the database.
+pub async fn get_users(db: &Database, user_ids: &[i64]) -> Result<Vec<User>> {
+    let user_ids = user_ids.to_vec();
 
-    Ok(res)
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let res = users.filter(id.eq_any(user_ids)).load::<User>(conn)?;
+
+        Ok(res)
+    })
+    .await
 }
 
-/// Sets a user's state to `state`.
-pub async fn set_user_state(user_id: UserId, user_state: UserState) -> Result<()> {
-    use schema::users::dsl::*;
+/// Check if a discord user is verified. If the user doesn't exist, return false.
+pub async fn is_verified(db: &Database, user_id: UserId) -> Result<bool> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
 
-    diesel::update(users.find(i64::from(user_id)))
-        .set(state.eq(user_state))
-        .execute(PG_CONNECTION.lock().await.deref_mut())?;
+        let res = users
+            .find(i64::from(user_id))
+            .select(state)
+            .first::<UserState>(conn)
+            .optional()?;
 
-    Ok(())
+        Ok(res == Some(UserState::Verified))
+    })
+    .await
+}
+
+/// Sets a user's state to `state`, and records the transition in the audit trail. `actor` is
+/// whoever caused the transition (or `None` if the bot did it on its own), and `server` is the
+/// server it happened in, if any. Fails with `NotFound`, recording nothing, if the user doesn't
+/// exist.
+pub async fn set_user_state(
+    db: &Database,
+    user_id: UserId,
+    user_state: UserState,
+    actor: Option<UserId>,
+    server: Option<GuildId>,
+    method: AuditMethod,
+) -> Result<()> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let old_state = users
+                .find(i64::from(user_id))
+                .select(state)
+                .first::<UserState>(conn)?;
+
+            diesel::update(users.find(i64::from(user_id)))
+                .set(state.eq(user_state))
+                .execute(conn)?;
+
+            diesel::insert_into(schema::audit_events::table)
+                .values(&NewAuditEvent {
+                    actor_id: actor.map(i64::from),
+                    subject_id: i64::from(user_id),
+                    guild_id: server.map(i64::from),
+                    old_state: Some(old_state),
+                    new_state: Some(user_state),
+                    method,
+                    reason: None,
+                })
+                .execute(conn)?;
+
+            Ok(())
+        })?;
+
+        Ok(())
+    })
+    .await
 }
 
 /// Creates a new user entry given their discord ID, and returns the user object
-pub async fn create_user(user_id: UserId) -> Result<User> {
-    use schema::users::dsl::*;
+pub async fn create_user(db: &Database, user_id: UserId) -> Result<User> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
 
-    let new_user = NewUser {
-        id: i64::from(user_id),
-    };
+        let new_user = NewUser {
+            id: i64::from(user_id),
+        };
 
-    let u = diesel::insert_into(users)
-        .values(&new_user)
-        .get_result(PG_CONNECTION.lock().await.deref_mut())?;
+        let u = diesel::insert_into(users)
+            .values(&new_user)
+            .get_result(conn)?;
 
-    Ok(u)
+        Ok(u)
+    })
+    .await
 }
 
 /// Check if this email is already in use by a VERIFIED account.
-pub async fn email_exists(email: &str) -> Result<bool> {
-    use super::schema::users::dsl::*;
-
-    match users
-        .filter(
-            state
-                .eq(UserState::Verified)
-                .and(imperial_email.eq(Some(email))),
-        )
-        .first::<User>(PG_CONNECTION.lock().await.deref_mut())
-    {
-        Ok(_) => Ok(true),
-        Err(diesel::result::Error::NotFound) => Ok(false),
-        Err(err) => Err(Error::Db(err)),
+pub async fn email_exists(db: &Database, email: &str) -> Result<bool> {
+    let email = email.to_string();
+
+    db.run(move |conn| {
+        use super::schema::users::dsl::*;
+
+        match users
+            .filter(
+                state.eq(UserState::Verified).and(
+                    imperial_email_index
+                        .eq(Some(email_index(&email)))
+                        // Users stored before emails were encrypted.
+                        .or(imperial_email.eq(Some(&email))),
+                ),
+            )
+            .first::<User>(conn)
+        {
+            Ok(_) => Ok(true),
+            Err(diesel::result::Error::NotFound) => Ok(false),
+            Err(err) => Err(Error::Db(err)),
+        }
+    })
+    .await
+}
+
+/// Sets the user's imperial email. The email is stored encrypted. Fails with `NotFound` if the
+/// user doesn't exist.
+pub async fn set_imperial_email(db: &Database, user_id: UserId, email: String) -> Result<()> {
+    let encrypted = encrypt_email(i64::from(user_id), &email)?;
+
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let updated = diesel::update(users.find(i64::from(user_id)))
+            .set((
+                imperial_email.eq(None::<String>),
+                imperial_email_ciphertext.eq(Some(encrypted.ciphertext)),
+                imperial_email_key_id.eq(Some(encrypted.key_id)),
+                imperial_email_index.eq(Some(encrypted.index)),
+            ))
+            .execute(conn)?;
+
+        if updated == 0 {
+            return Err(Error::Db(diesel::result::Error::NotFound));
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+/// Gets the user's (decrypted) imperial email, if they have one.
+pub async fn get_imperial_email(db: &Database, user_id: UserId) -> Result<Option<String>> {
+    match get_user(db, user_id).await? {
+        Some(user) => Ok(decrypt_user_email(&user)?),
+        None => Ok(None),
     }
 }
 
-/// Sets the user's imperial email.
-pub async fn set_imperial_email(user_id: UserId, email: String) -> Result<()> {
-    use schema::users::dsl::*;
+/// Re-encrypts up to `batch_size` emails that are stored in plaintext or with a key other than
+/// the active one. Returns the number of emails re-encrypted, so this should be called until it
+/// returns 0.
+pub async fn reencrypt_emails_batch(db: &Database, batch_size: i64) -> Result<usize> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let batch = users
+            .filter(
+                imperial_email.is_not_null().or(imperial_email_ciphertext
+                    .is_not_null()
+                    .and(imperial_email_key_id.ne(active_key_id()))),
+            )
+            .order(id)
+            .limit(batch_size)
+            .load::<User>(conn)?;
 
-    diesel::update(users.find(i64::from(user_id)))
-        .set(imperial_email.eq(Some(email)))
-        .execute(PG_CONNECTION.lock().await.deref_mut())?;
+        let mut updates = Vec::with_capacity(batch.len());
+        for user in &batch {
+            if let Some(email) = decrypt_user_email(user)? {
+                updates.push((user.id, encrypt_email(user.id, &email)?));
+            }
+        }
 
-    Ok(())
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            for (user_id, encrypted) in updates {
+                diesel::update(users.find(user_id))
+                    .set((
+                        imperial_email.eq(None::<String>),
+                        imperial_email_ciphertext.eq(Some(encrypted.ciphertext)),
+                        imperial_email_key_id.eq(Some(encrypted.key_id)),
+                        imperial_email_index.eq(Some(encrypted.index)),
+                    ))
+                    .execute(conn)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(batch.len())
+    })
+    .await
 }
 
-/// Inserts an OTP into a user's OTPs.
-pub async fn insert_otp(user_id: UserId, otp: i32) -> Result<()> {
-    use schema::users::dsl::*;
+/// Inserts an OTP into a user's OTPs. Fails with `NotFound` if the user doesn't exist.
+pub async fn insert_otp(db: &Database, user_id: UserId, otp: i32) -> Result<()> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
 
-    diesel::update(users.find(i64::from(user_id)))
-        .set(otps.eq(otps.concat(vec![Some(otp)])))
-        .execute(PG_CONNECTION.lock().await.deref_mut())?;
+        // Not every database can append to an array in SQL, so read and write them instead.
+        conn.transaction(|conn| {
+            let mut user_otps = users
+                .find(i64::from(user_id))
+                .select(otps)
+                .first::<Vec<Option<i32>>>(conn)?;
+            user_otps.push(Some(otp));
 
-    Ok(())
+            diesel::update(users.find(i64::from(user_id)))
+                .set((otps.eq(user_otps), otp_sent_at.eq(Some(Utc::now()))))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    })
+    .await
 }
 
 /// Check if an OTP exists in a user's OTPs.
-pub async fn otp_exists_for_user(user_id: UserId, otp: i32) -> Result<bool> {
-    use schema::users::dsl::*;
-
-    match users
-        .find(i64::from(user_id))
-        .select(otps)
-        .first::<Vec<Option<i32>>>(PG_CONNECTION.lock().await.deref_mut())
-        .map(|_otps| _otps.contains(&Some(otp)))
-    {
-        Ok(_) => Ok(true),
-        Err(diesel::result::Error::NotFound) => Ok(false),
-        Err(err) => Err(Error::Db(err)),
-    }
+pub async fn otp_exists_for_user(db: &Database, user_id: UserId, otp: i32) -> Result<bool> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
+
+        let res = users
+            .find(i64::from(user_id))
+            .select(otps)
+            .first::<Vec<Option<i32>>>(conn)
+            .optional()?;
+
+        Ok(res.is_some_and(|user_otps| user_otps.contains(&Some(otp))))
+    })
+    .await
 }
 
-/// Clear all the user's OTPs.
-pub async fn clear_otps(user_id: UserId) -> Result<()> {
-    use schema::users::dsl::*;
+/// When the user's OTPs were last sent, if they have any.
+pub async fn get_otps_sent_at(db: &Database, user_id: UserId) -> Result<Option<DateTime<Utc>>> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
 
-    diesel::update(users.find(i64::from(user_id)))
-        .set(otps.eq::<Vec<i32>>(vec![]))
-        .execute(PG_CONNECTION.lock().await.deref_mut())?;
+        let res = users
+            .find(i64::from(user_id))
+            .select(otp_sent_at)
+            .first::<Option<DateTime<Utc>>>(conn)
+            .optional()?;
 
-    Ok(())
+        Ok(res.flatten())
+    })
+    .await
 }
 
-/*
-/// Gets all the verified users.
-pub async fn get_verified() -> Result<Vec<User>> {
-    use schema::users::dsl::*;
+/// Clear all the user's OTPs.
+pub async fn clear_otps(db: &Database, user_id: UserId) -> Result<()> {
+    db.run(move |conn| {
+        use schema::users::dsl::*;
 
-    let u = users
-        .filter(state.eq(UserState::Verified))
-        .load(PG_CONNECTION.lock().await.deref_mut())?;
+        diesel::update(users.find(i64::from(user_id)))
+            .set((
+                otps.eq(Vec::<Option<i32>>::new()),
+                otp_sent_at.eq(None::<DateTime<Utc>>),
+            ))
+            .execute(conn)?;
 
-    Ok(u)
+        Ok(())
+    })
+    .await
 }
-*/
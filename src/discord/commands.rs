@@ -1,20 +1,28 @@
 use super::{
-    roles::{set_verified_role_for_verified_on_single_server, verify_on_all_servers},
+    erasure::erase_user,
+    get_members,
+    log_channel::{log_event, log_event_for_member, LogEvent},
+    roles::{
+        log_failed_updates, set_verified_role_for_verified_on_single_server, verify_on_all_servers,
+    },
     Context, Error,
 };
+use crate::attestation;
 use crate::db::models::*;
-use crate::db::{
-    clear_otps, create_user, email_exists, insert_otp, is_verified, otp_exists_for_user,
-    set_imperial_email, set_user_state, set_verified_role as set_verified_role_db, user_exists,
-};
-use crate::mail::MAILER;
-use lettre::message::header::ContentType;
-use lettre::{Message, Transport};
+use crate::db::StatsPeriod;
+use crate::mail::VerificationEmail;
+use crate::metrics;
+use crate::verification::{self, EmailCheck, OtpCheck, Start};
+use chrono::{TimeDelta, Utc};
+use lettre::Message;
 use log::info;
-use poise::serenity_prelude::{self as serenity, CreateMessage};
-use rand::Rng;
-use std::env;
-use std::ops::DerefMut;
+use poise::serenity_prelude::{
+    self as serenity, ButtonStyle, ComponentInteractionCollector, CreateActionRow,
+    CreateAttachment, CreateButton, CreateEmbed, CreateInteractionResponse, CreateMessage,
+    Mentionable,
+};
+use poise::CreateReply;
+use std::time::Duration;
 
 /// Starts the process of verifying a user.
 #[poise::command(slash_command, guild_only)]
@@ -22,33 +30,48 @@ pub async fn verify(
     ctx: Context<'_>,
     #[description = "User to verify"] user: Option<serenity::User>,
 ) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
     let user = user.unwrap_or_else(|| ctx.author().clone());
+    let guild_id = ctx.guild_id().unwrap();
 
-    // If the user exists, do not insert a new user.
-    if user_exists(user.id)
-        .await
-        .expect("Error checking if user exists")
-    {
-        // If a user with the same discord ID is verified, do not insert a new user.
-        if is_verified(user.id)
-            .await
-            .expect("Error checking if user is verified")
-        {
+    let start = verification::start(
+        users,
+        user.id,
+        Some(ctx.author().id),
+        guild_id,
+        AuditMethod::VerifyCommand,
+    )
+    .await?;
+
+    match start {
+        Start::AlreadyVerified => {
             ctx.say("User is already verified!").await?;
             return Ok(());
         }
+        Start::Restarted => ctx.say("User verification process restarted!").await?,
+        Start::Started => ctx.say("User verification process started!").await?,
+    };
 
-        ctx.say("User verification process restarted!").await?;
-    }
-    // Otherwise, insert a new user.
-    else {
-        create_user(user.id).await.expect("Error creating user");
-        ctx.say("User verification process started!").await?;
+    if user.id != ctx.author().id {
+        log_event(
+            &ctx,
+            servers,
+            guild_id,
+            LogEvent::ModeratorAction {
+                moderator: ctx.author(),
+                action: format!("started verification for {}.", user.name),
+            },
+        )
+        .await;
     }
-
-    set_user_state(user.id, UserState::QueryingEmail)
-        .await
-        .expect("Error setting user state");
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::VerificationStarted { user: &user },
+    )
+    .await;
 
     // Ask for their Imperial email.
     user.dm(
@@ -70,57 +93,84 @@ pub async fn set_email(
     ctx: Context<'_>,
     #[description = "Email to set"] email: String,
 ) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
     let user = ctx.author();
+    let config = ctx.data().config;
+    let reloadable = config.reloadable();
+
+    // Only users who are verifying are sent passcodes, so that the bot can't be used to send
+    // emails to any address.
+    if !verification::is_verifying(users, user.id).await? {
+        ctx.say("You aren't verifying at the moment. Please use `/verify` in the server you'd like to be verified in first.")
+            .await?;
+        return Ok(());
+    }
 
     // Preprocess the email, and check if it's valid.
     let email = email.trim();
 
-    if !email.ends_with("@imperial.ac.uk") {
-        ctx.say(
-           "Sorry, the email you provided is not an Imperial email. Please provide an Imperial email."
-        )
-        .await?;
-        return Ok(());
-    }
+    // Make sure the email is unique, and doesn't belong to an erased, banned user.
+    let check = match verification::check_email(users, email, &reloadable.allowed_domains).await? {
+        EmailCheck::Accepted if users.email_is_tombstoned(email).await? => EmailCheck::InUse,
+        check => check,
+    };
 
-    // Make sure the email is unique.
-    if email_exists(email)
-        .await
-        .expect("Error checking if email exists")
-    {
-        ctx.say("Sorry, the email you provided is already in use. Please provide a unique Imperial email.")
+    let rejection = match check {
+        EmailCheck::Accepted => None,
+        EmailCheck::NotImperial => Some((
+            "provided a non-Imperial email.",
+            "Sorry, the email you provided is not an Imperial email. Please provide an Imperial email.",
+        )),
+        EmailCheck::InUse => Some((
+            "provided an email that is already in use.",
+            "Sorry, the email you provided is already in use. Please provide a unique Imperial email.",
+        )),
+    };
+
+    if let Some((reason, reply)) = rejection {
+        log_event_for_member(
+            &ctx,
+            servers,
+            user.id,
+            LogEvent::VerificationFailed {
+                user,
+                reason,
+                email: Some(email),
+            },
+        )
+        .await;
+        users
+            .record_rejection(user.id, AuditMethod::EmailRejected)
             .await?;
+        ctx.say(reply).await?;
         return Ok(());
     }
 
-    let otp = rand::thread_rng().gen_range(100000..=99999999);
-
-    insert_otp(user.id, otp).await.expect("Error inserting OTP");
+    let otp = verification::generate_otp();
 
-    let email_msg = Message::builder()
-        .from(
-            env::var("SMTP_FROM")
-                .expect("SMTP_FROM is required!")
-                .parse()
-                .unwrap(),
-        )
-        .to(email.parse().unwrap()) // TODO: Error handling
-        .subject("Verify your Imperial Email")
-        .header(ContentType::TEXT_PLAIN)
-        .body(format!(
-            "Hello, {}! Your secret password is {}",
-            user.name, otp
-        ))
-        .unwrap();
-
-    MAILER.lock().unwrap().deref_mut().send(&email_msg).unwrap();
+    let server = users
+        .get_verifying_server(user.id)
+        .await?
+        .and_then(|server| server.name(ctx))
+        .unwrap_or_else(|| "a server".to_string());
+    let mut email_msg = reloadable.templates.message(
+        config.mail.from.clone(),
+        email.parse()?,
+        &VerificationEmail {
+            name: &user.name,
+            server: &server,
+            code: otp,
+            expires_at: verification::otp_expires_at(Utc::now()),
+        },
+    )?;
+    if let Some(dkim) = &config.mail.dkim {
+        dkim.sign(&mut email_msg);
+    }
 
-    set_user_state(user.id, UserState::QueryingOTP)
-        .await
-        .expect("Error setting user state");
-    set_imperial_email(user.id, email.to_string())
-        .await
-        .expect("Error setting imperial email");
+    // Save the passcode first, so that it's never sent without being accepted.
+    verification::otp_sent(users, user.id, email, otp).await?;
+    ctx.data().outbox.enqueue(user.id, &email_msg).await?;
 
     ctx.say(
         r"Thank you!
@@ -137,56 +187,925 @@ pub async fn otp(
     ctx: Context<'_>,
     #[description = "The secret passcode to set"] otp: i32,
 ) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
+    let user = ctx.author();
+
+    let check = verification::submit_otp(users, user.id, otp).await?;
+
+    match check {
+        OtpCheck::Accepted => {
+            metrics::code_checked(true);
+            let updates = verify_on_all_servers(&ctx, servers, user.id).await?;
+            log_failed_updates(user.id, &updates);
+
+            info!("Verified user {}", user.name);
+
+            let email = users.get_imperial_email(user.id).await?.unwrap_or_default();
+            log_event_for_member(
+                &ctx,
+                servers,
+                user.id,
+                LogEvent::Verified {
+                    user,
+                    email: &email,
+                },
+            )
+            .await;
+
+            ctx.say("Congratulations! You've been verified!").await?;
+        }
+        OtpCheck::Malformed => {
+            metrics::code_checked(false);
+            log_event_for_member(
+                &ctx,
+                servers,
+                user.id,
+                LogEvent::VerificationFailed {
+                    user,
+                    reason: "provided a malformed passcode.",
+                    email: None,
+                },
+            )
+            .await;
+            users
+                .record_rejection(user.id, AuditMethod::OtpRejected)
+                .await?;
+            ctx
+                .say("Sorry, the secret passcode you provided is invalid. Please provide a valid secret passcode.")
+                .await?;
+        }
+        OtpCheck::Incorrect => {
+            metrics::code_checked(false);
+            log_event_for_member(
+                &ctx,
+                servers,
+                user.id,
+                LogEvent::VerificationFailed {
+                    user,
+                    reason: "provided an incorrect passcode.",
+                    email: None,
+                },
+            )
+            .await;
+            users
+                .record_rejection(user.id, AuditMethod::OtpRejected)
+                .await?;
+            ctx.say("Sorry, the secret passcode you provided is incorrect. Please provide the correct secret passcode.").await?;
+        }
+        OtpCheck::Expired => {
+            metrics::code_checked(false);
+            log_event_for_member(
+                &ctx,
+                servers,
+                user.id,
+                LogEvent::VerificationFailed {
+                    user,
+                    reason: "provided an expired passcode.",
+                    email: None,
+                },
+            )
+            .await;
+            users
+                .record_rejection(user.id, AuditMethod::OtpRejected)
+                .await?;
+            ctx.say("Sorry, the secret passcode you provided has expired. Please run `/set_email` again to get a new one.").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends you a copy of all the data stored about you.
+#[poise::command(slash_command, dm_only)]
+pub async fn my_data(ctx: Context<'_>) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let user = ctx.author();
+
+    let data = users.collect_user_data(user.id).await?;
+    let json = serde_json::to_vec_pretty(&data)?;
+
+    info!("Sent user data export to {}", user.name);
+
+    ctx.send(
+        CreateReply::default()
+            .content("Here is all the data stored about you.")
+            .attachment(CreateAttachment::bytes(json, "my_data.json")),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Gives you a signed, short-lived token proving that you are verified, to show elsewhere.
+#[poise::command(slash_command, dm_only)]
+pub async fn attest(ctx: Context<'_>) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
     let user = ctx.author();
 
-    // Check if the OTP is valid.
-    if !(100000..=99999999).contains(&otp) {
-        ctx
-            .say("Sorry, the secret passcode you provided is invalid. Please provide a valid secret passcode.")
+    if !users.is_verified(user.id).await? {
+        ctx.say("You need to verify your Imperial email before you can get an attestation.")
             .await?;
+        return Ok(());
+    }
+
+    let token = attestation::issue(user.id, true);
 
+    info!("Issued attestation to {}", user.name);
+
+    ctx.say(format!(
+        "Here is your attestation. It expires shortly, and doesn't contain your email.\n```\n{}\n```",
+        token
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes all the data stored about you, and removes your verified roles.
+#[poise::command(slash_command, dm_only)]
+pub async fn forget_me(ctx: Context<'_>) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
+    let user = ctx.author();
+
+    if !confirm(
+        ctx,
+        "This will delete all the data stored about you and remove your verified roles on every \
+        server. You will have to verify again to regain them. Are you sure?",
+    )
+    .await?
+    {
         return Ok(());
-    };
+    }
+
+    erase_user(&ctx, users, servers, user.id).await?;
 
-    // Check if the OTP is correct.
+    ctx.say("All the data stored about you has been deleted.")
+        .await?;
+
+    Ok(())
+}
 
-    if otp_exists_for_user(user.id, otp)
-        .await
-        .expect("Error checking if OTP exists")
+/// Deletes all the data stored about a user, and removes their verified roles.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn forget_user(
+    ctx: Context<'_>,
+    #[description = "User (or user ID) to forget"] user: serenity::User,
+) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
+    if !confirm(
+        ctx,
+        &format!(
+            "This will delete all the data stored about {} and remove their verified roles on \
+            every server. Are you sure?",
+            user.name
+        ),
+    )
+    .await?
     {
-        clear_otps(user.id).await.expect("Error clearing OTPs");
-        set_user_state(user.id, UserState::Verified)
-            .await
-            .expect("Error setting user state");
-        verify_on_all_servers(&ctx, user.id).await?;
+        return Ok(());
+    }
 
-        info!("Verified user {}", user.name);
+    let tombstoned = erase_user(&ctx, users, servers, user.id).await?;
+
+    log_event(
+        &ctx,
+        servers,
+        ctx.guild_id().unwrap(),
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!("erased all data stored about {}.", user.name),
+        },
+    )
+    .await;
 
-        ctx.say("Congratulations! You've been verified!").await?;
+    ctx.say(if tombstoned {
+        format!(
+            "All the data stored about {} has been deleted. They are banned, so a tombstone \
+            has been kept to stop their email being reused.",
+            user.name
+        )
     } else {
-        // Keep them in the same state, so they can try again.
-        ctx.say("Sorry, the secret passcode you provided is incorrect. Please provide the correct secret passcode.").await?;
-    }
+        format!("All the data stored about {} has been deleted.", user.name)
+    })
+    .await?;
 
     Ok(())
 }
 
+/// Asks the author to confirm an action with buttons, returning whether they confirmed it.
+async fn confirm(ctx: Context<'_>, prompt: &str) -> Result<bool, Error> {
+    let confirm_id = format!("{}confirm", ctx.id());
+    let cancel_id = format!("{}cancel", ctx.id());
+
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .content(prompt)
+                .ephemeral(true)
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(&confirm_id)
+                        .label("Confirm")
+                        .style(ButtonStyle::Danger),
+                    CreateButton::new(&cancel_id)
+                        .label("Cancel")
+                        .style(ButtonStyle::Secondary),
+                ])]),
+        )
+        .await?;
+
+    let press = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .custom_ids(vec![confirm_id.clone(), cancel_id])
+        .timeout(Duration::from_secs(60))
+        .await;
+
+    let confirmed = press
+        .as_ref()
+        .is_some_and(|press| press.data.custom_id == confirm_id);
+
+    if let Some(press) = press {
+        press
+            .create_response(ctx, CreateInteractionResponse::Acknowledge)
+            .await?;
+    }
+
+    // Remove the buttons, so they can't be pressed again.
+    reply
+        .edit(
+            ctx,
+            CreateReply::default()
+                .content(if confirmed {
+                    "Confirmed."
+                } else {
+                    "Cancelled."
+                })
+                .components(vec![]),
+        )
+        .await?;
+
+    Ok(confirmed)
+}
+
 /// Sets the server's verified user role.
 #[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
 pub async fn set_verified_role(
     ctx: Context<'_>,
     #[description = "Role to set"] role: serenity::Role,
 ) -> Result<(), Error> {
-    set_verified_role_db(ctx.guild_id().unwrap(), role.id)
-        .await
-        .unwrap();
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
 
-    set_verified_role_for_verified_on_single_server(&ctx, ctx.guild_id().unwrap())
-        .await
-        .expect("Error setting verified role"); // TODO: Better error handling
+    servers.set_verified_role(guild_id, role.id).await?;
+    set_verified_role_for_verified_on_single_server(&ctx, users, servers, guild_id).await?;
+
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!("set the verified role to `{}`.", role.name),
+        },
+    )
+    .await;
 
     ctx.say(format!("Verified role set to `{}`!", role.name))
         .await?;
 
     Ok(())
 }
+
+/// Sets the channel that verification events are logged to. Leave empty to stop logging.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn set_log_channel(
+    ctx: Context<'_>,
+    #[description = "Channel to log to"] channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+
+    servers
+        .set_log_channel(guild_id, channel.as_ref().map(|c| c.id))
+        .await?;
+
+    let action = match &channel {
+        Some(channel) => format!("set the log channel to `#{}`.", channel.name),
+        None => "disabled the log channel.".to_string(),
+    };
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action,
+        },
+    )
+    .await;
+
+    let reply = match channel {
+        Some(channel) => format!("Log channel set to `#{}`!", channel.name),
+        None => "Log channel disabled!".to_string(),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// Sets whether emails are shown in the log channel. Emails are redacted by default.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn show_emails_in_log(
+    ctx: Context<'_>,
+    #[description = "Whether to show emails"] show: bool,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+
+    servers.set_show_emails(guild_id, show).await?;
+
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!(
+                "{} emails in the log channel.",
+                if show { "enabled" } else { "disabled" }
+            ),
+        },
+    )
+    .await;
+
+    if show {
+        ctx.say("Emails will now be shown in the log channel.")
+    } else {
+        ctx.say("Emails will now be redacted in the log channel.")
+    }
+    .await?;
+
+    Ok(())
+}
+
+/// The number of audit events shown per page by `/audit user`.
+const AUDIT_PAGE_SIZE: i64 = 10;
+
+/// Commands for inspecting the audit trail.
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "ADMINISTRATOR",
+    subcommands("audit_user")
+)]
+pub async fn audit(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Shows the verification history of a user.
+#[poise::command(slash_command, guild_only, rename = "user")]
+pub async fn audit_user(
+    ctx: Context<'_>,
+    #[description = "User to show the history of"] user: serenity::User,
+    #[description = "Page to show"]
+    #[min = 1]
+    page: Option<i64>,
+) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let page = page.unwrap_or(1);
+    let (events, total) = users
+        .get_audit_events(user.id, ctx.guild_id().unwrap(), page - 1, AUDIT_PAGE_SIZE)
+        .await?;
+    let pages = ((total + AUDIT_PAGE_SIZE - 1) / AUDIT_PAGE_SIZE).max(1);
+
+    let description = if events.is_empty() {
+        "No events.".to_string()
+    } else {
+        events
+            .iter()
+            .map(|event| {
+                let old_state = event
+                    .old_state
+                    .map_or("-".to_string(), |state| format!("{:?}", state));
+                let actor = event.actor_id.map_or("the bot".to_string(), |actor_id| {
+                    serenity::UserId::new(actor_id as u64).mention().to_string()
+                });
+
+                // Events that aren't transitions only have the state the user was in.
+                let state = match event.new_state {
+                    Some(new_state) => format!("`{}` → `{:?}`", old_state, new_state),
+                    None => format!("`{}`", old_state),
+                };
+                let line = format!(
+                    "<t:{}:f> {} via `{:?}` by {}",
+                    event.created_at.timestamp(),
+                    state,
+                    event.method,
+                    actor
+                );
+
+                match &event.reason {
+                    Some(reason) => format!("{} (reason: {})", line, reason),
+                    None => line,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title(format!("Audit trail for {}", user.name))
+                    .description(description)
+                    .footer(serenity::CreateEmbedFooter::new(format!(
+                        "Page {}/{} ({} events)",
+                        page, pages, total
+                    ))),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Sets the role allowed to look up members' emails with `/whois`. Leave empty to disable it.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn set_whois_role(
+    ctx: Context<'_>,
+    #[description = "Role to allow"] role: Option<serenity::Role>,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+
+    servers
+        .set_whois_role(guild_id, role.as_ref().map(|r| r.id))
+        .await?;
+
+    let action = match &role {
+        Some(role) => format!("allowed `{}` to use `/whois`.", role.name),
+        None => "disabled `/whois`.".to_string(),
+    };
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action,
+        },
+    )
+    .await;
+
+    let reply = match role {
+        Some(role) => format!("`/whois` can now be used by `{}`!", role.name),
+        None => "`/whois` disabled!".to_string(),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// Looks up the Imperial email of a verified member. Every lookup is recorded.
+#[poise::command(slash_command, guild_only)]
+pub async fn whois(
+    ctx: Context<'_>,
+    #[description = "Member to look up"] user: serenity::User,
+    #[description = "Why you need to know"] reason: String,
+) -> Result<(), Error> {
+    let users = ctx.data().users.as_ref();
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+    let server = servers.get_server(guild_id).await?;
+
+    // Only the server's privileged role may look members up.
+    let whois_role = server
+        .as_ref()
+        .and_then(|server| server.whois_role_id)
+        .map(|role_id| serenity::RoleId::new(role_id as u64));
+    let allowed = match (whois_role, ctx.author_member().await) {
+        (Some(role_id), Some(member)) => member.roles.contains(&role_id),
+        _ => false,
+    };
+
+    if !allowed {
+        ctx.send(
+            CreateReply::default()
+                .content("Sorry, you are not allowed to use this command.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let reason = reason.trim();
+    if reason.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content("Please give a reason for looking this member up.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Only members verified in this server can be looked up.
+    let verified_role = server
+        .as_ref()
+        .and_then(|server| server.verified_role_id)
+        .map(|role_id| serenity::RoleId::new(role_id as u64));
+    let verified_here = match guild_id.member(&ctx, user.id).await {
+        Ok(member) => {
+            let has_verified_role = match verified_role {
+                Some(role_id) => member.roles.contains(&role_id),
+                None => true,
+            };
+
+            has_verified_role && users.is_verified(user.id).await?
+        }
+        Err(_) => false,
+    };
+
+    if !verified_here {
+        ctx.send(
+            CreateReply::default()
+                .content("Sorry, that user is not a verified member of this server.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Record the lookup before revealing anything.
+    users
+        .record_whois_lookup(ctx.author().id, user.id, guild_id, reason)
+        .await?;
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!("looked up the email of {}. Reason: {}", user.name, reason),
+        },
+    )
+    .await;
+
+    let email = users.get_imperial_email(user.id).await?.unwrap_or_default();
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("{} is verified as `{}`.", user.name, email))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The period verifications are counted in by `/stats`.
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum StatsPeriodChoice {
+    /// Per day, over the last two weeks.
+    Day,
+    /// Per week, over the last eight weeks.
+    Week,
+}
+
+/// Shows verification statistics for this server's members.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn stats(
+    ctx: Context<'_>,
+    #[description = "Count verifications per day or per week"] period: Option<StatsPeriodChoice>,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    // Fetching all the members can take a while.
+    ctx.defer_ephemeral().await?;
+
+    let guild_id = ctx.guild_id().unwrap();
+    let member_ids = get_member_ids(&ctx, guild_id).await?;
+
+    let (period, since, date_format) = match period.unwrap_or(StatsPeriodChoice::Day) {
+        StatsPeriodChoice::Day => (
+            StatsPeriod::Day,
+            Utc::now() - TimeDelta::days(14),
+            "%a %d %b",
+        ),
+        StatsPeriodChoice::Week => (
+            StatsPeriod::Week,
+            Utc::now() - TimeDelta::weeks(8),
+            "w/c %d %b",
+        ),
+    };
+
+    let stats = servers
+        .get_verification_stats(guild_id, &member_ids, period, since)
+        .await?;
+
+    let state_counts = stats
+        .state_counts
+        .iter()
+        .map(|(state, count)| format!("`{:?}`: {}", state, count))
+        .collect::<Vec<_>>();
+    let verifications = stats
+        .verifications
+        .iter()
+        .map(|(start, count)| format!("{}: {}", start.format(date_format), count))
+        .collect::<Vec<_>>();
+
+    let or_none = |lines: Vec<String>| {
+        if lines.is_empty() {
+            "None".to_string()
+        } else {
+            lines.join("\n")
+        }
+    };
+
+    let failure_rate = |failures: i64, attempts: i64| {
+        if attempts == 0 {
+            "n/a".to_string()
+        } else {
+            format!(
+                "{:.1}% ({}/{})",
+                failures as f64 / attempts as f64 * 100.0,
+                failures,
+                attempts
+            )
+        }
+    };
+
+    let median = stats
+        .median_seconds_to_verify
+        .map_or("n/a".to_string(), |seconds| format_duration(seconds as i64));
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title("Verification statistics")
+                .description(format!("{} members", member_ids.len()))
+                .field("Members by state", or_none(state_counts), true)
+                .field("Verifications", or_none(verifications), true)
+                .field("Median time from joining to verifying", median, false)
+                .field(
+                    "Rejected emails",
+                    failure_rate(stats.email_failures, stats.email_attempts),
+                    true,
+                )
+                .field(
+                    "Rejected passcodes",
+                    failure_rate(stats.otp_failures, stats.otp_attempts),
+                    true,
+                )
+                .timestamp(serenity::Timestamp::now()),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Gets the IDs of all the members of a server.
+async fn get_member_ids(ctx: &Context<'_>, guild_id: serenity::GuildId) -> Result<Vec<i64>, Error> {
+    let members = get_members(ctx, guild_id).await?;
+
+    Ok(members
+        .iter()
+        .map(|member| i64::from(member.user.id))
+        .collect())
+}
+
+/// Formats a duration in seconds as e.g. `1d 2h 3m`.
+fn format_duration(seconds: i64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+/// Commands for managing the tokens other bots use to query verification status.
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_permissions = "ADMINISTRATOR",
+    subcommands("api_token_create", "api_token_list", "api_token_revoke")
+)]
+pub async fn api_token(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Creates a token for the verification status API, scoped to this server.
+#[poise::command(slash_command, guild_only, rename = "create")]
+pub async fn api_token_create(
+    ctx: Context<'_>,
+    #[description = "What the token is for, e.g. the bot using it"] name: String,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+
+    let token = servers
+        .create_api_token(guild_id, &name, ctx.author().id)
+        .await?;
+
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!("created the API token `{}`.", name),
+        },
+    )
+    .await;
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Created the API token `{}`. Keep it secret, it won't be shown again:\n`{}`",
+                name, token
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Lists this server's API tokens.
+#[poise::command(slash_command, guild_only, rename = "list")]
+pub async fn api_token_list(ctx: Context<'_>) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let tokens = servers.get_api_tokens(ctx.guild_id().unwrap()).await?;
+
+    let description = if tokens.is_empty() {
+        "No tokens.".to_string()
+    } else {
+        tokens
+            .iter()
+            .map(|token| {
+                let creator = token.created_by.map_or("unknown".to_string(), |creator| {
+                    serenity::UserId::new(creator as u64).mention().to_string()
+                });
+
+                format!(
+                    "`{}`: `{}`, created <t:{}:f> by {}",
+                    token.id,
+                    token.name,
+                    token.created_at.timestamp(),
+                    creator
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("API tokens")
+                    .description(description),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes one of this server's API tokens.
+#[poise::command(slash_command, guild_only, rename = "revoke")]
+pub async fn api_token_revoke(
+    ctx: Context<'_>,
+    #[description = "ID of the token to revoke, from `/api_token list`"] id: i64,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+
+    if !servers.revoke_api_token(guild_id, id).await? {
+        ctx.send(
+            CreateReply::default()
+                .content("Sorry, there is no such token.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!("revoked the API token with ID `{}`.", id),
+        },
+    )
+    .await;
+
+    ctx.send(
+        CreateReply::default()
+            .content("Token revoked!")
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Sends a test email through the bot's mailer, to check that emails are being delivered.
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn test_email(
+    ctx: Context<'_>,
+    #[description = "Imperial email to send the test to"] email: String,
+) -> Result<(), Error> {
+    let servers = ctx.data().servers.as_ref();
+    let guild_id = ctx.guild_id().unwrap();
+    let config = ctx.data().config;
+    let reloadable = config.reloadable();
+    let email = email.trim();
+
+    let to = match email.parse() {
+        Ok(to) if verification::is_imperial_email(email, &reloadable.allowed_domains) => to,
+        _ => {
+            ctx.send(
+                CreateReply::default()
+                    .content("Sorry, that is not an Imperial email.")
+                    .ephemeral(true),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let cooldown = reloadable.test_email_cooldown;
+    if let Err(wait) = ctx.data().test_email_limit.check(guild_id, cooldown) {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "A test email was sent from this server recently. Please try again in {}.",
+                    format_duration((wait.as_secs() + 59) as i64)
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // Sending can take a while if the mail server is slow.
+    ctx.defer_ephemeral().await?;
+
+    let server = guild_id.name(ctx).unwrap_or_else(|| "a server".to_string());
+    let mut message = Message::builder()
+        .from(config.mail.from.clone())
+        .to(to)
+        .subject("Test email from the verification bot")
+        .body(format!(
+            "This is a test email, sent by an admin of {} to check that verification emails are \
+            delivered. You don't need to do anything.",
+            server
+        ))?;
+    if let Some(dkim) = &config.mail.dkim {
+        dkim.sign(&mut message);
+    }
+
+    let sent = ctx
+        .data()
+        .mailer
+        .send(message.envelope(), &message.formatted())
+        .await;
+
+    log_event(
+        &ctx,
+        servers,
+        guild_id,
+        LogEvent::ModeratorAction {
+            moderator: ctx.author(),
+            action: format!(
+                "sent a test email, which {}.",
+                if sent.is_ok() {
+                    "was accepted"
+                } else {
+                    "failed"
+                }
+            ),
+        },
+    )
+    .await;
+
+    let reply = match sent {
+        Ok(response) => format!("The test email was accepted:\n```\n{}\n```", response),
+        Err(err) => format!("The test email couldn't be sent:\n```\n{}\n```", err),
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
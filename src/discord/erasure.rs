@@ -0,0 +1,61 @@
+use super::roles::{log_failed_updates, unverify_on_all_servers};
+use crate::db::{ServerRepository, UserRepository};
+use crate::errors::Result;
+use log::{info, warn};
+use poise::serenity_prelude::{CacheHttp, UserId, UserPagination};
+
+/// Erase a user: remove their verified roles everywhere, then delete everything stored about
+/// them. A tombstone is only kept if the user is banned from a server, so that they can't get
+/// around the ban by erasing themselves and verifying again. Returns whether a tombstone was kept.
+///
+/// The data is erased even if some roles can't be removed (e.g. the bot lacks permissions on a
+/// server), since those are up to each server, but the data is ours to delete.
+pub async fn erase_user<C: CacheHttp>(
+    ctx: &C,
+    users: &dyn UserRepository,
+    servers: &dyn ServerRepository,
+    user_id: UserId,
+) -> Result<bool> {
+    match unverify_on_all_servers(ctx, servers, user_id).await {
+        Ok(updates) => log_failed_updates(user_id, &updates),
+        Err(err) => warn!(
+            "Could not remove the verified roles of user {}, erasing them anyway: {}",
+            user_id, err
+        ),
+    }
+
+    let keep_tombstone = is_banned_anywhere(ctx, user_id).await;
+    users.erase_user_data(user_id, keep_tombstone).await?;
+
+    info!(
+        "Erased user {} ({})",
+        user_id,
+        if keep_tombstone {
+            "tombstone kept"
+        } else {
+            "no tombstone"
+        }
+    );
+
+    Ok(keep_tombstone)
+}
+
+/// Check if a user is banned from any server the bot is on.
+async fn is_banned_anywhere<C: CacheHttp>(ctx: &C, user_id: UserId) -> bool {
+    let guild_ids = ctx.cache().map(|cache| cache.guilds()).unwrap_or_default();
+
+    for guild_id in guild_ids {
+        // Bans are sorted by user ID, so the first ban after the user's ID minus one is theirs if
+        // they are banned.
+        let target =
+            UserPagination::After(UserId::new(u64::from(user_id).saturating_sub(1).max(1)));
+
+        match guild_id.bans(ctx.http(), Some(target), Some(1)).await {
+            Ok(bans) if bans.first().is_some_and(|ban| ban.user.id == user_id) => return true,
+            Ok(_) => {}
+            Err(err) => warn!("Could not check bans on server {}: {}", guild_id, err),
+        }
+    }
+
+    false
+}
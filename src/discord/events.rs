@@ -1,11 +1,8 @@
+use super::log_channel::{log_event, LogEvent};
 use super::{Data, Error};
-use crate::db::create_user;
-use crate::db::get_verified_role;
-use crate::db::is_verified;
-use crate::db::models::UserState;
-use crate::db::set_user_state;
-use crate::db::user_exists;
+use crate::db::models::AuditMethod;
 use crate::errors::Result;
+use crate::verification::{self, Start};
 use log::info;
 use poise::serenity_prelude as serenity;
 use poise::FrameworkContext;
@@ -30,8 +27,10 @@ async fn event_handler(
     ctx: &Context,
     event: &FullEvent,
     _framework: FrameworkContext<'_, Data, Error>,
-    _data: &Data,
+    data: &Data,
 ) -> Result<()> {
+    let servers = data.servers.as_ref();
+
     match event {
         FullEvent::Ready { data_about_bot, .. } => {
             info!("Logged in as {}", data_about_bot.user.name);
@@ -46,25 +45,50 @@ async fn event_handler(
 
             let user = &new_member.user;
 
-            // If the user exists, do not insert a new user.
-            if user_exists(user.id).await? {
-                // If a user with the same discord ID is verified, do not insert a new user.
-                // Instead, add their roles.
-                if is_verified(user.id).await? {
-                    let verified_role = get_verified_role(new_member.guild_id).await?;
-                    if let Some(role_id) = verified_role {
-                        new_member.add_role(&ctx.http, role_id).await?;
-                    }
+            log_event(
+                ctx,
+                servers,
+                new_member.guild_id,
+                LogEvent::MemberJoined { user },
+            )
+            .await;
 
-                    return Ok(());
+            let start = verification::start(
+                data.users.as_ref(),
+                user.id,
+                None,
+                new_member.guild_id,
+                AuditMethod::MemberJoin,
+            )
+            .await?;
+
+            // If the user is already verified, add their roles instead.
+            if start == Start::AlreadyVerified {
+                let verified_role = servers.get_verified_role(new_member.guild_id).await?;
+                if let Some(role_id) = verified_role {
+                    new_member.add_role(&ctx.http, role_id).await?;
+                    log_event(
+                        ctx,
+                        servers,
+                        new_member.guild_id,
+                        LogEvent::RoleGranted {
+                            user_id: user.id,
+                            role_id,
+                        },
+                    )
+                    .await;
                 }
-            }
-            // Otherwise, insert a new user.
-            else {
-                create_user(user.id).await?;
+
+                return Ok(());
             }
 
-            set_user_state(user.id, UserState::QueryingEmail).await?;
+            log_event(
+                ctx,
+                servers,
+                new_member.guild_id,
+                LogEvent::VerificationStarted { user },
+            )
+            .await;
 
             // Ask for their Imperial email.
             user.dm(
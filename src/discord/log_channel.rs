@@ -0,0 +1,198 @@
+use crate::db::{models::Server, ServerRepository};
+use crate::errors::Result;
+use log::warn;
+use poise::serenity_prelude::{
+    CacheHttp, ChannelId, Colour, CreateEmbed, CreateEmbedFooter, CreateMessage, GuildId,
+    Mentionable, RoleId, Timestamp, User, UserId,
+};
+
+/// An event that is posted to a server's log channel.
+pub enum LogEvent<'a> {
+    /// A member joined the server.
+    MemberJoined { user: &'a User },
+    /// A user started (or restarted) the verification process.
+    VerificationStarted { user: &'a User },
+    /// A step of the verification process failed.
+    VerificationFailed {
+        user: &'a User,
+        reason: &'a str,
+        email: Option<&'a str>,
+    },
+    /// A user successfully verified their email.
+    Verified { user: &'a User, email: &'a str },
+    /// A user was given the verified role.
+    RoleGranted { user_id: UserId, role_id: RoleId },
+    /// Every verified member was given the verified role, when it was set.
+    RoleGrantedToVerified { role_id: RoleId, count: usize },
+    /// The verified role was taken away from a user.
+    RoleRemoved { user_id: UserId, role_id: RoleId },
+    /// A moderator did something to the server's verification settings or members.
+    ModeratorAction { moderator: &'a User, action: String },
+}
+
+impl LogEvent<'_> {
+    /// Build the embed for this event. Emails are redacted unless `show_emails` is set.
+    fn embed(&self, show_emails: bool) -> CreateEmbed {
+        let email = |email: &str| {
+            if show_emails {
+                format!("`{}`", email)
+            } else {
+                redact_email(email)
+            }
+        };
+
+        let embed = match self {
+            LogEvent::MemberJoined { user } => CreateEmbed::new()
+                .title("Member joined")
+                .colour(Colour::BLUE)
+                .description(format!(
+                    "{} ({}) joined the server.",
+                    user.mention(),
+                    user.name
+                )),
+            LogEvent::VerificationStarted { user } => CreateEmbed::new()
+                .title("Verification started")
+                .colour(Colour::GOLD)
+                .description(format!(
+                    "{} ({}) started the verification process.",
+                    user.mention(),
+                    user.name
+                )),
+            LogEvent::VerificationFailed {
+                user,
+                reason,
+                email: submitted,
+            } => {
+                let embed = CreateEmbed::new()
+                    .title("Verification failed")
+                    .colour(Colour::RED)
+                    .description(format!("{} ({}): {}", user.mention(), user.name, reason));
+
+                match submitted {
+                    Some(submitted) => embed.field("Email", email(submitted), true),
+                    None => embed,
+                }
+            }
+            LogEvent::Verified {
+                user,
+                email: verified,
+            } => CreateEmbed::new()
+                .title("Verification succeeded")
+                .colour(Colour::DARK_GREEN)
+                .description(format!("{} ({}) was verified.", user.mention(), user.name))
+                .field("Email", email(verified), true),
+            LogEvent::RoleGranted { user_id, role_id } => CreateEmbed::new()
+                .title("Role granted")
+                .colour(Colour::DARK_GREEN)
+                .description(format!(
+                    "{} was given {}.",
+                    user_id.mention(),
+                    role_id.mention()
+                )),
+            LogEvent::RoleGrantedToVerified { role_id, count } => CreateEmbed::new()
+                .title("Role granted")
+                .colour(Colour::DARK_GREEN)
+                .description(format!(
+                    "{} verified member{} given {}.",
+                    count,
+                    if *count == 1 { " was" } else { "s were" },
+                    role_id.mention()
+                )),
+            LogEvent::RoleRemoved { user_id, role_id } => CreateEmbed::new()
+                .title("Role removed")
+                .colour(Colour::DARK_RED)
+                .description(format!(
+                    "{} was removed from {}.",
+                    role_id.mention(),
+                    user_id.mention()
+                )),
+            LogEvent::ModeratorAction { moderator, action } => CreateEmbed::new()
+                .title("Moderator action")
+                .colour(Colour::ORANGE)
+                .description(format!(
+                    "{} ({}) {}",
+                    moderator.mention(),
+                    moderator.name,
+                    action
+                )),
+        };
+
+        embed
+            .footer(CreateEmbedFooter::new("imperial-bot"))
+            .timestamp(Timestamp::now())
+    }
+}
+
+/// Redacts the local part of an email, leaving only the domain.
+fn redact_email(email: &str) -> String {
+    match email.rsplit_once('@') {
+        Some((_, domain)) => format!("`[redacted]@{}`", domain),
+        None => "`[redacted]`".to_string(),
+    }
+}
+
+/// Post an event to a server's log channel, if it has one. Failures are logged and otherwise
+/// ignored, so that a misconfigured log channel never breaks verification.
+pub async fn log_event<C: CacheHttp>(
+    ctx: &C,
+    servers: &dyn ServerRepository,
+    guild_id: GuildId,
+    event: LogEvent<'_>,
+) {
+    let result = match servers.get_server(guild_id).await {
+        Ok(Some(server)) => post_event(ctx, &server, &event).await,
+        Ok(None) => Ok(()),
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        warn!("Could not log event to server {}: {}", guild_id, err);
+    }
+}
+
+/// Post an event to the log channel of every server the user is a member of. Used for events
+/// that happen outside of a server, such as in DMs.
+pub async fn log_event_for_member<C: CacheHttp>(
+    ctx: &C,
+    servers: &dyn ServerRepository,
+    user_id: UserId,
+    event: LogEvent<'_>,
+) {
+    let entries = match servers.get_servers_with_log_channels().await {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Could not get servers with log channels: {}", err);
+            return;
+        }
+    };
+
+    for server in entries {
+        let guild_id = GuildId::new(server.id as u64);
+
+        // Only log to servers the user is actually on.
+        if guild_id.member(ctx, user_id).await.is_err() {
+            continue;
+        }
+
+        if let Err(err) = post_event(ctx, &server, &event).await {
+            warn!("Could not log event to server {}: {}", guild_id, err);
+        }
+    }
+}
+
+async fn post_event<C: CacheHttp>(ctx: &C, server: &Server, event: &LogEvent<'_>) -> Result<()> {
+    let channel_id = if let Some(channel_id) = server.log_channel_id {
+        ChannelId::new(channel_id as u64)
+    } else {
+        return Ok(());
+    };
+
+    channel_id
+        .send_message(
+            ctx,
+            CreateMessage::new().embed(event.embed(server.show_emails)),
+        )
+        .await?;
+
+    Ok(())
+}
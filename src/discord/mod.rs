@@ -1,19 +1,48 @@
 mod commands;
+mod erasure;
 mod events;
+mod log_channel;
+mod rate_limit;
 mod roles;
 
+use crate::config::Config;
+use crate::db::{Database, ServerRepository, UserRepository};
+use crate::mail::{Mailer, Outbox};
+use crate::metrics;
 use events::event_handler_wrapper;
 use poise::serenity_prelude as serenity;
-use serenity::GatewayIntents;
-use std::env;
+use rate_limit::RateLimiter;
+use serenity::{CacheHttp, GatewayIntents, GuildId, Member};
+use std::sync::Arc;
 
-/// User data, which is stored and accessible in all command invocations
-struct Data {}
+pub use log_channel::{log_event, LogEvent};
+pub use roles::{
+    set_verified_role_for_verified_on_single_server, unverify_on_all_servers, verify_on_all_servers,
+};
+
+/// User data, which is stored and accessible in all command invocations. Commands only reach the
+/// database through the repositories, so that they can be tested without one.
+struct Data {
+    users: Arc<dyn UserRepository>,
+    servers: Arc<dyn ServerRepository>,
+    outbox: Outbox,
+    /// Sends test emails from `/test_email` straight away, rather than through the outbox.
+    mailer: Arc<dyn Mailer>,
+    /// How often each server can send a test email.
+    test_email_limit: RateLimiter<GuildId>,
+    /// What emails are sent from and how they are made, and the settings that can be reloaded.
+    config: &'static Config,
+}
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
-pub async fn run() {
-    let token = env::var("DISCORD_TOKEN").expect("missing DISCORD_TOKEN");
+/// Build the discord client.
+pub async fn client(
+    config: &'static Config,
+    db: Database,
+    outbox: Outbox,
+    mailer: Arc<dyn Mailer>,
+) -> serenity::Client {
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::DIRECT_MESSAGES // Needed for DM commands
         | GatewayIntents::DIRECT_MESSAGE_REACTIONS
@@ -25,24 +54,73 @@ pub async fn run() {
                 commands::verify(),
                 commands::set_email(),
                 commands::otp(),
+                commands::my_data(),
+                commands::attest(),
+                commands::forget_me(),
+                commands::forget_user(),
                 commands::set_verified_role(),
+                commands::set_log_channel(),
+                commands::show_emails_in_log(),
+                commands::audit(),
+                commands::set_whois_role(),
+                commands::whois(),
+                commands::stats(),
+                commands::api_token(),
+                commands::test_email(),
             ],
+            pre_command: |ctx| {
+                Box::pin(async move { metrics::command_run(&ctx.command().qualified_name) })
+            },
             event_handler: |ctx, event, framework, data| {
                 Box::pin(event_handler_wrapper(ctx, event, framework, data))
             },
             ..Default::default()
         })
-        .setup(|ctx, _ready, framework| {
+        .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {})
+                Ok(Data {
+                    users: Arc::new(db.clone()),
+                    servers: Arc::new(db),
+                    outbox,
+                    mailer,
+                    test_email_limit: RateLimiter::new(),
+                    config,
+                })
             })
         })
         .build();
 
-    let client = serenity::ClientBuilder::new(token, intents)
+    serenity::ClientBuilder::new(&config.discord_token, intents)
         .framework(framework)
-        .await;
+        .await
+        .unwrap()
+}
+
+/// Connect to discord and run the bot.
+pub async fn run(mut client: serenity::Client) {
+    client.start().await.unwrap();
+}
+
+/// Get all the members of a server.
+pub async fn get_members<C: CacheHttp>(
+    ctx: &C,
+    guild_id: GuildId,
+) -> serenity::Result<Vec<Member>> {
+    let mut members = vec![];
+    let mut after = None;
+
+    loop {
+        let page = guild_id.members(ctx.http(), Some(1000), after).await?;
+        let full = page.len() == 1000;
+        after = page.last().map(|member| member.user.id);
+
+        members.extend(page);
+
+        if !full {
+            break;
+        }
+    }
 
-    client.unwrap().start().await.unwrap();
+    Ok(members)
 }
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits how often something can be done, separately for each key (e.g. each server).
+pub struct RateLimiter<K> {
+    last_allowed: Mutex<HashMap<K, Instant>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new() -> RateLimiter<K> {
+        RateLimiter {
+            last_allowed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check whether `key` may do it now, if it's allowed once per `interval`, and if so, count
+    /// this as its go. Otherwise returns how long until it may. The interval is passed each time
+    /// so that it can change while the bot is running.
+    pub fn check(&self, key: K, interval: Duration) -> Result<(), Duration> {
+        self.check_at(key, interval, Instant::now())
+    }
+
+    fn check_at(&self, key: K, interval: Duration, now: Instant) -> Result<(), Duration> {
+        let mut last_allowed = self.last_allowed.lock().unwrap();
+
+        // Forget keys that are allowed again, so the map doesn't grow forever.
+        last_allowed.retain(|_, last| now.duration_since(*last) < interval);
+
+        if let Some(last) = last_allowed.get(&key) {
+            return Err(interval - now.duration_since(*last));
+        }
+        last_allowed.insert(key, now);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_limited_separately_until_the_interval_passes() {
+        let limiter = RateLimiter::new();
+        let interval = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert_eq!(limiter.check_at(1, interval, start), Ok(()));
+        assert_eq!(limiter.check_at(2, interval, start), Ok(()));
+        assert_eq!(
+            limiter.check_at(1, interval, start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        assert_eq!(
+            limiter.check_at(1, interval, start + Duration::from_secs(60)),
+            Ok(())
+        );
+    }
+}
@@ -1,50 +1,159 @@
-use crate::db::{get_servers_with_verified_roles, get_verified_role, is_verified, models::Server};
+use super::log_channel::{log_event, LogEvent};
+use crate::db::{models::Server, ServerRepository, UserRepository};
 use crate::errors::Result;
-use poise::serenity_prelude::{CacheHttp, Guild, GuildId, RoleId, UserId};
+use crate::metrics;
+use log::warn;
+use poise::serenity_prelude::{self as serenity, CacheHttp, Guild, GuildId, RoleId, UserId};
+use std::future::Future;
+
+/// Run a discord API request, recording any error in the metrics, labelled by `operation`.
+async fn track<T>(
+    operation: &str,
+    request: impl Future<Output = serenity::Result<T>>,
+) -> serenity::Result<T> {
+    let result = request.await;
+
+    if result.is_err() {
+        metrics::discord_error(operation);
+    }
+
+    result
+}
 
 /// Verify all verified users on a single server.
 pub async fn set_verified_role_for_verified_on_single_server<C: CacheHttp>(
     ctx: &C,
+    users: &dyn UserRepository,
+    servers: &dyn ServerRepository,
     guild_id: GuildId,
 ) -> Result<()> {
     // Get the role id
-    let role_id = if let Some(role_id) = get_verified_role(guild_id).await? {
+    let role_id = if let Some(role_id) = servers.get_verified_role(guild_id).await? {
         role_id
     } else {
         return Ok(());
     };
 
-    let guild = Guild::get(ctx.http(), guild_id).await?;
+    let guild = track("get_guild", Guild::get(ctx.http(), guild_id)).await?;
 
-    let mut guild_members = guild.members(ctx.http(), None, None).await?;
+    let mut guild_members = track("get_members", guild.members(ctx.http(), None, None)).await?;
 
+    let mut count = 0;
     for member in guild_members.iter_mut() {
-        if is_verified(member.user.id).await? {
-            member.add_role(ctx.http(), role_id).await?;
+        if users.is_verified(member.user.id).await? {
+            track("add_role", member.add_role(ctx.http(), role_id)).await?;
+            count += 1;
         }
     }
 
+    // One event for the lot, rather than one per member, so that big servers don't flood the
+    // log channel.
+    log_event(
+        ctx,
+        servers,
+        guild_id,
+        LogEvent::RoleGrantedToVerified { role_id, count },
+    )
+    .await;
+
     Ok(())
 }
 
-/// Verify a user on all servers the user is on.
-pub async fn verify_on_all_servers<C: CacheHttp>(ctx: &C, user_id: UserId) -> Result<()> {
-    let entries = get_servers_with_verified_roles().await?;
+/// The outcome of updating a user's roles on each server they are on.
+pub type RoleUpdates = Vec<(GuildId, Result<()>)>;
+
+/// Verify a user on all servers the user is on. Servers the user isn't on (or that the bot has
+/// left) are skipped, and a failure on one server doesn't stop the others being updated.
+pub async fn verify_on_all_servers<C: CacheHttp>(
+    ctx: &C,
+    servers: &dyn ServerRepository,
+    user_id: UserId,
+) -> Result<RoleUpdates> {
+    let entries = servers.get_servers_with_verified_roles().await?;
+    let mut updates = Vec::new();
 
-    // TODO: What if the bot has left the server?
     for Server {
         id,
         verified_role_id,
+        ..
     } in entries
     {
         let guild_id = GuildId::new(id as u64);
         let role_id = RoleId::new(verified_role_id.expect("This should be Some!") as u64);
-        let guild = Guild::get(ctx.http(), guild_id).await?;
 
-        let member = guild.member(&ctx.http(), user_id).await?;
+        // The user might not be on this server.
+        let member = match guild_id.member(ctx, user_id).await {
+            Ok(member) => member,
+            Err(_) => continue,
+        };
 
-        member.add_role(&ctx.http(), role_id).await?;
+        let result = track("add_role", member.add_role(ctx.http(), role_id)).await;
+        if result.is_ok() {
+            log_event(
+                ctx,
+                servers,
+                guild_id,
+                LogEvent::RoleGranted { user_id, role_id },
+            )
+            .await;
+        }
+        updates.push((guild_id, result.map_err(Into::into)));
     }
 
-    Ok(())
+    Ok(updates)
+}
+
+/// Log the servers on which updating a user's roles failed.
+pub fn log_failed_updates(user_id: UserId, updates: &RoleUpdates) {
+    for (guild_id, result) in updates {
+        if let Err(err) = result {
+            warn!(
+                "Could not update the roles of user {} on server {}: {}",
+                user_id, guild_id, err
+            );
+        }
+    }
+}
+
+/// Remove the verified role from a user on all servers the user is on, like
+/// [`verify_on_all_servers`].
+pub async fn unverify_on_all_servers<C: CacheHttp>(
+    ctx: &C,
+    servers: &dyn ServerRepository,
+    user_id: UserId,
+) -> Result<RoleUpdates> {
+    let entries = servers.get_servers_with_verified_roles().await?;
+    let mut updates = Vec::new();
+
+    for Server {
+        id,
+        verified_role_id,
+        ..
+    } in entries
+    {
+        let guild_id = GuildId::new(id as u64);
+        let role_id = RoleId::new(verified_role_id.expect("This should be Some!") as u64);
+
+        // The user might not be on this server.
+        let member = match guild_id.member(ctx, user_id).await {
+            Ok(member) => member,
+            Err(_) => continue,
+        };
+
+        if member.roles.contains(&role_id) {
+            let result = track("remove_role", member.remove_role(ctx.http(), role_id)).await;
+            if result.is_ok() {
+                log_event(
+                    ctx,
+                    servers,
+                    guild_id,
+                    LogEvent::RoleRemoved { user_id, role_id },
+                )
+                .await;
+            }
+            updates.push((guild_id, result.map_err(Into::into)));
+        }
+    }
+
+    Ok(updates)
 }
@@ -4,11 +4,33 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// Custom error type to wrap both discord errors and db errors.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    /// Discord error
+    /// Discord error, boxed because serenity's error type is large.
     #[error("Discord error: {0}")]
-    Discord(#[from] poise::serenity_prelude::Error),
+    Discord(Box<poise::serenity_prelude::Error>),
 
     // Database error
     #[error("Database error: {0}")]
     Db(#[from] diesel::result::Error),
+
+    /// Error getting a connection from the database pool.
+    #[error("Database pool error: {0}")]
+    Pool(#[from] diesel::r2d2::PoolError),
+
+    /// Encryption error, e.g. a stored email that can't be decrypted.
+    #[error("Encryption error: {0}")]
+    Encryption(#[from] crate::db::EncryptionError),
+
+    /// Error applying database migrations.
+    #[error("Migration error: {0}")]
+    Migration(String),
+
+    /// Error sending an email.
+    #[error("Mail error: {0}")]
+    Mail(String),
+}
+
+impl From<poise::serenity_prelude::Error> for Error {
+    fn from(err: poise::serenity_prelude::Error) -> Self {
+        Error::Discord(Box::new(err))
+    }
 }
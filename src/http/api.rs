@@ -0,0 +1,77 @@
+use super::AppState;
+use crate::db::{get_api_token_server, get_verified_at, is_verified};
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use poise::serenity_prelude::{GuildId, UserId};
+use serde::Serialize;
+
+/// The verification status of a member of a server. Deliberately doesn't include their email.
+#[derive(Serialize)]
+pub struct VerificationStatus {
+    guild_id: String,
+    user_id: String,
+    verified: bool,
+    verified_at: Option<DateTime<Utc>>,
+}
+
+/// `GET /api/v1/guilds/:guild_id/users/:user_id`: whether a member of a server is verified.
+/// Requires an API token for that server, created with `/api_token create`.
+pub async fn get_verification_status(
+    State(state): State<AppState>,
+    Path((guild_id, user_id)): Path<(u64, u64)>,
+    headers: HeaderMap,
+) -> Result<Json<VerificationStatus>, StatusCode> {
+    if guild_id == 0 || user_id == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let (guild_id, user_id) = (GuildId::new(guild_id), UserId::new(user_id));
+
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    // Tokens are scoped to the server they were created on.
+    match get_api_token_server(&state.db, token)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(token_guild_id) if token_guild_id == guild_id => {}
+        Some(_) => return Err(StatusCode::FORBIDDEN),
+        None => return Err(StatusCode::UNAUTHORIZED),
+    }
+
+    // Only members of the server can be looked up.
+    if guild_id.member(&state.http, user_id).await.is_err() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let verified = is_verified(&state.db, user_id)
+        .await
+        .map_err(internal_error)?;
+    let verified_at = if verified {
+        get_verified_at(&state.db, user_id)
+            .await
+            .map_err(internal_error)?
+    } else {
+        None
+    };
+
+    Ok(Json(VerificationStatus {
+        guild_id: guild_id.to_string(),
+        user_id: user_id.to_string(),
+        verified,
+        verified_at,
+    }))
+}
+
+fn internal_error(err: crate::errors::Error) -> StatusCode {
+    error!("Error in API request: {}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
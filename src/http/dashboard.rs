@@ -0,0 +1,796 @@
+//! A small web UI for server admins, with login through discord OAuth. Everything it changes goes
+//! through the same repositories as the slash commands, and is posted to the log channel.
+
+use crate::config::DashboardConfig;
+use crate::db::models::*;
+use crate::db::{ServerRepository, UserRepository};
+use crate::discord::{
+    get_members, log_event, set_verified_role_for_verified_on_single_server, LogEvent,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        HeaderMap, StatusCode,
+    },
+    response::{AppendHeaders, Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Router,
+};
+use base64::prelude::*;
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info, warn};
+use poise::serenity_prelude::{
+    ChannelId, ChannelType, GuildId, GuildPagination, Http, Permissions, RoleId, User,
+};
+use rand::Rng;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// The cookie holding the session ID.
+const SESSION_COOKIE: &str = "imperial_bot_session";
+/// The cookie holding the OAuth `state` while logging in.
+const STATE_COOKIE: &str = "imperial_bot_oauth_state";
+/// How long a login lasts.
+const SESSION_TTL: TimeDelta = TimeDelta::hours(12);
+/// The number of audit events shown per page.
+const AUDIT_PAGE_SIZE: i64 = 25;
+
+/// The discord application the dashboard logs in with.
+struct OAuth {
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    secure_cookies: bool,
+}
+
+/// A logged in admin.
+#[derive(Clone)]
+struct Session {
+    user: User,
+    /// The servers the user could administrate when they logged in. Access is checked again on
+    /// every request, this is just used to list them.
+    guilds: Vec<(GuildId, String)>,
+    /// Sent with every form, so other sites can't submit them.
+    csrf_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// State shared by the dashboard endpoints.
+#[derive(Clone)]
+struct DashboardState {
+    users: Arc<dyn UserRepository>,
+    servers: Arc<dyn ServerRepository>,
+    http: Arc<Http>,
+    oauth: Arc<OAuth>,
+    client: reqwest::Client,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+/// Build the dashboard, to be served on `/dashboard`.
+pub fn router<S>(
+    config: &DashboardConfig,
+    users: Arc<dyn UserRepository>,
+    servers: Arc<dyn ServerRepository>,
+    http: Arc<Http>,
+) -> Router<S> {
+    let state = DashboardState {
+        users,
+        servers,
+        http,
+        oauth: Arc::new(OAuth {
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone(),
+            redirect_uri: format!("{}/dashboard/callback", config.url),
+            secure_cookies: config.url.starts_with("https://"),
+        }),
+        client: reqwest::Client::new(),
+        sessions: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    Router::new()
+        .route("/", get(index))
+        .route("/login", get(login))
+        .route("/callback", get(callback))
+        .route("/logout", post(logout))
+        .route("/guilds/:guild_id", get(guild))
+        .route("/guilds/:guild_id/settings", post(update_settings))
+        .with_state(state)
+}
+
+/// `GET /dashboard`: the servers the user can configure.
+async fn index(State(state): State<DashboardState>, headers: HeaderMap) -> Response {
+    let session = match current_session(&state, &headers) {
+        Some(session) => session,
+        None => return Redirect::to("/dashboard/login").into_response(),
+    };
+
+    let guilds = if session.guilds.is_empty() {
+        "<p>You aren't an administrator of any server the bot is on.</p>".to_string()
+    } else {
+        let items: String = session
+            .guilds
+            .iter()
+            .map(|(guild_id, name)| {
+                format!(
+                    r#"<li><a href="/dashboard/guilds/{}">{}</a></li>"#,
+                    guild_id,
+                    escape(name)
+                )
+            })
+            .collect();
+        format!("<ul>{}</ul>", items)
+    };
+
+    page("Servers", &session, &format!("<h1>Servers</h1>{}", guilds)).into_response()
+}
+
+/// `GET /dashboard/login`: send the user to discord to log in.
+async fn login(State(state): State<DashboardState>) -> Response {
+    let oauth_state = random_token();
+
+    let url = Url::parse_with_params(
+        "https://discord.com/oauth2/authorize",
+        &[
+            ("response_type", "code"),
+            ("client_id", &state.oauth.client_id),
+            ("scope", "identify guilds"),
+            ("redirect_uri", &state.oauth.redirect_uri),
+            ("state", &oauth_state),
+        ],
+    )
+    .unwrap();
+
+    (
+        AppendHeaders([(
+            SET_COOKIE,
+            cookie(&state, STATE_COOKIE, &oauth_state, TimeDelta::minutes(10)),
+        )]),
+        Redirect::to(url.as_str()),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// `GET /dashboard/callback`: discord sends the user back here after they log in.
+async fn callback(
+    State(state): State<DashboardState>,
+    Query(params): Query<CallbackParams>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    // Make sure this login was started by this browser.
+    if get_cookie(&headers, STATE_COOKIE) != Some(params.state.as_str()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let token: TokenResponse = state
+        .client
+        .post("https://discord.com/api/oauth2/token")
+        .basic_auth(&state.oauth.client_id, Some(&state.oauth.client_secret))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", &params.code),
+            ("redirect_uri", &state.oauth.redirect_uri),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| {
+            warn!("Error exchanging OAuth code: {}", err);
+            StatusCode::BAD_REQUEST
+        })?
+        .json()
+        .await
+        .map_err(internal_error)?;
+
+    let user_http = Http::new(&format!("Bearer {}", token.access_token));
+    let user = user_http.get_current_user().await.map_err(internal_error)?;
+    let user_guilds = user_http
+        .get_guilds(None, None)
+        .await
+        .map_err(internal_error)?;
+
+    // Only list servers the bot is on.
+    let bot_guilds = get_bot_guilds(&state.http).await.map_err(internal_error)?;
+    let guilds = user_guilds
+        .into_iter()
+        .filter(|guild| guild.owner || guild.permissions.contains(Permissions::ADMINISTRATOR))
+        .filter(|guild| bot_guilds.contains(&guild.id))
+        .map(|guild| (guild.id, guild.name))
+        .collect();
+
+    let session_id = random_token();
+    let session = Session {
+        user: user.clone().into(),
+        guilds,
+        csrf_token: random_token(),
+        expires_at: Utc::now() + SESSION_TTL,
+    };
+
+    {
+        let mut sessions = state.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > Utc::now());
+        sessions.insert(session_id.clone(), session);
+    }
+
+    info!("{} logged in to the dashboard", user.name);
+
+    Ok((
+        AppendHeaders([
+            (
+                SET_COOKIE,
+                cookie(&state, SESSION_COOKIE, &session_id, SESSION_TTL),
+            ),
+            (
+                SET_COOKIE,
+                cookie(&state, STATE_COOKIE, "", TimeDelta::zero()),
+            ),
+        ]),
+        Redirect::to("/dashboard"),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct LogoutForm {
+    csrf_token: String,
+}
+
+/// `POST /dashboard/logout`
+async fn logout(
+    State(state): State<DashboardState>,
+    headers: HeaderMap,
+    Form(form): Form<LogoutForm>,
+) -> Response {
+    if let Some(session_id) = get_cookie(&headers, SESSION_COOKIE) {
+        let mut sessions = state.sessions.lock().unwrap();
+        if sessions
+            .get(session_id)
+            .is_some_and(|session| session.csrf_token == form.csrf_token)
+        {
+            sessions.remove(session_id);
+        }
+    }
+
+    (
+        AppendHeaders([(
+            SET_COOKIE,
+            cookie(&state, SESSION_COOKIE, "", TimeDelta::zero()),
+        )]),
+        Redirect::to("/dashboard"),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct GuildParams {
+    #[serde(default)]
+    page: i64,
+}
+
+/// `GET /dashboard/guilds/:guild_id`: a server's settings, members and audit trail.
+async fn guild(
+    State(state): State<DashboardState>,
+    Path(guild_id): Path<u64>,
+    Query(params): Query<GuildParams>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let session = match current_session(&state, &headers) {
+        Some(session) => session,
+        None => return Ok(Redirect::to("/dashboard/login").into_response()),
+    };
+    let guild_id = check_admin(&state, &session, guild_id).await?;
+
+    let guild = guild_id
+        .to_partial_guild(&state.http)
+        .await
+        .map_err(internal_error)?;
+    let channels = guild_id
+        .channels(&state.http)
+        .await
+        .map_err(internal_error)?;
+    let server = state
+        .servers
+        .get_server(guild_id)
+        .await
+        .map_err(internal_error)?;
+
+    let members = get_members(&state.http, guild_id)
+        .await
+        .map_err(internal_error)?;
+    let member_ids: Vec<i64> = members.iter().map(|m| i64::from(m.user.id)).collect();
+    let users: HashMap<i64, User> = members
+        .into_iter()
+        .map(|member| (i64::from(member.user.id), member.user))
+        .collect();
+
+    // Members that aren't in the database haven't started verifying.
+    let states: HashMap<i64, UserState> = state
+        .users
+        .get_users(&member_ids)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|user| (user.id, user.state))
+        .collect();
+    let state_of = |id: &i64| states.get(id).copied().unwrap_or(UserState::Unverified);
+
+    let page_number = params.page.max(0);
+    let (events, total) = state
+        .servers
+        .get_server_audit_events(guild_id, &member_ids, page_number, AUDIT_PAGE_SIZE)
+        .await
+        .map_err(internal_error)?;
+
+    let name_of = |id: i64| match users.get(&id) {
+        Some(user) => escape(&user.name),
+        None => id.to_string(),
+    };
+
+    // Settings
+    let role_options = |selected: Option<i64>, allow_none: bool| {
+        let mut options = String::new();
+        if allow_none || selected.is_none() {
+            options += r#"<option value="">None</option>"#;
+        }
+        let mut roles: Vec<_> = guild
+            .roles
+            .values()
+            .filter(|r| r.id != guild.id.everyone_role())
+            .collect();
+        roles.sort_by_key(|role| std::cmp::Reverse(role.position));
+        for role in roles {
+            options += &format!(
+                r#"<option value="{}"{}>{}</option>"#,
+                role.id,
+                selected_attr(selected == Some(i64::from(role.id))),
+                escape(&role.name)
+            );
+        }
+        options
+    };
+
+    let log_channel_id = server.as_ref().and_then(|s| s.log_channel_id);
+    let mut text_channels: Vec<_> = channels
+        .values()
+        .filter(|channel| channel.kind == ChannelType::Text)
+        .collect();
+    text_channels.sort_by_key(|channel| channel.position);
+    let mut channel_options = r#"<option value="">None</option>"#.to_string();
+    for channel in text_channels {
+        channel_options += &format!(
+            r#"<option value="{}"{}>#{}</option>"#,
+            channel.id,
+            selected_attr(log_channel_id == Some(i64::from(channel.id))),
+            escape(&channel.name)
+        );
+    }
+
+    let settings = format!(
+        r#"<h2>Settings</h2>
+<form method="post" action="/dashboard/guilds/{guild_id}/settings">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<label>Verified role <select name="verified_role">{verified_roles}</select></label>
+<label>Log channel <select name="log_channel">{channel_options}</select></label>
+<label><input type="checkbox" name="show_emails" value="true"{show_emails}> Show emails in the log channel</label>
+<label>Role allowed to use <code>/whois</code> <select name="whois_role">{whois_roles}</select></label>
+<button type="submit">Save</button>
+</form>"#,
+        guild_id = guild_id,
+        csrf_token = session.csrf_token,
+        verified_roles = role_options(server.as_ref().and_then(|s| s.verified_role_id), false),
+        channel_options = channel_options,
+        show_emails = if server.as_ref().is_some_and(|s| s.show_emails) {
+            " checked"
+        } else {
+            ""
+        },
+        whois_roles = role_options(server.as_ref().and_then(|s| s.whois_role_id), true),
+    );
+
+    // Members
+    let (verified, pending): (Vec<i64>, Vec<i64>) = member_ids
+        .iter()
+        .filter(|id| users.get(id).is_some_and(|user| !user.bot))
+        .partition(|id| state_of(id) == UserState::Verified);
+    let member_rows = |ids: &[i64]| -> String {
+        ids.iter()
+            .map(|id| {
+                format!(
+                    "<tr><td>{}</td><td><code>{}</code></td><td>{:?}</td></tr>",
+                    name_of(*id),
+                    id,
+                    state_of(id)
+                )
+            })
+            .collect()
+    };
+    let members = format!(
+        r#"<h2>Pending members ({})</h2>
+<table><tr><th>Name</th><th>ID</th><th>State</th></tr>{}</table>
+<h2>Verified members ({})</h2>
+<table><tr><th>Name</th><th>ID</th><th>State</th></tr>{}</table>"#,
+        pending.len(),
+        member_rows(&pending),
+        verified.len(),
+        member_rows(&verified),
+    );
+
+    // Audit trail
+    let event_rows: String = events
+        .iter()
+        .map(|event| {
+            let old_state = event
+                .old_state
+                .map_or("-".to_string(), |old| format!("{:?}", old));
+            // Events that aren't transitions only have the state the user was in.
+            let state = match event.new_state {
+                Some(new_state) => format!("{} → {:?}", old_state, new_state),
+                None => old_state,
+            };
+
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:?}</td><td>{}</td></tr>",
+                event.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                name_of(event.subject_id),
+                event.actor_id.map_or("the bot".to_string(), name_of),
+                state,
+                event.method,
+                escape(event.reason.as_deref().unwrap_or("")),
+            )
+        })
+        .collect();
+    let pages = (total + AUDIT_PAGE_SIZE - 1) / AUDIT_PAGE_SIZE;
+    let mut pagination = String::new();
+    if page_number > 0 {
+        pagination += &format!(
+            r#"<a href="/dashboard/guilds/{}?page={}">Newer</a> "#,
+            guild_id,
+            page_number - 1
+        );
+    }
+    if page_number + 1 < pages {
+        pagination += &format!(
+            r#"<a href="/dashboard/guilds/{}?page={}">Older</a>"#,
+            guild_id,
+            page_number + 1
+        );
+    }
+    let audit = format!(
+        r#"<h2>Audit events ({})</h2>
+<table><tr><th>Time</th><th>User</th><th>By</th><th>State</th><th>Method</th><th>Reason</th></tr>{}</table>
+<p>{}</p>"#,
+        total, event_rows, pagination
+    );
+
+    Ok(page(
+        &guild.name,
+        &session,
+        &format!(
+            "<h1>{}</h1>{}{}{}",
+            escape(&guild.name),
+            settings,
+            members,
+            audit
+        ),
+    )
+    .into_response())
+}
+
+#[derive(Deserialize)]
+struct SettingsForm {
+    csrf_token: String,
+    #[serde(default)]
+    verified_role: String,
+    #[serde(default)]
+    log_channel: String,
+    #[serde(default)]
+    show_emails: Option<String>,
+    #[serde(default)]
+    whois_role: String,
+}
+
+/// `POST /dashboard/guilds/:guild_id/settings`: update a server's settings. Only the settings
+/// that changed are saved, and each change is posted to the log channel.
+async fn update_settings(
+    State(state): State<DashboardState>,
+    Path(guild_id): Path<u64>,
+    headers: HeaderMap,
+    Form(form): Form<SettingsForm>,
+) -> Result<Response, StatusCode> {
+    let session = current_session(&state, &headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    if form.csrf_token != session.csrf_token {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let guild_id = check_admin(&state, &session, guild_id).await?;
+
+    let guild = guild_id
+        .to_partial_guild(&state.http)
+        .await
+        .map_err(internal_error)?;
+    let channels = guild_id
+        .channels(&state.http)
+        .await
+        .map_err(internal_error)?;
+    let server = state
+        .servers
+        .get_server(guild_id)
+        .await
+        .map_err(internal_error)?;
+
+    // Roles and channels must belong to this server.
+    let role = |id: &str| -> Result<Option<RoleId>, StatusCode> {
+        match id {
+            "" => Ok(None),
+            id => id
+                .parse()
+                .ok()
+                .map(RoleId::new)
+                .filter(|role_id| guild.roles.contains_key(role_id))
+                .map(Some)
+                .ok_or(StatusCode::BAD_REQUEST),
+        }
+    };
+    let verified_role = role(&form.verified_role)?;
+    let whois_role = role(&form.whois_role)?;
+    let log_channel = match form.log_channel.as_str() {
+        "" => None,
+        id => Some(
+            id.parse()
+                .ok()
+                .map(ChannelId::new)
+                .filter(|channel_id| channels.contains_key(channel_id))
+                .ok_or(StatusCode::BAD_REQUEST)?,
+        ),
+    };
+    let show_emails = form.show_emails.is_some();
+
+    let role_name = |role_id: RoleId| guild.roles[&role_id].name.clone();
+    let mut actions = vec![];
+
+    // The verified role can't be unset, only changed.
+    if let Some(role_id) = verified_role {
+        if server.as_ref().and_then(|s| s.verified_role_id) != Some(i64::from(role_id)) {
+            state
+                .servers
+                .set_verified_role(guild_id, role_id)
+                .await
+                .map_err(internal_error)?;
+            actions.push(format!(
+                "set the verified role to `{}`.",
+                role_name(role_id)
+            ));
+
+            // Giving every verified member the role can take a while.
+            let (http, users, servers) = (
+                state.http.clone(),
+                state.users.clone(),
+                state.servers.clone(),
+            );
+            tokio::spawn(async move {
+                if let Err(err) = set_verified_role_for_verified_on_single_server(
+                    &http,
+                    users.as_ref(),
+                    servers.as_ref(),
+                    guild_id,
+                )
+                .await
+                {
+                    error!("Error setting verified role on {}: {}", guild_id, err);
+                }
+            });
+        }
+    }
+
+    if server.as_ref().and_then(|s| s.log_channel_id) != log_channel.map(i64::from) {
+        state
+            .servers
+            .set_log_channel(guild_id, log_channel)
+            .await
+            .map_err(internal_error)?;
+        actions.push(match log_channel {
+            Some(channel_id) => {
+                format!("set the log channel to `#{}`.", channels[&channel_id].name)
+            }
+            None => "disabled the log channel.".to_string(),
+        });
+    }
+
+    if server.as_ref().is_some_and(|s| s.show_emails) != show_emails {
+        state
+            .servers
+            .set_show_emails(guild_id, show_emails)
+            .await
+            .map_err(internal_error)?;
+        actions.push(format!(
+            "{} emails in the log channel.",
+            if show_emails { "enabled" } else { "disabled" }
+        ));
+    }
+
+    if server.as_ref().and_then(|s| s.whois_role_id) != whois_role.map(i64::from) {
+        state
+            .servers
+            .set_whois_role(guild_id, whois_role)
+            .await
+            .map_err(internal_error)?;
+        actions.push(match whois_role {
+            Some(role_id) => format!("allowed `{}` to use `/whois`.", role_name(role_id)),
+            None => "disabled `/whois`.".to_string(),
+        });
+    }
+
+    for action in actions {
+        log_event(
+            &state.http,
+            state.servers.as_ref(),
+            guild_id,
+            LogEvent::ModeratorAction {
+                moderator: &session.user,
+                action: format!("{} (from the dashboard)", action),
+            },
+        )
+        .await;
+    }
+
+    Ok(Redirect::to(&format!("/dashboard/guilds/{}", guild_id)).into_response())
+}
+
+/// Check that the logged in user is still an administrator of a server the bot is on.
+async fn check_admin(
+    state: &DashboardState,
+    session: &Session,
+    guild_id: u64,
+) -> Result<GuildId, StatusCode> {
+    if guild_id == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let guild_id = GuildId::new(guild_id);
+
+    let guild = guild_id
+        .to_partial_guild(&state.http)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let member = guild_id
+        .member(&state.http, session.user.id)
+        .await
+        .map_err(|_| StatusCode::FORBIDDEN)?;
+
+    if guild.owner_id == member.user.id || guild.member_permissions(&member).administrator() {
+        Ok(guild_id)
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Get the IDs of all the servers the bot is on.
+async fn get_bot_guilds(http: &Http) -> poise::serenity_prelude::Result<HashSet<GuildId>> {
+    let mut guild_ids = HashSet::new();
+    let mut after = None;
+
+    loop {
+        let guilds = http.get_guilds(after, Some(200)).await?;
+        let full = guilds.len() == 200;
+        after = guilds.last().map(|guild| GuildPagination::After(guild.id));
+
+        guild_ids.extend(guilds.iter().map(|guild| guild.id));
+
+        if !full {
+            break;
+        }
+    }
+
+    Ok(guild_ids)
+}
+
+/// Get the session of the logged in user, if there is one.
+fn current_session(state: &DashboardState, headers: &HeaderMap) -> Option<Session> {
+    let session_id = get_cookie(headers, SESSION_COOKIE)?;
+
+    state
+        .sessions
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .filter(|session| session.expires_at > Utc::now())
+        .cloned()
+}
+
+/// Get the value of a cookie sent with a request.
+fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Build a `Set-Cookie` header value. A `max_age` of zero deletes the cookie.
+fn cookie(state: &DashboardState, name: &str, value: &str, max_age: TimeDelta) -> String {
+    format!(
+        "{}={}; Path=/dashboard; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        name,
+        value,
+        max_age.num_seconds(),
+        if state.oauth.secure_cookies {
+            "; Secure"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Generate a random, URL-safe token for session IDs and the like.
+fn random_token() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Escape text for use in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn selected_attr(selected: bool) -> &'static str {
+    if selected {
+        " selected"
+    } else {
+        ""
+    }
+}
+
+/// Wrap a page's body in the dashboard's layout.
+fn page(title: &str, session: &Session, body: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title} - imperial-bot</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: 0 auto; padding: 1em; }}
+header {{ display: flex; justify-content: space-between; align-items: center; }}
+label {{ display: block; margin: 0.5em 0; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border-bottom: 1px solid #ddd; padding: 0.25em 0.5em; text-align: left; }}
+</style>
+</head>
+<body>
+<header>
+<a href="/dashboard">imperial-bot</a>
+<form method="post" action="/dashboard/logout">
+{name} <input type="hidden" name="csrf_token" value="{csrf_token}"><button type="submit">Log out</button>
+</form>
+</header>
+{body}
+</body>
+</html>"#,
+        title = escape(title),
+        name = escape(&session.user.name),
+        csrf_token = session.csrf_token,
+        body = body,
+    ))
+}
+
+fn internal_error(err: impl std::fmt::Display) -> StatusCode {
+    error!("Error in dashboard request: {}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
@@ -0,0 +1,63 @@
+use super::AppState;
+use crate::db;
+use axum::{extract::State, http::StatusCode, Json};
+use poise::serenity_prelude::ConnectionStage;
+use serde::Serialize;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// How long each readiness check may take before it is considered failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The result of each readiness check.
+#[derive(Serialize)]
+pub struct Readiness {
+    gateway: bool,
+    database: bool,
+    smtp: bool,
+}
+
+/// Liveness: the process is up and serving requests.
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness: the bot is connected to discord, and can reach the database and the mailer.
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let (gateway, database, smtp) = tokio::join!(
+        check(gateway_connected(&state)),
+        check(async { db::ping(&state.db).await.is_ok() }),
+        check(state.mailer.ping()),
+    );
+
+    let status = if gateway && database && smtp {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(Readiness {
+            gateway,
+            database,
+            smtp,
+        }),
+    )
+}
+
+/// Run a readiness check, failing it if it takes too long (e.g. if the database is wedged).
+async fn check(check: impl Future<Output = bool>) -> bool {
+    timeout(CHECK_TIMEOUT, check).await.unwrap_or(false)
+}
+
+/// Check that every shard is connected to the discord gateway.
+async fn gateway_connected(state: &AppState) -> bool {
+    let runners = state.shard_manager.runners.lock().await;
+
+    !runners.is_empty()
+        && runners
+            .values()
+            .all(|runner| runner.stage == ConnectionStage::Connected)
+}
@@ -0,0 +1,110 @@
+mod api;
+mod dashboard;
+mod health;
+
+use crate::attestation;
+use crate::config::HttpConfig;
+use crate::db::Database;
+use crate::mail::Mailer;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use axum::{routing::get, Json, Router};
+use base64::prelude::*;
+use log::{error, info};
+use poise::serenity_prelude::{Http, ShardManager};
+use std::sync::Arc;
+
+/// State shared by all the HTTP endpoints.
+#[derive(Clone)]
+pub struct AppState {
+    db: Database,
+    mailer: Arc<dyn Mailer>,
+    shard_manager: Arc<ShardManager>,
+    http: Arc<Http>,
+}
+
+/// Serve the HTTP endpoints on the address in the config, and metrics on their own address. Does
+/// nothing for either that doesn't have one.
+pub async fn run(
+    config: &'static HttpConfig,
+    db: Database,
+    mailer: Arc<dyn Mailer>,
+    shard_manager: Arc<ShardManager>,
+    http: Arc<Http>,
+) {
+    #[cfg(feature = "metrics")]
+    match &config.metrics_addr {
+        Some(addr) => {
+            let app = Router::new().route("/metrics", get(|| async { metrics::render() }));
+            tokio::spawn(serve(addr, app, "metrics"));
+        }
+        None => info!("METRICS_ADDR not set, not serving metrics"),
+    }
+
+    let addr = match &config.addr {
+        Some(addr) => addr,
+        None => {
+            info!("HTTP_ADDR not set, not serving HTTP endpoints");
+            return;
+        }
+    };
+
+    let app = Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route(
+            "/api/v1/guilds/:guild_id/users/:user_id",
+            get(api::get_verification_status),
+        )
+        .route(
+            "/attestation/public-key",
+            get(|| async {
+                Json(serde_json::json!({
+                    "algorithm": "Ed25519",
+                    "public_key": BASE64_STANDARD.encode(attestation::public_key().as_bytes()),
+                }))
+            }),
+        );
+
+    let app = match &config.dashboard {
+        Some(dashboard) => app.nest(
+            "/dashboard",
+            dashboard::router(
+                dashboard,
+                Arc::new(db.clone()),
+                Arc::new(db.clone()),
+                http.clone(),
+            ),
+        ),
+        None => {
+            info!("DISCORD_CLIENT_ID, DISCORD_CLIENT_SECRET and DASHBOARD_URL not set, not serving the dashboard");
+            app
+        }
+    };
+
+    let app = app.with_state(AppState {
+        db,
+        mailer,
+        shard_manager,
+        http,
+    });
+
+    serve(addr, app, "HTTP endpoints").await;
+}
+
+/// Serve `app` on `addr`, logging any errors. `what` is what is being served, for the logs.
+async fn serve(addr: &str, app: Router, what: &str) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Error binding to {}: {}", addr, err);
+            return;
+        }
+    };
+
+    info!("Serving {} on {}", what, addr);
+
+    if let Err(err) = axum::serve(listener, app).await {
+        error!("Error serving {}: {}", what, err);
+    }
+}
@@ -0,0 +1,75 @@
+use super::Mailer;
+use crate::errors::{Error, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::address::Envelope;
+use std::path::PathBuf;
+
+/// Writes each email to a `.eml` file in a directory instead of sending it, for development.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> FileMailer {
+        FileMailer { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, _envelope: &Envelope, email: &[u8]) -> Result<String> {
+        // Named by when they were sent, so the directory lists them in order.
+        let name = format!(
+            "{}-{:08x}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            rand::random::<u32>()
+        );
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|err| Error::Mail(err.to_string()))?;
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, email)
+            .await
+            .map_err(|err| Error::Mail(err.to_string()))?;
+
+        Ok(format!("Written to {}", path.display()))
+    }
+
+    async fn ping(&self) -> bool {
+        tokio::fs::metadata(&self.dir)
+            .await
+            .map_or(true, |dir| dir.is_dir())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lettre::Message;
+
+    #[tokio::test]
+    async fn emails_are_written_to_the_directory() {
+        let dir = std::env::temp_dir().join(format!("imperial-bot-mail-{}", rand::random::<u64>()));
+        let mailer = FileMailer::new(&dir);
+        let email = Message::builder()
+            .from("bot@example.com".parse().unwrap())
+            .to("someone@imperial.ac.uk".parse().unwrap())
+            .subject("Test")
+            .body("Hello!".to_string())
+            .unwrap();
+
+        mailer
+            .send(email.envelope(), &email.formatted())
+            .await
+            .unwrap();
+
+        let files = std::fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let written = std::fs::read(files[0].as_ref().unwrap().path()).unwrap();
+        assert_eq!(written, email.formatted());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
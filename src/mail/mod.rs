@@ -1,28 +1,41 @@
-use lettre::{transport::smtp::authentication::Credentials, SmtpTransport};
-use log::debug;
-use std::{
-    env,
-    sync::{LazyLock, Mutex},
-};
+mod dkim;
+mod file;
+mod outbox;
+mod smtp;
+mod stdout;
+mod templates;
 
-/// The global connection to the SMTP server.
-/// NOTE: If using `dotenv`, run `dotenv::dotenv().ok();` before using this.
-/// TODO: This is bad! Get rid of this!
-pub static MAILER: LazyLock<Mutex<SmtpTransport>> = LazyLock::new(|| Mutex::new(establish_smtp()));
+use crate::config::MailBackend;
+use crate::errors::Result;
+use async_trait::async_trait;
+use lettre::address::Envelope;
+use std::sync::Arc;
 
-fn establish_smtp() -> SmtpTransport {
-    let user = env::var("SMTP_USER").expect("SMTP_USER must be set");
-    let pass = env::var("SMTP_PASS").expect("SMTP_PASS must be set");
-    let host = env::var("SMTP_HOST").expect("SMTP_HOST must be set");
-    let port = env::var("SMTP_PORT").expect("SMTP_PORT must be set");
+pub use dkim::Dkim;
+pub use file::FileMailer;
+pub use outbox::Outbox;
+pub use smtp::SmtpMailer;
+pub use stdout::StdoutMailer;
+pub use templates::{VerificationEmail, VerificationTemplates};
 
-    let creds = Credentials::new(user, pass);
+/// Somewhere emails can be sent.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// Send an email, already formatted, to the recipients in `envelope`. Returns what became of
+    /// it, such as the SMTP server's response.
+    async fn send(&self, envelope: &Envelope, email: &[u8]) -> Result<String>;
 
-    debug!("Connecting to {}:{} with credentials", host, port);
+    /// Check that emails can be sent.
+    async fn ping(&self) -> bool {
+        true
+    }
+}
 
-    SmtpTransport::starttls_relay(&host)
-        .unwrap_or_else(|_| panic!("Error connecting to {}:{} with credentials", host, port))
-        .credentials(creds)
-        .port(port.parse().expect("SMTP_PORT must be a number"))
-        .build()
+/// Build the mailer for a backend in the config.
+pub fn mailer(backend: &MailBackend) -> Arc<dyn Mailer> {
+    match backend {
+        MailBackend::Smtp(config) => Arc::new(SmtpMailer::new(config)),
+        MailBackend::File(dir) => Arc::new(FileMailer::new(dir)),
+        MailBackend::Stdout => Arc::new(StdoutMailer),
+    }
 }
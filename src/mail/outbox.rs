@@ -0,0 +1,170 @@
+use super::Mailer;
+use crate::db::{
+    dead_letter_email, decrypt_outbox_message, enqueue_email, get_due_emails, mark_email_sent,
+    models::OutboxEmail, retry_email, Database,
+};
+use crate::errors::{Error, Result};
+use crate::metrics;
+use base64::prelude::*;
+use chrono::{TimeDelta, Utc};
+use lettre::address::Envelope;
+use lettre::Message;
+use log::{error, info, warn};
+use poise::serenity_prelude::{CreateMessage, Http, UserId};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How many times sending an email is attempted before it is given up on.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// How long to wait before retrying an email for the first time. The wait doubles after every
+/// failed attempt.
+const FIRST_RETRY_DELAY: TimeDelta = TimeDelta::seconds(30);
+
+/// How often to check for emails that are due to be retried.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many emails are loaded from the outbox at once.
+const BATCH_SIZE: i64 = 20;
+
+/// An email as it's stored in the outbox: where it's going, and the formatted message.
+#[derive(Serialize, Deserialize)]
+struct QueuedEmail {
+    envelope: Envelope,
+    /// The formatted message, in base64.
+    message: String,
+}
+
+/// Emails waiting to be sent, kept in the database so that they survive restarts and can be
+/// retried. Cloning it is cheap, and clones share the same outbox.
+#[derive(Clone)]
+pub struct Outbox {
+    db: Database,
+    /// Wakes the worker when an email is added, so it doesn't wait for the next poll.
+    added: Arc<Notify>,
+}
+
+impl Outbox {
+    pub fn new(db: Database) -> Outbox {
+        Outbox {
+            db,
+            added: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Add an email for a user to the outbox. They are told if it can't be delivered.
+    pub async fn enqueue(&self, user_id: UserId, email: &Message) -> Result<()> {
+        let queued = QueuedEmail {
+            envelope: email.envelope().clone(),
+            message: BASE64_STANDARD.encode(email.formatted()),
+        };
+        let queued = serde_json::to_vec(&queued).map_err(|err| Error::Mail(err.to_string()))?;
+
+        enqueue_email(&self.db, user_id, &queued).await?;
+        self.added.notify_one();
+
+        Ok(())
+    }
+
+    /// Send the emails in the outbox with `mailer` as they become due, retrying failures with
+    /// exponential backoff. Runs forever.
+    pub async fn run(self, mailer: Arc<dyn Mailer>, http: Arc<Http>) {
+        info!("Sending emails from the outbox");
+
+        loop {
+            if let Err(err) = self.send_due(mailer.as_ref(), &http).await {
+                error!("Error sending emails from the outbox: {}", err);
+            }
+
+            tokio::select! {
+                _ = self.added.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Send every email that is due, until there are none left.
+    async fn send_due(&self, mailer: &dyn Mailer, http: &Http) -> Result<()> {
+        loop {
+            let emails = get_due_emails(&self.db, BATCH_SIZE).await?;
+            if emails.is_empty() {
+                return Ok(());
+            }
+
+            for email in emails {
+                self.send(mailer, http, &email).await?;
+            }
+        }
+    }
+
+    /// Attempt to send one email, and record how it went.
+    async fn send(&self, mailer: &dyn Mailer, http: &Http, email: &OutboxEmail) -> Result<()> {
+        let sent = match decode(email) {
+            Ok((envelope, message)) => mailer.send(&envelope, &message).await,
+            Err(err) => Err(err),
+        };
+        metrics::email_sent(sent.is_ok());
+
+        let err = match sent {
+            Ok(_) => return mark_email_sent(&self.db, email.id).await,
+            Err(err) => err.to_string(),
+        };
+
+        let attempts = email.attempts + 1;
+        if attempts < MAX_ATTEMPTS {
+            let delay = FIRST_RETRY_DELAY * 2_i32.pow(attempts as u32 - 1);
+            warn!(
+                "Error sending email {} (attempt {} of {}), retrying in {}s: {}",
+                email.id,
+                attempts,
+                MAX_ATTEMPTS,
+                delay.num_seconds(),
+                err
+            );
+            return retry_email(&self.db, email.id, err, Utc::now() + delay).await;
+        }
+
+        error!(
+            "Giving up on email {} after {} attempts: {}",
+            email.id, attempts, err
+        );
+        dead_letter_email(&self.db, email.id, err).await?;
+        metrics::email_dead();
+        notify_undelivered(http, email.user_id).await;
+
+        Ok(())
+    }
+}
+
+/// Decrypt and decode an email from the outbox.
+fn decode(email: &OutboxEmail) -> Result<(Envelope, Vec<u8>)> {
+    let queued = decrypt_outbox_message(email)?
+        .ok_or_else(|| Error::Mail("the message has already been cleared".to_string()))?;
+    let queued = serde_json::from_slice::<QueuedEmail>(&queued)
+        .map_err(|err| Error::Mail(err.to_string()))?;
+    let message = BASE64_STANDARD
+        .decode(queued.message)
+        .map_err(|err| Error::Mail(err.to_string()))?;
+
+    Ok((queued.envelope, message))
+}
+
+/// Tell a user that their email couldn't be delivered, so they don't wait for it forever.
+async fn notify_undelivered(http: &Http, user_id: i64) {
+    let message = CreateMessage::new().content(
+        "Sorry, your verification email couldn't be delivered. Please check the address, and \
+        run `/set_email` again to get a new passcode.",
+    );
+
+    if let Err(err) = UserId::new(user_id as u64)
+        .direct_message(http, message)
+        .await
+    {
+        error!(
+            "Error telling user {} that their email wasn't delivered: {}",
+            user_id, err
+        );
+    }
+}
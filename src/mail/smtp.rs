@@ -0,0 +1,59 @@
+use super::Mailer;
+use crate::config::SmtpConfig;
+use crate::errors::{Error, Result};
+use async_trait::async_trait;
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use log::debug;
+
+/// Sends emails through an SMTP server.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    /// Connect to the server in the config, logging in with its credentials.
+    pub fn new(config: &SmtpConfig) -> SmtpMailer {
+        let creds = Credentials::new(config.user.clone(), config.pass.clone());
+
+        debug!(
+            "Connecting to {}:{} with credentials",
+            config.host, config.port
+        );
+
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Error connecting to {}:{} with credentials",
+                    config.host, config.port
+                )
+            })
+            .credentials(creds)
+            .port(config.port)
+            .build();
+
+        SmtpMailer { transport }
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, envelope: &Envelope, email: &[u8]) -> Result<String> {
+        let response = self
+            .transport
+            .send_raw(envelope, email)
+            .await
+            .map_err(|err| Error::Mail(err.to_string()))?;
+
+        Ok(format!(
+            "{} {}",
+            response.code(),
+            response.message().collect::<Vec<_>>().join(" ")
+        ))
+    }
+
+    async fn ping(&self) -> bool {
+        self.transport.test_connection().await.unwrap_or(false)
+    }
+}
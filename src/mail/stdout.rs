@@ -0,0 +1,16 @@
+use super::Mailer;
+use crate::errors::Result;
+use async_trait::async_trait;
+use lettre::address::Envelope;
+
+/// Prints each email to stdout instead of sending it, for development.
+pub struct StdoutMailer;
+
+#[async_trait]
+impl Mailer for StdoutMailer {
+    async fn send(&self, _envelope: &Envelope, email: &[u8]) -> Result<String> {
+        println!("{}", String::from_utf8_lossy(email));
+
+        Ok("Printed to stdout".to_string())
+    }
+}
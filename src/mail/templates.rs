@@ -0,0 +1,267 @@
+use chrono::{DateTime, Utc};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+/// The built-in templates, used for any that `MAIL_TEMPLATE_DIR` doesn't override.
+const DEFAULT_SUBJECT: &str = include_str!("../../templates/verification_subject.txt");
+const DEFAULT_TEXT: &str = include_str!("../../templates/verification.txt");
+const DEFAULT_HTML: &str = include_str!("../../templates/verification.html");
+
+/// A variable that can be used in a template, as `{{name}}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    /// The user's discord name.
+    Name,
+    /// The name of the server the user is verifying for.
+    Server,
+    /// The secret passcode.
+    Code,
+    /// When the passcode expires.
+    ExpiresAt,
+}
+
+impl Var {
+    const ALL: [Var; 4] = [Var::Name, Var::Server, Var::Code, Var::ExpiresAt];
+
+    fn name(self) -> &'static str {
+        match self {
+            Var::Name => "name",
+            Var::Server => "server",
+            Var::Code => "code",
+            Var::ExpiresAt => "expires_at",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Var(Var),
+}
+
+/// A template, checked when it was loaded so that rendering it can't fail.
+#[derive(Debug, Clone)]
+struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse a template, checking that every variable in it is one we know.
+    fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = vec![];
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            parts.push(Part::Text(rest[..start].to_string()));
+
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| "a `{{` is never closed".to_string())?;
+            let name = rest[start + 2..start + end].trim();
+            let var = Var::ALL
+                .into_iter()
+                .find(|var| var.name() == name)
+                .ok_or_else(|| format!("unknown variable `{{{{{}}}}}`", name))?;
+            parts.push(Part::Var(var));
+
+            rest = &rest[start + end + 2..];
+        }
+        parts.push(Part::Text(rest.to_string()));
+
+        Ok(Template { parts })
+    }
+
+    fn uses(&self, var: Var) -> bool {
+        self.parts.contains(&Part::Var(var))
+    }
+
+    fn render(&self, values: &VerificationEmail, escape: fn(&str) -> String) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Var(var) => escape(&values.value(*var)),
+            })
+            .collect()
+    }
+}
+
+/// What goes in a verification email.
+pub struct VerificationEmail<'a> {
+    pub name: &'a str,
+    pub server: &'a str,
+    pub code: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl VerificationEmail<'_> {
+    fn value(&self, var: Var) -> String {
+        match var {
+            Var::Name => self.name.to_string(),
+            Var::Server => self.server.to_string(),
+            Var::Code => self.code.to_string(),
+            Var::ExpiresAt => self.expires_at.format("%H:%M UTC on %-d %B %Y").to_string(),
+        }
+    }
+}
+
+/// The templates verification emails are made from: a subject, and plain text and HTML bodies.
+#[derive(Debug, Clone)]
+pub struct VerificationTemplates {
+    subject: Template,
+    text: Template,
+    html: Template,
+}
+
+/// The problems found with a set of templates.
+#[derive(Debug)]
+pub struct TemplateErrors(pub Vec<String>);
+
+impl fmt::Display for TemplateErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join("; "))
+    }
+}
+
+impl VerificationTemplates {
+    /// Load the templates from `dir`, falling back to the built-in ones for any that aren't
+    /// there.
+    pub fn load(dir: Option<&Path>) -> Result<VerificationTemplates, TemplateErrors> {
+        let mut errors = vec![];
+        let mut load = |file: &str, default: &str, required: &[Var]| {
+            let source = match dir.map(|dir| fs::read_to_string(dir.join(file))) {
+                Some(Ok(source)) => source,
+                Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => {
+                    errors.push(format!("{}: {}", file, err));
+                    return None;
+                }
+                _ => default.to_string(),
+            };
+
+            let template = match Template::parse(&source) {
+                Ok(template) => template,
+                Err(err) => {
+                    errors.push(format!("{}: {}", file, err));
+                    return None;
+                }
+            };
+            for &var in required {
+                if !template.uses(var) {
+                    errors.push(format!("{}: must use `{{{{{}}}}}`", file, var.name()));
+                }
+            }
+
+            Some(template)
+        };
+
+        let subject = load("verification_subject.txt", DEFAULT_SUBJECT, &[]);
+        let text = load("verification.txt", DEFAULT_TEXT, &[Var::Code]);
+        let html = load("verification.html", DEFAULT_HTML, &[Var::Code]);
+
+        match (subject, text, html) {
+            (Some(subject), Some(text), Some(html)) if errors.is_empty() => {
+                Ok(VerificationTemplates {
+                    subject,
+                    text,
+                    html,
+                })
+            }
+            _ => Err(TemplateErrors(errors)),
+        }
+    }
+
+    /// Build a verification email, with plain text and HTML versions.
+    pub fn message(
+        &self,
+        from: Mailbox,
+        to: Mailbox,
+        values: &VerificationEmail,
+    ) -> Result<Message, lettre::error::Error> {
+        // A subject can't have line breaks, and the subject template ends with one.
+        let subject = self.subject.render(values, str::to_string);
+        let subject = subject.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        Message::builder()
+            .from(from)
+            .to(to)
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(
+                self.text.render(values, str::to_string),
+                self.html.render(values, escape_html),
+            ))
+    }
+}
+
+/// Escape a value for putting in HTML.
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> VerificationEmail<'static> {
+        VerificationEmail {
+            name: "<b>someone</b>",
+            server: "Imperial & Friends",
+            code: 123456,
+            expires_at: DateTime::from_timestamp(0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn default_templates_are_valid() {
+        let templates = VerificationTemplates::load(None).unwrap();
+        let text = templates.text.render(&values(), str::to_string);
+        let html = templates.html.render(&values(), escape_html);
+
+        assert!(text.contains("123456") && text.contains("00:00 UTC on 1 January 1970"));
+        assert!(
+            html.contains("&lt;b&gt;someone&lt;/b&gt;") && html.contains("Imperial &amp; Friends")
+        );
+
+        let message = templates
+            .message(
+                "bot@example.com".parse().unwrap(),
+                "someone@imperial.ac.uk".parse().unwrap(),
+                &values(),
+            )
+            .unwrap();
+        let message = String::from_utf8(message.formatted()).unwrap();
+        assert!(message.contains("Subject: Your verification code for Imperial & Friends\r\n"));
+        assert!(message.contains("multipart/alternative"));
+    }
+
+    #[test]
+    fn unknown_and_unclosed_variables_are_rejected() {
+        assert_eq!(
+            Template::parse("Hi {{ nmae }}").unwrap_err(),
+            "unknown variable `{{nmae}}`"
+        );
+        assert_eq!(
+            Template::parse("Your code is {{code").unwrap_err(),
+            "a `{{` is never closed"
+        );
+    }
+
+    #[test]
+    fn overrides_must_use_the_code() {
+        let dir =
+            std::env::temp_dir().join(format!("imperial-bot-templates-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("verification.txt"), "Hello {{name}}!").unwrap();
+
+        let errors = VerificationTemplates::load(Some(&dir)).unwrap_err();
+
+        assert_eq!(errors.0, vec!["verification.txt: must use `{{code}}`"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
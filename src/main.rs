@@ -1,11 +1,22 @@
+mod attestation;
+mod cli;
+mod config;
 mod db;
 mod discord;
 mod errors;
+mod http;
 mod mail;
+mod metrics;
+mod retention;
+mod verification;
 
+use clap::Parser;
+use cli::{Cli, Command};
+use db::Database;
 use dotenv::dotenv;
 use env_logger::{Builder, Env};
-use log::info;
+use log::{error, info};
+use std::process;
 
 #[tokio::main]
 async fn main() {
@@ -13,7 +24,67 @@ async fn main() {
     Builder::from_env(Env::default().filter("LOG_LEVEL")).init(); // Initialize logger.
     log_panics::init(); // Log panics instead of printing them
 
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config::load_or_exit()).await,
+        command => cli::run(command).await,
+    }
+}
+
+async fn serve(config: &'static config::Config) {
     info!("Starting up...");
 
-    discord::run().await;
+    let db = Database::connect(&config.database);
+    prepare_database(&db, config.database.run_migrations).await;
+
+    let mailer = mail::mailer(&config.mail.backend);
+    let outbox = mail::Outbox::new(db.clone());
+    let client = discord::client(config, db.clone(), outbox.clone(), mailer.clone()).await;
+
+    tokio::spawn(config::watch());
+    tokio::spawn(retention::run(db.clone(), &config.retention));
+    tokio::spawn(outbox.run(mailer.clone(), client.http.clone()));
+    tokio::spawn(http::run(
+        &config.http,
+        db,
+        mailer,
+        client.shard_manager.clone(),
+        client.http.clone(),
+    ));
+
+    discord::run(client).await;
+}
+
+/// Make sure the database schema is the one this version of the bot expects, first applying any
+/// pending migrations if `run_migrations` is set. Exits if it isn't, rather than letting queries
+/// fail later.
+async fn prepare_database(db: &Database, run_migrations: bool) {
+    if run_migrations {
+        let applied = db::run_pending_migrations(db)
+            .await
+            .expect("Error applying migrations");
+
+        for version in applied {
+            info!("Applied migration {}", version);
+        }
+    }
+
+    let status = db::check_migrations(db)
+        .await
+        .expect("Error checking migrations");
+
+    if !status.pending.is_empty() {
+        error!(
+            "The database is missing migrations {}. Run `imperial-bot migrate`, or set RUN_MIGRATIONS=true.",
+            status.pending.join(", ")
+        );
+    }
+    if !status.unknown.is_empty() {
+        error!(
+            "The database has migrations {} that this version of the bot doesn't know about. Is an older version of the bot being run?",
+            status.unknown.join(", ")
+        );
+    }
+    if !status.is_up_to_date() {
+        process::exit(1);
+    }
 }
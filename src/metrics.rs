@@ -0,0 +1,149 @@
+//! Prometheus metrics. Without the `metrics` feature, recording metrics does nothing.
+
+#[cfg(feature = "metrics")]
+pub use enabled::*;
+
+#[cfg(not(feature = "metrics"))]
+pub use disabled::*;
+
+#[cfg(feature = "metrics")]
+mod enabled {
+    use prometheus::{
+        register_histogram, register_int_counter, register_int_counter_vec, Encoder, Histogram,
+        IntCounter, IntCounterVec, TextEncoder,
+    };
+    use std::sync::LazyLock;
+    use std::time::Duration;
+
+    static COMMANDS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "imperial_bot_commands_total",
+            "Number of commands run",
+            &["command"]
+        )
+        .unwrap()
+    });
+
+    static EMAILS_SENT: LazyLock<IntCounter> = LazyLock::new(|| {
+        register_int_counter!("imperial_bot_emails_sent_total", "Number of emails sent").unwrap()
+    });
+
+    static EMAILS_FAILED: LazyLock<IntCounter> = LazyLock::new(|| {
+        register_int_counter!(
+            "imperial_bot_emails_failed_total",
+            "Number of emails that could not be sent"
+        )
+        .unwrap()
+    });
+
+    static EMAILS_DEAD: LazyLock<IntCounter> = LazyLock::new(|| {
+        register_int_counter!(
+            "imperial_bot_emails_dead_total",
+            "Number of emails given up on after too many failed attempts"
+        )
+        .unwrap()
+    });
+
+    static CODES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "imperial_bot_codes_total",
+            "Number of passcodes submitted",
+            &["result"]
+        )
+        .unwrap()
+    });
+
+    static DISCORD_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+        register_int_counter_vec!(
+            "imperial_bot_discord_errors_total",
+            "Number of errors from the discord API while managing roles",
+            &["operation"]
+        )
+        .unwrap()
+    });
+
+    static DB_QUERY_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+        register_histogram!(
+            "imperial_bot_db_query_seconds",
+            "Time spent running database queries"
+        )
+        .unwrap()
+    });
+
+    static DB_WAIT_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+        register_histogram!(
+            "imperial_bot_db_wait_seconds",
+            "Time spent waiting for the database connection"
+        )
+        .unwrap()
+    });
+
+    /// Record that a command was run.
+    pub fn command_run(command: &str) {
+        COMMANDS.with_label_values(&[command]).inc();
+    }
+
+    /// Record whether an email was sent.
+    pub fn email_sent(sent: bool) {
+        if sent {
+            EMAILS_SENT.inc();
+        } else {
+            EMAILS_FAILED.inc();
+        }
+    }
+
+    /// Record that an email was given up on.
+    pub fn email_dead() {
+        EMAILS_DEAD.inc();
+    }
+
+    /// Record whether a passcode was accepted.
+    pub fn code_checked(accepted: bool) {
+        CODES
+            .with_label_values(&[if accepted { "accepted" } else { "rejected" }])
+            .inc();
+    }
+
+    /// Record an error from the discord API while managing roles.
+    pub fn discord_error(operation: &str) {
+        DISCORD_ERRORS.with_label_values(&[operation]).inc();
+    }
+
+    /// Record how long a database query took.
+    pub fn db_query(duration: Duration) {
+        DB_QUERY_SECONDS.observe(duration.as_secs_f64());
+    }
+
+    /// Record how long was spent waiting for the database connection.
+    pub fn db_wait(duration: Duration) {
+        DB_WAIT_SECONDS.observe(duration.as_secs_f64());
+    }
+
+    /// Render all metrics in the prometheus text format.
+    pub fn render() -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&prometheus::gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod disabled {
+    use std::time::Duration;
+
+    pub fn command_run(_command: &str) {}
+
+    pub fn email_sent(_sent: bool) {}
+
+    pub fn email_dead() {}
+
+    pub fn code_checked(_accepted: bool) {}
+
+    pub fn discord_error(_operation: &str) {}
+
+    pub fn db_query(_duration: Duration) {}
+
+    pub fn db_wait(_duration: Duration) {}
+}
@@ -0,0 +1,77 @@
+use crate::db::{
+    anonymise_stale_users, count_stale_users, delete_stale_users, models::UserState, Database,
+};
+use crate::errors::Result;
+use chrono::{TimeDelta, Utc};
+use log::{error, info};
+use std::time::Duration;
+
+/// What to do with users whose data has expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionAction {
+    /// Clear their email, passcodes and queued emails, and move them back to `Unverified`.
+    Anonymise,
+    /// Delete their entry entirely.
+    Delete,
+}
+
+/// How long users may stay in each (non-verified) state before their data is purged.
+#[derive(Debug)]
+pub struct RetentionPolicy {
+    /// The retention period for each state. Users in states without one are kept forever.
+    pub periods: Vec<(UserState, TimeDelta)>,
+    pub action: RetentionAction,
+    /// If set, only log what would be purged.
+    pub dry_run: bool,
+    /// How often to purge.
+    pub interval: Duration,
+}
+
+/// Periodically purge users whose data has expired under the retention policy. Runs forever,
+/// unless no retention periods are set.
+pub async fn run(db: Database, policy: &RetentionPolicy) {
+    if policy.periods.is_empty() {
+        info!("No retention periods set, expired users will not be purged");
+        return;
+    }
+
+    info!("Purging expired users with {:?}", policy);
+
+    let mut interval = tokio::time::interval(policy.interval);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = purge(&db, policy).await {
+            error!("Error purging expired users: {}", err);
+        }
+    }
+}
+
+/// Purge all users whose data has expired.
+async fn purge(db: &Database, policy: &RetentionPolicy) -> Result<()> {
+    for &(state, period) in &policy.periods {
+        let cutoff = Utc::now() - period;
+
+        if policy.dry_run {
+            let count = count_stale_users(db, state, cutoff).await?;
+            info!(
+                "Retention ({:?}, dry run): would purge {} expired users in state {:?}",
+                policy.action, count, state
+            );
+            continue;
+        }
+
+        let count = match policy.action {
+            RetentionAction::Anonymise => anonymise_stale_users(db, state, cutoff).await?,
+            RetentionAction::Delete => delete_stale_users(db, state, cutoff).await?,
+        };
+
+        info!(
+            "Retention ({:?}): purged {} expired users in state {:?}",
+            policy.action, count, state
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,492 @@
+use crate::db::models::{AuditMethod, UserState};
+use crate::db::UserRepository;
+use crate::errors::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use poise::serenity_prelude::{GuildId, UserId};
+use rand::Rng;
+use std::ops::RangeInclusive;
+
+/// The range secret passcodes are picked from.
+const OTP_RANGE: RangeInclusive<i32> = 100000..=99999999;
+
+/// How long secret passcodes can be used for after they're sent.
+const OTP_TTL: TimeDelta = TimeDelta::hours(1);
+
+/// What happened when a user was asked to start verifying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Start {
+    /// The user is new, and now needs to give their email.
+    Started,
+    /// The user had started verifying before, and now needs to give their email again.
+    Restarted,
+    /// The user is already verified, so nothing changed.
+    AlreadyVerified,
+}
+
+/// Whether an email can be used to verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailCheck {
+    Accepted,
+    /// The email isn't an Imperial email, i.e. isn't in one of the allowed domains.
+    NotImperial,
+    /// The email already belongs to a verified user.
+    InUse,
+}
+
+/// Whether a passcode verified the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpCheck {
+    /// The passcode was correct, and the user is now verified.
+    Accepted,
+    /// The passcode can't be one we sent.
+    Malformed,
+    /// The passcode isn't one we sent to this user.
+    Incorrect,
+    /// The passcode is one we sent, but too long ago.
+    Expired,
+}
+
+/// Start verifying a user, creating them if they don't exist yet, so that they are asked for
+/// their email. `actor` is whoever started it (or `None` if the bot did on its own).
+pub async fn start(
+    users: &dyn UserRepository,
+    user_id: UserId,
+    actor: Option<UserId>,
+    server: GuildId,
+    method: AuditMethod,
+) -> Result<Start> {
+    let start = if !users.user_exists(user_id).await? {
+        users.create_user(user_id).await?;
+        Start::Started
+    } else if users.is_verified(user_id).await? {
+        return Ok(Start::AlreadyVerified);
+    } else {
+        // Passcodes sent before the restart are no good any more.
+        users.clear_otps(user_id).await?;
+        Start::Restarted
+    };
+
+    users
+        .set_user_state(
+            user_id,
+            UserState::QueryingEmail,
+            actor,
+            Some(server),
+            method,
+        )
+        .await?;
+
+    Ok(start)
+}
+
+/// Whether a user has started verifying and not finished, so can be sent a passcode.
+pub async fn is_verifying(users: &dyn UserRepository, user_id: UserId) -> Result<bool> {
+    Ok(matches!(
+        users.get_user_state(user_id).await?,
+        Some(UserState::QueryingEmail | UserState::QueryingOTP)
+    ))
+}
+
+/// Check whether an email can be used to verify. The email should already be trimmed.
+pub async fn check_email(
+    users: &dyn UserRepository,
+    email: &str,
+    allowed_domains: &[String],
+) -> Result<EmailCheck> {
+    if !is_imperial_email(email, allowed_domains) {
+        return Ok(EmailCheck::NotImperial);
+    }
+
+    if users.email_exists(email).await? {
+        return Ok(EmailCheck::InUse);
+    }
+
+    Ok(EmailCheck::Accepted)
+}
+
+/// Check whether an email is an Imperial one, i.e. is in one of `allowed_domains`. The email
+/// should already be trimmed.
+pub fn is_imperial_email(email: &str, allowed_domains: &[String]) -> bool {
+    allowed_domains
+        .iter()
+        .any(|domain| email.ends_with(&format!("@{}", domain)))
+}
+
+/// Pick a new secret passcode to send to a user.
+pub fn generate_otp() -> i32 {
+    rand::thread_rng().gen_range(OTP_RANGE)
+}
+
+/// When a passcode sent at `sent_at` stops being accepted.
+pub fn otp_expires_at(sent_at: DateTime<Utc>) -> DateTime<Utc> {
+    sent_at + OTP_TTL
+}
+
+/// Record that `otp` was sent to the user's `email`, so that they now need to give it back. Fails,
+/// changing nothing, if the user doesn't exist.
+pub async fn otp_sent(
+    users: &dyn UserRepository,
+    user_id: UserId,
+    email: &str,
+    otp: i32,
+) -> Result<()> {
+    users.insert_otp(user_id, otp).await?;
+    users
+        .set_user_state(
+            user_id,
+            UserState::QueryingOTP,
+            Some(user_id),
+            None,
+            AuditMethod::SetEmail,
+        )
+        .await?;
+    users.set_imperial_email(user_id, email.to_string()).await?;
+
+    Ok(())
+}
+
+/// Check a passcode given by a user, and verify them if it's one we sent them.
+pub async fn submit_otp(users: &dyn UserRepository, user_id: UserId, otp: i32) -> Result<OtpCheck> {
+    if !OTP_RANGE.contains(&otp) {
+        return Ok(OtpCheck::Malformed);
+    }
+
+    if !users.otp_exists_for_user(user_id, otp).await? {
+        // Keep them in the same state, so they can try again.
+        return Ok(OtpCheck::Incorrect);
+    }
+
+    match users.otps_sent_at(user_id).await? {
+        Some(sent_at) if otp_expires_at(sent_at) >= Utc::now() => {}
+        // They need to ask for a new one with `/set_email`.
+        _ => return Ok(OtpCheck::Expired),
+    }
+
+    users.clear_otps(user_id).await?;
+    users
+        .set_user_state(
+            user_id,
+            UserState::Verified,
+            Some(user_id),
+            None,
+            AuditMethod::Otp,
+        )
+        .await?;
+
+    Ok(OtpCheck::Accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::InMemoryRepository;
+    use crate::errors::Error;
+
+    const USER: UserId = UserId::new(1);
+    const OTHER_USER: UserId = UserId::new(2);
+    const SERVER: GuildId = GuildId::new(10);
+    const EMAIL: &str = "someone@imperial.ac.uk";
+
+    fn domains() -> Vec<String> {
+        vec!["imperial.ac.uk".to_string()]
+    }
+
+    fn state(users: &InMemoryRepository, user_id: UserId) -> Option<UserState> {
+        users.get_user(user_id).map(|user| user.state)
+    }
+
+    /// Take a user from joining up to being sent a passcode, returning the passcode.
+    async fn start_and_send_otp(users: &InMemoryRepository, user_id: UserId, email: &str) -> i32 {
+        start(users, user_id, None, SERVER, AuditMethod::MemberJoin)
+            .await
+            .unwrap();
+        assert_eq!(
+            check_email(users, email, &domains()).await.unwrap(),
+            EmailCheck::Accepted
+        );
+
+        let otp = generate_otp();
+        otp_sent(users, user_id, email, otp).await.unwrap();
+
+        otp
+    }
+
+    #[tokio::test]
+    async fn start_creates_user_asking_for_email() {
+        let users = InMemoryRepository::default();
+
+        let started = start(&users, USER, Some(USER), SERVER, AuditMethod::VerifyCommand)
+            .await
+            .unwrap();
+
+        assert_eq!(started, Start::Started);
+        assert_eq!(state(&users, USER), Some(UserState::QueryingEmail));
+    }
+
+    #[tokio::test]
+    async fn start_restarts_unfinished_verification() {
+        let users = InMemoryRepository::default();
+        start_and_send_otp(&users, USER, EMAIL).await;
+
+        let started = start(&users, USER, None, SERVER, AuditMethod::MemberJoin)
+            .await
+            .unwrap();
+
+        assert_eq!(started, Start::Restarted);
+        assert_eq!(state(&users, USER), Some(UserState::QueryingEmail));
+    }
+
+    #[tokio::test]
+    async fn start_leaves_verified_users_alone() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        submit_otp(&users, USER, otp).await.unwrap();
+
+        let started = start(&users, USER, None, SERVER, AuditMethod::MemberJoin)
+            .await
+            .unwrap();
+
+        assert_eq!(started, Start::AlreadyVerified);
+        assert_eq!(state(&users, USER), Some(UserState::Verified));
+    }
+
+    #[tokio::test]
+    async fn verification_is_audited() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        submit_otp(&users, USER, otp).await.unwrap();
+
+        assert_eq!(
+            users.get_verifying_server(USER).await.unwrap(),
+            Some(SERVER)
+        );
+
+        let transitions: Vec<_> = users
+            .audit_events_for(USER)
+            .iter()
+            .map(|event| (event.old_state, event.new_state, event.method))
+            .collect();
+        assert_eq!(
+            transitions,
+            [
+                (
+                    Some(UserState::Unverified),
+                    Some(UserState::QueryingEmail),
+                    AuditMethod::MemberJoin
+                ),
+                (
+                    Some(UserState::QueryingEmail),
+                    Some(UserState::QueryingOTP),
+                    AuditMethod::SetEmail
+                ),
+                (
+                    Some(UserState::QueryingOTP),
+                    Some(UserState::Verified),
+                    AuditMethod::Otp
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_users_partway_through_are_verifying() {
+        let users = InMemoryRepository::default();
+        assert!(!is_verifying(&users, USER).await.unwrap());
+
+        start(&users, USER, None, SERVER, AuditMethod::MemberJoin)
+            .await
+            .unwrap();
+        assert!(is_verifying(&users, USER).await.unwrap());
+
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        assert!(is_verifying(&users, USER).await.unwrap());
+
+        submit_otp(&users, USER, otp).await.unwrap();
+        assert!(!is_verifying(&users, USER).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn check_email_rejects_other_domains() {
+        let users = InMemoryRepository::default();
+
+        for email in ["someone@gmail.com", "someone@imperial.ac.uk.evil.com"] {
+            assert_eq!(
+                check_email(&users, email, &domains()).await.unwrap(),
+                EmailCheck::NotImperial
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn check_email_rejects_emails_of_verified_users() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+
+        // Someone else may give the same email until the first user is verified.
+        assert_eq!(
+            check_email(&users, EMAIL, &domains()).await.unwrap(),
+            EmailCheck::Accepted
+        );
+
+        submit_otp(&users, USER, otp).await.unwrap();
+
+        assert_eq!(
+            check_email(&users, EMAIL, &domains()).await.unwrap(),
+            EmailCheck::InUse
+        );
+    }
+
+    #[tokio::test]
+    async fn otp_sent_asks_for_otp() {
+        let users = InMemoryRepository::default();
+
+        start_and_send_otp(&users, USER, EMAIL).await;
+
+        assert_eq!(state(&users, USER), Some(UserState::QueryingOTP));
+        assert_eq!(
+            users.get_imperial_email(USER).await.unwrap().as_deref(),
+            Some(EMAIL)
+        );
+    }
+
+    #[tokio::test]
+    async fn otp_sent_fails_for_unknown_users() {
+        let users = InMemoryRepository::default();
+
+        let result = otp_sent(&users, USER, EMAIL, generate_otp()).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::Db(diesel::result::Error::NotFound))
+        ));
+        assert_eq!(state(&users, USER), None);
+        assert!(users.audit_events_for(USER).is_empty());
+    }
+
+    #[tokio::test]
+    async fn correct_otp_verifies_user() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+
+        assert_eq!(
+            submit_otp(&users, USER, otp).await.unwrap(),
+            OtpCheck::Accepted
+        );
+        assert_eq!(state(&users, USER), Some(UserState::Verified));
+        assert!(users.get_user(USER).unwrap().otps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn incorrect_otp_keeps_asking_for_otp() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        let wrong = if otp == *OTP_RANGE.start() {
+            otp + 1
+        } else {
+            otp - 1
+        };
+
+        assert_eq!(
+            submit_otp(&users, USER, wrong).await.unwrap(),
+            OtpCheck::Incorrect
+        );
+        assert_eq!(state(&users, USER), Some(UserState::QueryingOTP));
+
+        // They can still verify with the right one.
+        assert_eq!(
+            submit_otp(&users, USER, otp).await.unwrap(),
+            OtpCheck::Accepted
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_otp_is_rejected() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        users.backdate_otps(USER, OTP_TTL + TimeDelta::minutes(1));
+
+        assert_eq!(
+            submit_otp(&users, USER, otp).await.unwrap(),
+            OtpCheck::Expired
+        );
+        assert_eq!(state(&users, USER), Some(UserState::QueryingOTP));
+    }
+
+    #[tokio::test]
+    async fn later_updates_do_not_extend_otp_expiry() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        users.backdate_otps(USER, OTP_TTL + TimeDelta::minutes(1));
+
+        users
+            .set_imperial_email(USER, "someone.else@imperial.ac.uk".to_string())
+            .await
+            .unwrap();
+        users
+            .set_user_state(USER, UserState::QueryingOTP, None, None, AuditMethod::Cli)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            submit_otp(&users, USER, otp).await.unwrap(),
+            OtpCheck::Expired
+        );
+    }
+
+    #[tokio::test]
+    async fn otp_from_before_a_restart_is_rejected() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+
+        start(&users, USER, Some(USER), SERVER, AuditMethod::VerifyCommand)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            submit_otp(&users, USER, otp).await.unwrap(),
+            OtpCheck::Incorrect
+        );
+        assert_eq!(state(&users, USER), Some(UserState::QueryingEmail));
+    }
+
+    #[tokio::test]
+    async fn malformed_otp_is_rejected() {
+        let users = InMemoryRepository::default();
+        start_and_send_otp(&users, USER, EMAIL).await;
+
+        for otp in [0, 99999, 100000000, -123456] {
+            assert_eq!(
+                submit_otp(&users, USER, otp).await.unwrap(),
+                OtpCheck::Malformed
+            );
+        }
+        assert_eq!(state(&users, USER), Some(UserState::QueryingOTP));
+    }
+
+    #[tokio::test]
+    async fn otp_only_verifies_the_user_it_was_sent_to() {
+        let users = InMemoryRepository::default();
+        let otp = start_and_send_otp(&users, USER, EMAIL).await;
+        start_and_send_otp(&users, OTHER_USER, "someone.else@imperial.ac.uk").await;
+
+        assert_eq!(
+            submit_otp(&users, OTHER_USER, otp).await.unwrap(),
+            OtpCheck::Incorrect
+        );
+        assert_eq!(state(&users, OTHER_USER), Some(UserState::QueryingOTP));
+    }
+
+    #[tokio::test]
+    async fn otp_is_rejected_before_one_is_sent() {
+        let users = InMemoryRepository::default();
+        start(&users, USER, None, SERVER, AuditMethod::MemberJoin)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            submit_otp(&users, USER, 123456).await.unwrap(),
+            OtpCheck::Incorrect
+        );
+        assert_eq!(state(&users, USER), Some(UserState::QueryingEmail));
+    }
+}